@@ -0,0 +1,225 @@
+/*
+ * Runtime for C generated from Tiny BASIC. The compiler writes this header
+ * next to every generated .c file, so printing, input and runtime errors
+ * behave the same everywhere and can be changed in one place.
+ */
+#ifndef TB_RUNTIME_H
+#define TB_RUNTIME_H
+
+#include <math.h>
+#include <stdarg.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+
+/* Stops the program, pointing at the Tiny BASIC line that failed. */
+static inline void tb_error(int line, const char *format, ...) {
+    va_list arguments;
+
+    fflush(stdout);
+    fprintf(stderr, "Runtime error at line %d: ", line);
+    va_start(arguments, format);
+    vfprintf(stderr, format, arguments);
+    va_end(arguments);
+    fputc('\n', stderr);
+    exit(1);
+}
+
+/*
+ * The sign of a NaN depends on how it was produced, so every NaN prints as
+ * "nan" rather than printf's "-nan".
+ */
+static inline double tb_unsigned_nan(double value) {
+    return isnan(value) ? fabs(value) : value;
+}
+
+/*
+ * The tb_print functions end the line, the tb_write ones leave it open for
+ * the rest of a PRINT statement.
+ */
+static inline void tb_print_num(double value) {
+    printf("%.2f\n", tb_unsigned_nan(value));
+}
+
+static inline void tb_write_num(double value) {
+    printf("%.2f", tb_unsigned_nan(value));
+}
+
+/* PRINT USING, padded to the width with the given number of decimals. */
+static inline void tb_print_using(double value, int width, int decimals) {
+    printf("%*.*f\n", width, decimals, tb_unsigned_nan(value));
+}
+
+static inline void tb_write_using(double value, int width, int decimals) {
+    printf("%*.*f", width, decimals, tb_unsigned_nan(value));
+}
+
+static inline void tb_print_str(const char *text) {
+    puts(text);
+}
+
+static inline void tb_write_str(const char *text) {
+    fputs(text, stdout);
+}
+
+/*
+ * Reads the next number into the variable. A word that is not a number reads
+ * as 0 and is skipped. Running out of input is an error, a program waiting for
+ * input that never comes would otherwise loop forever.
+ */
+static inline void tb_input_num(double *variable, int line) {
+    int read = scanf("%lf", variable);
+
+    if (read == EOF) {
+        tb_error(line, "end of input");
+    }
+    if (read == 0) {
+        *variable = 0;
+        scanf("%*s");
+    }
+}
+
+/* SGN, NaN gives 0. */
+static inline double tb_sgn(double value) {
+    return (value > 0) - (value < 0);
+}
+
+/*
+ * RND and RANDOMIZE share a 64-bit LCG that every backend implements the same
+ * way, so a seed gives the same numbers whatever the program is compiled to.
+ */
+static uint64_t tb_random_state = 0;
+
+static inline double tb_rnd(double scale) {
+    tb_random_state = tb_random_state * 6364136223846793005u + 1442695040888963407u;
+    return (double)(tb_random_state >> 11) * 0x1p-53 * scale;
+}
+
+/* The seed is truncated and saturated to int64_t, and NaN seeds with 0. */
+static inline void tb_randomize(double seed) {
+    int64_t state = 0;
+    if (seed >= 0x1p63) {
+        state = INT64_MAX;
+    } else if (seed <= -0x1p63) {
+        state = INT64_MIN;
+    } else if (seed == seed) {
+        state = (int64_t)seed;
+    }
+    tb_random_state = (uint64_t)state;
+}
+
+/*
+ * GOSUB pushes the number of the place to come back to, and RETURN pops it
+ * for the generated code to jump there.
+ */
+#define TB_GOSUB_DEPTH 256
+
+static int tb_returns[TB_GOSUB_DEPTH];
+static int tb_return_depth = 0;
+
+static inline void tb_gosub(int resume, int line) {
+    if (tb_return_depth == TB_GOSUB_DEPTH) {
+        tb_error(line, "GOSUB nested too deeply");
+    }
+    tb_returns[tb_return_depth++] = resume;
+}
+
+static inline int tb_return(int line) {
+    if (tb_return_depth == 0) {
+        tb_error(line, "RETURN without GOSUB");
+    }
+    return tb_returns[--tb_return_depth];
+}
+
+/*
+ * Files opened with OPEN, by number. The table is checked on every use, so a
+ * bad number or a file that is not open stops the program instead of handing
+ * stdio a null pointer. Output is flushed by exit, including after an error.
+ */
+#define TB_FILES 8
+
+static FILE *tb_files[TB_FILES + 1];
+static int tb_file_inputs[TB_FILES + 1];
+
+static inline void tb_check_file_number(int file, int line) {
+    if (file < 1 || file > TB_FILES) {
+        tb_error(line, "invalid file number %d", file);
+    }
+}
+
+/* The open file, which must have been opened for input or for output. */
+static inline FILE *tb_file(int file, int input, int line) {
+    tb_check_file_number(file, line);
+    if (tb_files[file] == NULL) {
+        tb_error(line, "file #%d is not open", file);
+    }
+    if (tb_file_inputs[file] != input) {
+        tb_error(line, "file #%d is not open for %s", file, input ? "input" : "output");
+    }
+    return tb_files[file];
+}
+
+/* Opens with an fopen mode, "r", "w" or "a". */
+static inline void tb_open(int file, const char *path, const char *mode, int line) {
+    tb_check_file_number(file, line);
+    if (tb_files[file] != NULL) {
+        tb_error(line, "file #%d is already open", file);
+    }
+
+    tb_files[file] = fopen(path, mode);
+    if (tb_files[file] == NULL) {
+        tb_error(line, "cannot open \"%s\"", path);
+    }
+    tb_file_inputs[file] = mode[0] == 'r';
+}
+
+static inline void tb_close(int file, int line) {
+    tb_check_file_number(file, line);
+    if (tb_files[file] == NULL) {
+        tb_error(line, "file #%d is not open", file);
+    }
+
+    fclose(tb_files[file]);
+    tb_files[file] = NULL;
+}
+
+/* PRINT #n, formatted like tb_write_using. */
+static inline void tb_fwrite_num(int file, double value, int width, int decimals, int line) {
+    fprintf(tb_file(file, 0, line), "%*.*f", width, decimals, tb_unsigned_nan(value));
+}
+
+static inline void tb_fwrite_str(int file, const char *text, int line) {
+    fputs(text, tb_file(file, 0, line));
+}
+
+/* INPUT #n, like tb_input_num but reading past the end is an error. */
+static inline void tb_finput_num(int file, double *variable, int line) {
+    FILE *stream = tb_file(file, 1, line);
+    int read = fscanf(stream, "%lf", variable);
+
+    if (read == EOF) {
+        tb_error(line, "end of file #%d", file);
+    }
+    if (read == 0) {
+        *variable = 0;
+        fscanf(stream, "%*s");
+    }
+}
+
+/* EOF(n), true once nothing but whitespace is left to read. */
+static inline double tb_eof(int file, int line) {
+    FILE *stream = tb_file(file, 1, line);
+    int c;
+
+    do {
+        c = fgetc(stream);
+    } while (c == ' ' || (c >= '\t' && c <= '\r'));
+
+    if (c == EOF) {
+        return 1;
+    }
+    ungetc(c, stream);
+    return 0;
+}
+
+#endif
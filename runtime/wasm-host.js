@@ -0,0 +1,252 @@
+// Runs a Tiny BASIC program compiled with --target=wasm.
+//
+//     node runtime/wasm-host.js output/program.wasm < input.txt
+//
+// Printing and input follow the C backend: numbers print like printf("%*.*f"),
+// input reads like scanf("%lf"), a value that does not parse stores 0 and skips
+// one word, and running out of input stops the program with a runtime error.
+// Files opened for input are read the same way.
+
+const fs = require("fs");
+
+const stdin = { text: fs.readFileSync(0, "latin1"), position: 0 };
+let output = "";
+
+function flush() {
+  if (output.length > 0) {
+    try {
+      fs.writeSync(1, output);
+    } catch (error) {
+      // The reader went away, like a C program killed by SIGPIPE
+      if (error.code === "EPIPE") {
+        process.exit(0);
+      }
+      throw error;
+    }
+    output = "";
+  }
+}
+
+function write(text) {
+  output += text;
+  if (output.length >= 4096) {
+    flush();
+  }
+}
+
+// printf("%*.*f") rounds the exact binary value half to even, toFixed does
+// not, so round the exact decimal expansion by hand.
+function formatNumber(value, width, decimals) {
+  return formatMagnitude(value, decimals).padStart(width);
+}
+
+function formatMagnitude(value, decimals) {
+  const bits = new Float64Array([value]);
+  const negative = new Uint8Array(bits.buffer)[7] >= 0x80;
+  const sign = negative ? "-" : "";
+
+  // Unsigned like the C runtime, the sign depends on how the NaN was produced
+  if (Number.isNaN(value)) {
+    return "nan";
+  }
+  if (!Number.isFinite(value)) {
+    return sign + "inf";
+  }
+
+  const magnitude = Math.abs(value);
+  const point = decimals > 0 ? "." : "";
+  if (magnitude >= 1e21) {
+    return sign + BigInt(magnitude).toString() + point + "0".repeat(decimals);
+  }
+
+  const [whole, fraction] = magnitude.toFixed(100).split(".");
+  let scaled = BigInt(whole + fraction.slice(0, decimals));
+  const rest = fraction.slice(decimals);
+  const half = "5".padEnd(rest.length, "0");
+
+  if (rest > half || (rest === half && scaled % 2n === 1n)) {
+    scaled += 1n;
+  }
+
+  const digits = scaled.toString().padStart(decimals + 1, "0");
+  const split = digits.length - decimals;
+  return sign + digits.slice(0, split) + point + digits.slice(split);
+}
+
+function runtimeError(line, message) {
+  flush();
+  fs.writeSync(2, `Runtime error at line ${line}: ${message}\n`);
+  process.exit(1);
+}
+
+const NUMBER = /^[+-]?(inf(inity)?|nan|(\d+\.?\d*|\.\d+)([eE][+-]?\d+)?)/i;
+
+// Skips whitespace, returning whether anything is left to read.
+function skipSpace(source) {
+  while (source.position < source.text.length && /\s/.test(source.text[source.position])) {
+    source.position++;
+  }
+  return source.position < source.text.length;
+}
+
+// The next number, or null once only whitespace is left.
+function readNumber(source) {
+  if (!skipSpace(source)) {
+    return null;
+  }
+
+  const match = NUMBER.exec(source.text.slice(source.position));
+  if (match) {
+    source.position += match[0].length;
+    return Number.parseFloat(match[0].replace(/^([+-]?)inf(inity)?$/i, "$1Infinity"));
+  }
+
+  while (source.position < source.text.length && !/\s/.test(source.text[source.position])) {
+    source.position++;
+  }
+  return 0;
+}
+
+function inputNumber(line) {
+  const value = readNumber(stdin);
+  if (value === null) {
+    runtimeError(line, "end of input");
+  }
+  return value;
+}
+
+// Resume blocks of the GOSUBs waiting for their RETURN, at most 256 like
+// tb_gosub in the C runtime
+const returns = [];
+
+function gosub(resume, line) {
+  if (returns.length === 256) {
+    runtimeError(line, "GOSUB nested too deeply");
+  }
+  returns.push(resume);
+}
+
+function resume(line) {
+  if (returns.length === 0) {
+    runtimeError(line, "RETURN without GOSUB");
+  }
+  return returns.pop();
+}
+
+// Files opened with OPEN by number, checked on every use like tb_file in the C
+// runtime. Input files are read whole, output goes straight to the file.
+const files = new Array(9).fill(null);
+
+function file(number, input, line) {
+  const open = files[number];
+  if (open === null) {
+    runtimeError(line, `file #${number} is not open`);
+  }
+  if (open.input !== input) {
+    runtimeError(line, `file #${number} is not open for ${input ? "input" : "output"}`);
+  }
+  return open;
+}
+
+function fileOpen(number, offset, length, mode, line) {
+  const path = Buffer.from(memory.buffer, offset, length).toString("utf8");
+  if (files[number] !== null) {
+    runtimeError(line, `file #${number} is already open`);
+  }
+
+  try {
+    if (mode === 0) {
+      files[number] = { input: true, text: fs.readFileSync(path, "latin1"), position: 0 };
+    } else {
+      files[number] = { input: false, fd: fs.openSync(path, mode === 1 ? "w" : "a") };
+    }
+  } catch (error) {
+    runtimeError(line, `cannot open "${path}"`);
+  }
+}
+
+function fileClose(number, line) {
+  if (files[number] === null) {
+    runtimeError(line, `file #${number} is not open`);
+  }
+  if (!files[number].input) {
+    fs.closeSync(files[number].fd);
+  }
+  files[number] = null;
+}
+
+function fileWrite(number, line, text) {
+  fs.writeSync(file(number, false, line).fd, text);
+}
+
+function fileInput(number, line) {
+  const value = readNumber(file(number, true, line));
+  if (value === null) {
+    runtimeError(line, `end of file #${number}`);
+  }
+  return value;
+}
+
+// The same 64-bit LCG as tb_rnd in the C runtime
+let randomState = 0n;
+
+function rnd(scale) {
+  randomState = BigInt.asUintN(64, randomState * 6364136223846793005n + 1442695040888963407n);
+  return Number(randomState >> 11n) * 2 ** -53 * scale;
+}
+
+// Truncated and saturated to 64 bits, NaN seeds with 0
+function randomize(seed) {
+  let state = 0n;
+  if (seed >= 2 ** 63) {
+    state = 2n ** 63n - 1n;
+  } else if (seed <= -(2 ** 63)) {
+    state = -(2n ** 63n);
+  } else if (!Number.isNaN(seed)) {
+    state = BigInt(Math.trunc(seed));
+  }
+  randomState = BigInt.asUintN(64, state);
+}
+
+const bytes = fs.readFileSync(process.argv[2]);
+let memory;
+
+const imports = {
+  env: {
+    print_number: (value, width, decimals) => write(formatNumber(value, width, decimals)),
+    print_string: (offset, length) => {
+      write(Buffer.from(memory.buffer, offset, length).toString("utf8"));
+    },
+    print_newline: () => write("\n"),
+    input_number: inputNumber,
+    fmod: (left, right) => left % right,
+    pow: Math.pow,
+    sin: Math.sin,
+    cos: Math.cos,
+    tan: Math.tan,
+    atan: Math.atan,
+    exp: Math.exp,
+    log: Math.log,
+    rnd,
+    randomize,
+    file_open: fileOpen,
+    file_close: fileClose,
+    file_print_number: (value, width, decimals, number, line) => {
+      fileWrite(number, line, formatNumber(value, width, decimals));
+    },
+    file_print_string: (offset, length, number, line) => {
+      fileWrite(number, line, Buffer.from(memory.buffer, offset, length).toString("utf8"));
+    },
+    file_print_newline: (number, line) => fileWrite(number, line, "\n"),
+    file_input: fileInput,
+    file_eof: (number, line) => (skipSpace(file(number, true, line)) ? 0 : 1),
+    gosub,
+    resume,
+  },
+};
+
+WebAssembly.instantiate(bytes, imports).then(({ instance }) => {
+  memory = instance.exports.memory;
+  instance.exports.main();
+  flush();
+});
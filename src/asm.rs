@@ -0,0 +1,579 @@
+use crate::ast::{BinaryOperator, FileMode, Function, Random, UnaryOperator, FILES, GOSUB_DEPTH};
+use crate::ir::{Instruction, Module, Operand, Terminator, FILE_ERRORS};
+
+/// Generates x86-64 System V assembly in GAS (AT&T) syntax. Numbers are
+/// doubles held in SSE registers, every virtual register gets its own stack
+/// slot and printing and input go through libc, so the output links with `cc`.
+pub fn generate(module: &Module) -> String {
+    let mut asm = Assembly::default();
+
+    asm.line("    .section .rodata");
+    asm.line(".Lformat_number:\n    .string \"%*.*f\"");
+    asm.line(".Lformat_input:\n    .string \"%lf\"");
+    asm.line(".Lformat_skip:\n    .string \"%*s\"");
+    asm.line(".Lformat_end_of_input:\n    .string \"Runtime error at line %d: end of input\\n\"");
+    let gosubs = module.uses_gosub();
+    if gosubs {
+        asm.line(
+            ".Lformat_gosub:\n    .string \"Runtime error at line %d: GOSUB nested too deeply\\n\"",
+        );
+        asm.line(
+            ".Lformat_return:\n    .string \"Runtime error at line %d: RETURN without GOSUB\\n\"",
+        );
+    }
+    let files = module.uses_files();
+    if files {
+        for (name, message) in FILE_ERRORS {
+            asm.line(&format!(
+                ".Lformat_{}:\n    .string \"Runtime error at line %d: {}\\n\"",
+                name,
+                escape(message)
+            ));
+        }
+        for mode in [FileMode::Input, FileMode::Output, FileMode::Append] {
+            asm.line(&format!(".Lmode_{0}:\n    .string \"{0}\"", mode.c_mode()));
+        }
+    }
+    for (index, text) in module.strings.iter().enumerate() {
+        asm.line(&format!(
+            ".Lstring{}:\n    .string \"{}\"",
+            index,
+            escape(text)
+        ));
+    }
+
+    asm.line("\n    .bss\n    .align 8");
+    for (index, name) in module.variables.iter().enumerate() {
+        // Zeroed storage is 0.0, matching the C backend's initializers
+        asm.line(&format!(".Lvariable{}: # {}\n    .zero 8", index, name));
+    }
+    asm.line(".Lrandom_state:\n    .zero 8");
+    if gosubs {
+        // Resume addresses of the GOSUBs waiting for their RETURN
+        asm.line(&format!(".Lreturns:\n    .zero {}", GOSUB_DEPTH * 8));
+        asm.line(".Lreturn_depth:\n    .zero 8");
+    }
+    if files {
+        // FILE pointers and whether each was opened for input, by file number
+        asm.line(&format!(".Lfiles:\n    .zero {}", (FILES + 1) * 8));
+        asm.line(&format!(".Lfile_inputs:\n    .zero {}", FILES + 1));
+    }
+
+    // Keep the stack 16-byte aligned for libc calls
+    let frame = (module.registers * 8).div_ceil(16) * 16;
+
+    asm.line("\n    .text\n    .globl main\n    .type main, @function\nmain:");
+    asm.line("    pushq %rbp\n    movq %rsp, %rbp");
+    if frame > 0 {
+        asm.line(&format!("    subq ${}, %rsp", frame));
+    }
+
+    for (index, block) in module.blocks.iter().enumerate() {
+        match &block.label {
+            Some(label) => asm.line(&format!(".Lblock{}: # {}", index, label)),
+            None => asm.line(&format!(".Lblock{}:", index)),
+        }
+
+        for instruction in &block.instructions {
+            asm.instruction(instruction);
+        }
+
+        match &block.terminator {
+            Terminator::Jump(target) => asm.line(&format!("    jmp .Lblock{}", target)),
+            Terminator::Branch {
+                condition,
+                then,
+                otherwise,
+            } => {
+                asm.operand(condition, "%xmm0");
+                asm.line("    xorpd %xmm1, %xmm1\n    ucomisd %xmm1, %xmm0");
+                // NaN compares unordered and counts as true, like in C
+                asm.line(&format!("    jne .Lblock{}\n    jp .Lblock{}", then, then));
+                asm.line(&format!("    jmp .Lblock{}", otherwise));
+            }
+            Terminator::Gosub {
+                target,
+                resume,
+                line,
+            } => {
+                asm.line("    movq .Lreturn_depth(%rip), %rax");
+                asm.line(&format!(
+                    "    movl ${}, %edx\n    leaq .Lformat_gosub(%rip), %rsi",
+                    line
+                ));
+                asm.line(&format!(
+                    "    cmpq ${}, %rax\n    je .Lgosub_error",
+                    GOSUB_DEPTH
+                ));
+                asm.line("    leaq .Lreturns(%rip), %rcx");
+                asm.line(&format!(
+                    "    leaq .Lblock{}(%rip), %rdi\n    movq %rdi, (%rcx,%rax,8)",
+                    resume
+                ));
+                asm.line("    incq %rax\n    movq %rax, .Lreturn_depth(%rip)");
+                asm.line(&format!("    jmp .Lblock{}", target));
+            }
+            Terminator::Resume { line } => {
+                asm.line("    movq .Lreturn_depth(%rip), %rax");
+                asm.line(&format!(
+                    "    movl ${}, %edx\n    leaq .Lformat_return(%rip), %rsi",
+                    line
+                ));
+                asm.line("    testq %rax, %rax\n    je .Lgosub_error");
+                asm.line("    decq %rax\n    movq %rax, .Lreturn_depth(%rip)");
+                asm.line("    leaq .Lreturns(%rip), %rcx\n    jmp *(%rcx,%rax,8)");
+            }
+            Terminator::Return => asm.line("    xorl %eax, %eax\n    leave\n    ret"),
+        }
+    }
+
+    // Shared by every INPUT, which jumps here with the line number in %edx
+    asm.line(".Lend_of_input:");
+    asm.line("    pushq %rdx\n    subq $8, %rsp");
+    asm.line("    xorl %edi, %edi\n    call fflush@PLT");
+    asm.line("    movq stderr@GOTPCREL(%rip), %rax\n    movq (%rax), %rdi");
+    asm.line("    leaq .Lformat_end_of_input(%rip), %rsi\n    movl 8(%rsp), %edx");
+    asm.line("    xorl %eax, %eax\n    call fprintf@PLT");
+    asm.line("    movl $1, %edi\n    call exit@PLT");
+
+    if gosubs {
+        // Shared by GOSUB and RETURN, with the format in %rsi and the line
+        // number in %edx
+        asm.line(".Lgosub_error:");
+        asm.line("    pushq %rsi\n    pushq %rdx");
+        asm.line("    xorl %edi, %edi\n    call fflush@PLT");
+        asm.line("    movq stderr@GOTPCREL(%rip), %rax\n    movq (%rax), %rdi");
+        asm.line("    movq 8(%rsp), %rsi\n    movl (%rsp), %edx");
+        asm.line("    xorl %eax, %eax\n    call fprintf@PLT");
+        asm.line("    movl $1, %edi\n    call exit@PLT");
+    }
+
+    if files {
+        // Shared by the file checks, with the format in %rsi, the line number
+        // in %edx and the file number or path in %rcx
+        asm.line(".Lfile_error:");
+        asm.line("    pushq %rsi\n    pushq %rdx\n    pushq %rcx\n    subq $8, %rsp");
+        asm.line("    xorl %edi, %edi\n    call fflush@PLT");
+        asm.line("    movq stderr@GOTPCREL(%rip), %rax\n    movq (%rax), %rdi");
+        asm.line("    movq 24(%rsp), %rsi\n    movl 16(%rsp), %edx\n    movq 8(%rsp), %rcx");
+        asm.line("    xorl %eax, %eax\n    call fprintf@PLT");
+        asm.line("    movl $1, %edi\n    call exit@PLT");
+    }
+
+    asm.line("\n    .section .note.GNU-stack,\"\",@progbits");
+    asm.text
+}
+
+#[derive(Default)]
+struct Assembly {
+    text: String,
+    /// Counter for labels local to a single instruction.
+    labels: usize,
+}
+
+impl Assembly {
+    fn line(&mut self, line: &str) {
+        self.text.push_str(line);
+        self.text.push('\n');
+    }
+
+    fn slot(register: usize) -> String {
+        format!("-{}(%rbp)", (register + 1) * 8)
+    }
+
+    /// Loads an operand into the given SSE register.
+    fn operand(&mut self, operand: &Operand, target: &str) {
+        match operand {
+            Operand::Register(register) => {
+                self.line(&format!("    movsd {}, {}", Self::slot(*register), target))
+            }
+            Operand::Constant(value) => {
+                self.line(&format!("    movabsq ${}, %rax", value.to_bits() as i64));
+                self.line(&format!("    movq %rax, {}", target));
+            }
+        }
+    }
+
+    fn store(&mut self, dest: usize) {
+        self.line(&format!("    movsd %xmm0, {}", Self::slot(dest)));
+    }
+
+    fn label(&mut self) -> usize {
+        self.labels += 1;
+        self.labels - 1
+    }
+
+    /// Jumps to `.Lfile_error` with one of `FILE_ERRORS`, `argument` loads %rcx.
+    fn file_error(&mut self, error: &str, line: usize, argument: &str) {
+        self.line(&format!("    leaq .Lformat_{}(%rip), %rsi", error));
+        self.line(&format!("    movl ${}, %edx", line));
+        self.line(&format!("    {}\n    jmp .Lfile_error", argument));
+    }
+
+    /// Checks the file is open, and for input or output when asked.
+    fn check_file(&mut self, file: usize, input: Option<bool>, line: usize) {
+        let open = self.label();
+        self.line(&format!("    cmpq $0, .Lfiles+{}(%rip)", file * 8));
+        self.line(&format!("    jne .Lfile{}", open));
+        self.file_error("not_open", line, &format!("movl ${}, %ecx", file));
+        self.line(&format!(".Lfile{}:", open));
+
+        if let Some(input) = input {
+            let right = self.label();
+            self.line(&format!(
+                "    cmpb ${}, .Lfile_inputs+{}(%rip)",
+                u8::from(input),
+                file
+            ));
+            self.line(&format!("    je .Lfile{}", right));
+            let error = if input { "not_input" } else { "not_output" };
+            self.file_error(error, line, &format!("movl ${}, %ecx", file));
+            self.line(&format!(".Lfile{}:", right));
+        }
+    }
+
+    /// Clears the sign of a NaN in %xmm0, so printf writes "nan" like every
+    /// other runtime.
+    fn unsigned_nan(&mut self) {
+        let number = self.label();
+        self.line(&format!(
+            "    ucomisd %xmm0, %xmm0\n    jnp .Lnumber{}",
+            number
+        ));
+        self.line("    movabsq $9223372036854775807, %rax");
+        self.line("    movq %rax, %xmm1\n    andpd %xmm1, %xmm0");
+        self.line(&format!(".Lnumber{}:", number));
+    }
+
+    fn stream(&mut self, file: usize, target: &str) {
+        self.line(&format!("    movq .Lfiles+{}(%rip), {}", file * 8, target));
+    }
+
+    fn instruction(&mut self, instruction: &Instruction) {
+        match instruction {
+            Instruction::Load { dest, variable } => {
+                self.line(&format!("    movsd .Lvariable{}(%rip), %xmm0", variable));
+                self.store(*dest);
+            }
+            Instruction::Store { variable, value } => {
+                self.operand(value, "%xmm0");
+                self.line(&format!("    movsd %xmm0, .Lvariable{}(%rip)", variable));
+            }
+            Instruction::Unary {
+                dest,
+                operator,
+                operand,
+            } => {
+                self.operand(operand, "%xmm0");
+                match operator {
+                    UnaryOperator::Plus => (),
+                    UnaryOperator::Minus => {
+                        self.line("    movabsq $-9223372036854775808, %rax");
+                        self.line("    movq %rax, %xmm1\n    xorpd %xmm1, %xmm0");
+                    }
+                    // Compared with zero, like `x == 0`
+                    UnaryOperator::Not => {
+                        self.line("    xorpd %xmm1, %xmm1");
+                        self.binary(BinaryOperator::Equal);
+                    }
+                }
+                self.store(*dest);
+            }
+            Instruction::Binary {
+                dest,
+                operator,
+                left,
+                right,
+            } => {
+                self.operand(left, "%xmm0");
+                self.operand(right, "%xmm1");
+                self.binary(*operator);
+                self.store(*dest);
+            }
+            Instruction::Call {
+                dest,
+                function,
+                arguments,
+            } => {
+                self.call(*function, arguments);
+                self.store(*dest);
+            }
+            Instruction::Randomize(seed) => {
+                self.operand(seed, "%xmm0");
+                // Out of range and NaN convert to i64::MIN, so saturate above and zero NaN
+                self.line("    cvttsd2siq %xmm0, %rax");
+                self.line("    movabsq $0x43e0000000000000, %rcx\n    movq %rcx, %xmm1");
+                self.line("    movabsq $9223372036854775807, %rcx");
+                self.line("    ucomisd %xmm1, %xmm0\n    cmovae %rcx, %rax");
+                self.line("    xorl %ecx, %ecx\n    ucomisd %xmm0, %xmm0\n    cmovp %rcx, %rax");
+                self.line("    movq %rax, .Lrandom_state(%rip)");
+            }
+            Instruction::PrintNumber(value, format) => {
+                self.operand(value, "%xmm0");
+                self.unsigned_nan();
+                self.line("    leaq .Lformat_number(%rip), %rdi");
+                self.line(&format!("    movl ${}, %esi", format.width));
+                self.line(&format!("    movl ${}, %edx", format.decimals));
+                self.line("    movl $1, %eax");
+                self.line("    call printf@PLT");
+            }
+            Instruction::PrintString(string) => {
+                self.line(&format!("    leaq .Lstring{}(%rip), %rdi", string));
+                self.line("    movq stdout@GOTPCREL(%rip), %rax\n    movq (%rax), %rsi");
+                self.line("    call fputs@PLT");
+            }
+            Instruction::PrintNewline => self.line("    movl $10, %edi\n    call putchar@PLT"),
+            Instruction::Input { variable, line } => {
+                // Same as the C runtime: a failed match stores 0 and skips a word
+                let done = self.labels;
+                self.labels += 1;
+
+                self.line("    leaq .Lformat_input(%rip), %rdi");
+                self.line(&format!("    leaq .Lvariable{}(%rip), %rsi", variable));
+                self.line("    xorl %eax, %eax\n    call scanf@PLT");
+                self.line(&format!("    movl ${}, %edx", line));
+                self.line("    cmpl $-1, %eax\n    je .Lend_of_input");
+                self.line(&format!("    testl %eax, %eax\n    jne .Linput{}", done));
+                self.line(&format!("    movq $0, .Lvariable{}(%rip)", variable));
+                self.line("    leaq .Lformat_skip(%rip), %rdi");
+                self.line("    xorl %eax, %eax\n    call scanf@PLT");
+                self.line(&format!(".Linput{}:", done));
+            }
+            Instruction::Open {
+                file,
+                path,
+                mode,
+                line,
+            } => {
+                let closed = self.label();
+                self.line(&format!("    cmpq $0, .Lfiles+{}(%rip)", file * 8));
+                self.line(&format!("    je .Lfile{}", closed));
+                self.file_error("already_open", *line, &format!("movl ${}, %ecx", file));
+                self.line(&format!(".Lfile{}:", closed));
+
+                let opened = self.label();
+                self.line(&format!("    leaq .Lstring{}(%rip), %rdi", path));
+                self.line(&format!("    leaq .Lmode_{}(%rip), %rsi", mode.c_mode()));
+                self.line("    call fopen@PLT");
+                self.line(&format!("    movq %rax, .Lfiles+{}(%rip)", file * 8));
+                self.line(&format!("    testq %rax, %rax\n    jne .Lfile{}", opened));
+                self.file_error(
+                    "cannot_open",
+                    *line,
+                    &format!("leaq .Lstring{}(%rip), %rcx", path),
+                );
+                self.line(&format!(".Lfile{}:", opened));
+                self.line(&format!(
+                    "    movb ${}, .Lfile_inputs+{}(%rip)",
+                    u8::from(*mode == FileMode::Input),
+                    file
+                ));
+            }
+            Instruction::Close { file, line } => {
+                self.check_file(*file, None, *line);
+                self.stream(*file, "%rdi");
+                self.line("    call fclose@PLT");
+                self.line(&format!("    movq $0, .Lfiles+{}(%rip)", file * 8));
+            }
+            Instruction::FilePrintNumber {
+                file,
+                value,
+                format,
+                line,
+            } => {
+                self.check_file(*file, Some(false), *line);
+                self.operand(value, "%xmm0");
+                self.unsigned_nan();
+                self.stream(*file, "%rdi");
+                self.line("    leaq .Lformat_number(%rip), %rsi");
+                self.line(&format!("    movl ${}, %edx", format.width));
+                self.line(&format!("    movl ${}, %ecx", format.decimals));
+                self.line("    movl $1, %eax");
+                self.line("    call fprintf@PLT");
+            }
+            Instruction::FilePrintString { file, string, line } => {
+                self.check_file(*file, Some(false), *line);
+                self.line(&format!("    leaq .Lstring{}(%rip), %rdi", string));
+                self.stream(*file, "%rsi");
+                self.line("    call fputs@PLT");
+            }
+            Instruction::FilePrintNewline { file, line } => {
+                self.check_file(*file, Some(false), *line);
+                self.line("    movl $10, %edi");
+                self.stream(*file, "%rsi");
+                self.line("    call fputc@PLT");
+            }
+            Instruction::FileInput {
+                file,
+                variable,
+                line,
+            } => {
+                self.check_file(*file, Some(true), *line);
+                let read = self.label();
+                let done = self.label();
+
+                self.stream(*file, "%rdi");
+                self.line("    leaq .Lformat_input(%rip), %rsi");
+                self.line(&format!("    leaq .Lvariable{}(%rip), %rdx", variable));
+                self.line("    xorl %eax, %eax\n    call fscanf@PLT");
+                self.line(&format!("    cmpl $-1, %eax\n    jne .Lfile{}", read));
+                self.file_error("end_of_file", *line, &format!("movl ${}, %ecx", file));
+                self.line(&format!(".Lfile{}:", read));
+                self.line(&format!("    testl %eax, %eax\n    jne .Lfile{}", done));
+                self.line(&format!("    movq $0, .Lvariable{}(%rip)", variable));
+                self.stream(*file, "%rdi");
+                self.line("    leaq .Lformat_skip(%rip), %rsi");
+                self.line("    xorl %eax, %eax\n    call fscanf@PLT");
+                self.line(&format!(".Lfile{}:", done));
+            }
+            Instruction::Eof { dest, file, line } => {
+                self.check_file(*file, Some(true), *line);
+                let skip = self.label();
+                let other = self.label();
+                let end = self.label();
+                let done = self.label();
+
+                // Skip whitespace like tb_eof, then put back whatever follows
+                self.line(&format!(".Lfile{}:", skip));
+                self.stream(*file, "%rdi");
+                self.line("    call fgetc@PLT");
+                self.line(&format!("    cmpl $32, %eax\n    je .Lfile{}", skip));
+                self.line(&format!("    cmpl $9, %eax\n    jl .Lfile{}", other));
+                self.line(&format!("    cmpl $13, %eax\n    jle .Lfile{}", skip));
+                self.line(&format!(".Lfile{}:", other));
+                self.line(&format!("    cmpl $-1, %eax\n    je .Lfile{}", end));
+                self.line("    movl %eax, %edi");
+                self.stream(*file, "%rsi");
+                self.line("    call ungetc@PLT");
+                self.line(&format!("    xorpd %xmm0, %xmm0\n    jmp .Lfile{}", done));
+                self.line(&format!(".Lfile{}:", end));
+                self.operand(&Operand::Constant(1.0), "%xmm0");
+                self.line(&format!(".Lfile{}:", done));
+                self.store(*dest);
+            }
+        }
+    }
+
+    /// Calls a built-in with its arguments in %xmm0 and %xmm1, leaving the
+    /// result in %xmm0. Most go to libm, RND is the runtime's LCG inlined.
+    fn call(&mut self, function: Function, arguments: &[Operand]) {
+        for (argument, register) in arguments.iter().zip(["%xmm0", "%xmm1"]) {
+            self.operand(argument, register);
+        }
+
+        match function {
+            Function::Abs => {
+                self.line("    movabsq $9223372036854775807, %rax");
+                self.line("    movq %rax, %xmm1\n    andpd %xmm1, %xmm0");
+            }
+            Function::Sqr => self.line("    sqrtsd %xmm0, %xmm0"),
+            Function::Sgn => {
+                // (x > 0) - (x < 0), both false for NaN
+                self.line("    xorpd %xmm1, %xmm1");
+                self.line("    ucomisd %xmm1, %xmm0\n    seta %al");
+                self.line("    ucomisd %xmm0, %xmm1\n    seta %cl");
+                self.line("    subb %cl, %al\n    movsbl %al, %eax\n    cvtsi2sdl %eax, %xmm0");
+            }
+            Function::Rnd => {
+                self.line("    movq .Lrandom_state(%rip), %rax");
+                self.line(&format!(
+                    "    movabsq ${}, %rcx\n    imulq %rcx, %rax",
+                    Random::MULTIPLIER
+                ));
+                self.line(&format!(
+                    "    movabsq ${}, %rcx\n    addq %rcx, %rax",
+                    Random::INCREMENT
+                ));
+                self.line("    movq %rax, .Lrandom_state(%rip)");
+                self.line("    shrq $11, %rax\n    cvtsi2sdq %rax, %xmm1");
+                self.line(&format!(
+                    "    movabsq ${}, %rax",
+                    (1.0f64 / (1u64 << 53) as f64).to_bits()
+                ));
+                self.line("    movq %rax, %xmm2\n    mulsd %xmm2, %xmm1");
+                self.line("    mulsd %xmm0, %xmm1\n    movapd %xmm1, %xmm0");
+            }
+            _ => self.line(&format!("    call {}@PLT", function.libm().unwrap())),
+        }
+    }
+
+    /// Applies the operator to %xmm0 and %xmm1, leaving the result in %xmm0.
+    fn binary(&mut self, operator: BinaryOperator) {
+        let compare = |flag: &str, swapped: bool| {
+            let operands = if swapped {
+                "%xmm0, %xmm1"
+            } else {
+                "%xmm1, %xmm0"
+            };
+            format!("    ucomisd {}\n    {} %al", operands, flag)
+        };
+
+        let code = match operator {
+            BinaryOperator::Add => String::from("    addsd %xmm1, %xmm0"),
+            BinaryOperator::Subtract => String::from("    subsd %xmm1, %xmm0"),
+            BinaryOperator::Multiply => String::from("    mulsd %xmm1, %xmm0"),
+            BinaryOperator::Divide => String::from("    divsd %xmm1, %xmm0"),
+            // Truncating needs SSE4.1, which x86-64 does not guarantee
+            BinaryOperator::IntegerDivide => {
+                String::from("    divsd %xmm1, %xmm0\n    call trunc@PLT")
+            }
+            BinaryOperator::Modulo => String::from("    call fmod@PLT"),
+            BinaryOperator::Power => String::from("    call pow@PLT"),
+            // Unordered (NaN) comparisons set CF, ZF and PF, all of these come out false
+            BinaryOperator::Greater => compare("seta", false),
+            BinaryOperator::GreaterEqual => compare("setae", false),
+            BinaryOperator::Less => compare("seta", true),
+            BinaryOperator::LessEqual => compare("setae", true),
+            BinaryOperator::Equal => compare("sete", false) + "\n    setnp %cl\n    andb %cl, %al",
+            BinaryOperator::NotEqual => {
+                compare("setne", false) + "\n    setp %cl\n    orb %cl, %al"
+            }
+        };
+        self.line(&code);
+
+        if operator.is_comparison() {
+            self.line("    movzbl %al, %eax\n    cvtsi2sdl %eax, %xmm0");
+        }
+    }
+}
+
+/// Escapes text for a GAS `.string`, with octal escapes for anything that is
+/// not printable ASCII.
+fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for byte in text.bytes() {
+        match byte {
+            b'"' | b'\\' => {
+                escaped.push('\\');
+                escaped.push(byte as char);
+            }
+            0x20..=0x7e => escaped.push(byte as char),
+            _ => escaped.push_str(&format!("\\{:03o}", byte)),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{build, cc_available, programs, run, INPUT, SHORT_INPUT};
+    use crate::Target;
+
+    #[test]
+    fn it_matches_the_c_backend() {
+        if !cc_available() {
+            eprintln!("Skipping, cc not found");
+            return;
+        }
+
+        for program in programs() {
+            let name = program.file_stem().unwrap().to_str().unwrap();
+            let c = build(&program, &format!("asm-c-{}", name), Target::C);
+            let asm = build(&program, &format!("asm-{}", name), Target::X86_64);
+
+            for input in [INPUT, SHORT_INPUT] {
+                assert_eq!(run(&asm, input), run(&c, input), "{}", name);
+            }
+        }
+    }
+}
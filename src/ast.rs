@@ -0,0 +1,799 @@
+use std::fmt;
+
+/// A parsed program. Statements keep the nesting of IF/WHILE blocks so later
+/// passes (and the emitter) can walk the tree instead of re-reading tokens.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Program {
+    pub statements: Vec<Statement>,
+    /// Source files in the order they were first included, the main file first.
+    pub files: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Statement {
+    /// Unique per program and increasing in source order.
+    pub id: usize,
+    pub line: usize,
+    /// Index into `Program::files` of the file the statement came from.
+    pub file: usize,
+    pub kind: StatementKind,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum StatementKind {
+    /// Prints the items in order, numbers with the given format, then ends the
+    /// line unless the source ended with `;` or `,`. `PRINT #n,` writes to the
+    /// file opened as number n instead of standard output.
+    Print {
+        file: Option<usize>,
+        items: Vec<PrintItem>,
+        format: NumberFormat,
+        newline: bool,
+    },
+    If {
+        condition: Expression,
+        body: Vec<Statement>,
+    },
+    While {
+        condition: Expression,
+        body: Vec<Statement>,
+    },
+    Label(String),
+    Goto(String),
+    /// Jumps to the label, coming back to the next statement on RETURN.
+    Gosub(String),
+    /// Goes back to after the latest GOSUB that has not returned yet.
+    Return,
+    Let(String, Expression),
+    /// Reads a number into the variable, from standard input or `INPUT #n,`
+    /// from the file opened as number n.
+    Input(Option<usize>, String),
+    /// Leaves the innermost WHILE loop.
+    Break,
+    /// Seeds the generator behind RND.
+    Randomize(Expression),
+    /// `OPEN "path" FOR mode AS #n`.
+    Open {
+        path: String,
+        mode: FileMode,
+        file: usize,
+    },
+    Close(usize),
+}
+
+/// Highest file number OPEN accepts, numbers start at 1.
+pub const FILES: usize = 8;
+
+/// How many GOSUBs can be waiting for their RETURN at once.
+pub const GOSUB_DEPTH: usize = 256;
+
+/// How OPEN opens a file. OUTPUT truncates it, APPEND writes after what is
+/// already there.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileMode {
+    Input,
+    Output,
+    Append,
+}
+
+impl FileMode {
+    pub fn keyword(&self) -> &'static str {
+        match self {
+            FileMode::Input => "INPUT",
+            FileMode::Output => "OUTPUT",
+            FileMode::Append => "APPEND",
+        }
+    }
+
+    /// The mode string `fopen` takes.
+    pub fn c_mode(&self) -> &'static str {
+        match self {
+            FileMode::Input => "r",
+            FileMode::Output => "w",
+            FileMode::Append => "a",
+        }
+    }
+}
+
+impl Program {
+    /// Where a statement is, for diagnostics.
+    pub fn location(&self, file: usize, line: usize) -> String {
+        location(self.files.get(file).map(String::as_str), line)
+    }
+
+    /// Visits every statement in source order, including those nested in blocks.
+    pub fn walk<'a>(&'a self, visit: &mut impl FnMut(&'a Statement)) {
+        walk_statements(&self.statements, visit);
+    }
+
+    /// Every variable the program assigns or reads, in order of first appearance.
+    pub fn variables(&self) -> Vec<&str> {
+        let mut variables: Vec<&str> = Vec::new();
+
+        self.walk(&mut |statement| {
+            let mut names = match &statement.kind {
+                StatementKind::Let(name, _) | StatementKind::Input(_, name) => {
+                    vec![name.as_str()]
+                }
+                _ => Vec::new(),
+            };
+            for expression in statement.expressions() {
+                names.extend(expression.variables());
+            }
+
+            for name in names {
+                if !variables.contains(&name) {
+                    variables.push(name);
+                }
+            }
+        });
+
+        variables
+    }
+}
+
+/// Whether any statement in the list, or nested in it, is a label.
+pub fn has_label(statements: &[Statement]) -> bool {
+    let mut found = false;
+    walk_statements(statements, &mut |statement| {
+        found |= matches!(statement.kind, StatementKind::Label(_));
+    });
+    found
+}
+
+fn walk_statements<'a>(statements: &'a [Statement], visit: &mut impl FnMut(&'a Statement)) {
+    for statement in statements {
+        visit(statement);
+
+        if let StatementKind::If { body, .. } | StatementKind::While { body, .. } = &statement.kind
+        {
+            walk_statements(body, visit);
+        }
+    }
+}
+
+impl Statement {
+    /// The expressions evaluated by this statement itself, not by its body.
+    pub fn expressions(&self) -> Vec<&Expression> {
+        match &self.kind {
+            StatementKind::Print { items, .. } => items
+                .iter()
+                .filter_map(|item| match item {
+                    PrintItem::Number(expression) => Some(expression),
+                    _ => None,
+                })
+                .collect(),
+            StatementKind::Let(_, expression)
+            | StatementKind::Randomize(expression)
+            | StatementKind::If {
+                condition: expression,
+                ..
+            }
+            | StatementKind::While {
+                condition: expression,
+                ..
+            } => vec![expression],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Mutable access to the same expressions as `expressions`.
+    pub fn expressions_mut(&mut self) -> Vec<&mut Expression> {
+        match &mut self.kind {
+            StatementKind::Print { items, .. } => items
+                .iter_mut()
+                .filter_map(|item| match item {
+                    PrintItem::Number(expression) => Some(expression),
+                    _ => None,
+                })
+                .collect(),
+            StatementKind::Let(_, expression)
+            | StatementKind::Randomize(expression)
+            | StatementKind::If {
+                condition: expression,
+                ..
+            }
+            | StatementKind::While {
+                condition: expression,
+                ..
+            } => vec![expression],
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// One thing printed by PRINT. Items written next to each other are separated
+/// by `;` in source, `,` becomes a tab.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PrintItem {
+    Text(String),
+    Number(Expression),
+    Tab,
+}
+
+/// Writes the statement's first line in source form, without its body.
+impl fmt::Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            StatementKind::Print {
+                file,
+                items,
+                format,
+                newline,
+            } => {
+                write!(f, "PRINT")?;
+                if let Some(file) = file {
+                    write!(f, " #{},", file)?;
+                }
+                if *format != NumberFormat::default() {
+                    write!(f, " USING \"{}\";", format.picture())?;
+                }
+
+                // Items need a `;` between them unless a `,` already separates them
+                let mut separated = true;
+                for item in items {
+                    match item {
+                        PrintItem::Tab if separated => write!(f, " ,")?,
+                        PrintItem::Tab => write!(f, ",")?,
+                        _ if !separated => write!(f, "; ")?,
+                        _ => write!(f, " ")?,
+                    }
+                    match item {
+                        PrintItem::Text(text) => write!(f, "{}", quote(text))?,
+                        PrintItem::Number(expression) => write!(f, "{}", expression)?,
+                        PrintItem::Tab => (),
+                    }
+                    separated = *item == PrintItem::Tab;
+                }
+
+                if !newline && (items.is_empty() || !separated) {
+                    write!(f, ";")?;
+                }
+                Ok(())
+            }
+            StatementKind::If { condition, .. } => write!(f, "IF {} THEN", Condition(condition)),
+            StatementKind::While { condition, .. } => {
+                write!(f, "WHILE {} REPEAT", Condition(condition))
+            }
+            // Line numbers are labels written without the keyword
+            StatementKind::Label(name) if name.starts_with(|c: char| c.is_ascii_digit()) => {
+                write!(f, "{}", name)
+            }
+            StatementKind::Label(name) => write!(f, "LABEL {}", name),
+            StatementKind::Goto(name) => write!(f, "GOTO {}", name),
+            StatementKind::Gosub(name) => write!(f, "GOSUB {}", name),
+            StatementKind::Return => write!(f, "RETURN"),
+            StatementKind::Let(name, expression) => write!(f, "LET {} = {}", name, expression),
+            StatementKind::Input(None, name) => write!(f, "INPUT {}", name),
+            StatementKind::Input(Some(file), name) => write!(f, "INPUT #{}, {}", file, name),
+            StatementKind::Break => write!(f, "BREAK"),
+            StatementKind::Randomize(seed) => write!(f, "RANDOMIZE {}", seed),
+            StatementKind::Open { path, mode, file } => write!(
+                f,
+                "OPEN {} FOR {} AS #{}",
+                quote(path),
+                mode.keyword(),
+                file
+            ),
+            StatementKind::Close(file) => write!(f, "CLOSE #{}", file),
+        }
+    }
+}
+
+/// Describes a line for diagnostics, naming the file when there is one.
+pub fn location(file: Option<&str>, line: usize) -> String {
+    match file {
+        Some(file) => format!("line {} of {}", line, file),
+        None => format!("line {}", line),
+    }
+}
+
+/// Quotes text as a string literal the lexer reads back unchanged.
+fn quote(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for char in text.chars() {
+        match char {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            char if char.is_control() => quoted.push_str(&format!("\\u{{{:x}}}", char as u32)),
+            char => quoted.push(char),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Conditions must be comparisons in source, optimized ones may not be.
+struct Condition<'a>(&'a Expression);
+
+impl fmt::Display for Condition<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Expression::Binary(operator, _, _) if operator.is_comparison() => {
+                write!(f, "{}", self.0)
+            }
+            Expression::Unary(UnaryOperator::Not, operand) => {
+                write!(f, "NOT {}", Condition(operand))
+            }
+            _ => write!(f, "{} != 0", self.0),
+        }
+    }
+}
+
+/// Writes the whole program back as source, indenting block bodies.
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_statements(f, &self.statements, 0)
+    }
+}
+
+fn write_statements(
+    f: &mut fmt::Formatter<'_>,
+    statements: &[Statement],
+    depth: usize,
+) -> fmt::Result {
+    let indent = "    ".repeat(depth);
+
+    for statement in statements {
+        writeln!(f, "{}{}", indent, statement)?;
+
+        match &statement.kind {
+            StatementKind::If { body, .. } => {
+                write_statements(f, body, depth + 1)?;
+                writeln!(f, "{}ENDIF", indent)?;
+            }
+            StatementKind::While { body, .. } => {
+                write_statements(f, body, depth + 1)?;
+                writeln!(f, "{}ENDWHILE", indent)?;
+            }
+            _ => (),
+        }
+    }
+
+    Ok(())
+}
+
+/// How PRINT writes a number, the same as `printf("%*.*f")`. Plain PRINT
+/// uses two decimals without padding, `PRINT USING "###.##"` pads to the
+/// length of the picture with as many decimals as there are #s after the point.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NumberFormat {
+    pub width: usize,
+    pub decimals: usize,
+}
+
+impl Default for NumberFormat {
+    fn default() -> Self {
+        NumberFormat {
+            width: 0,
+            decimals: 2,
+        }
+    }
+}
+
+impl NumberFormat {
+    /// Most decimals a picture can ask for, every runtime prints these exactly.
+    pub const MAX_DECIMALS: usize = 20;
+    /// Longest picture, wider than any line is worth padding to.
+    pub const MAX_WIDTH: usize = 100;
+
+    /// Reads a picture made of #s with at most one point, such as `"##.###"`.
+    pub fn from_picture(picture: &str) -> Option<NumberFormat> {
+        let (whole, fraction) = picture.split_once('.').unwrap_or((picture, ""));
+        let digits = |part: &str| part.chars().all(|char| char == '#');
+
+        if !digits(whole) || !digits(fraction) || whole.len() + fraction.len() == 0 {
+            return None;
+        }
+        if fraction.len() > Self::MAX_DECIMALS || picture.len() > Self::MAX_WIDTH {
+            return None;
+        }
+
+        Some(NumberFormat {
+            width: picture.len(),
+            decimals: fraction.len(),
+        })
+    }
+
+    /// A picture that reads back as this format.
+    pub fn picture(&self) -> String {
+        if self.decimals == 0 {
+            return "#".repeat(self.width.max(1));
+        }
+
+        let whole = self.width.saturating_sub(self.decimals + 1);
+        format!("{}.{}", "#".repeat(whole), "#".repeat(self.decimals))
+    }
+
+    /// Formats like `printf("%*.*f")`, except that NaN is always "nan" as its
+    /// sign depends on how it was produced. Every backend prints this way.
+    pub fn format(&self, value: f64) -> String {
+        let text = if value.is_nan() {
+            String::from("nan")
+        } else {
+            format!("{:.*}", self.decimals, value)
+        };
+
+        format!("{:>1$}", text, self.width)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expression {
+    Number(f64),
+    Variable(String),
+    Unary(UnaryOperator, Box<Expression>),
+    Binary(BinaryOperator, Box<Expression>, Box<Expression>),
+    Call(Function, Vec<Expression>),
+    /// `EOF(n)`, true once only whitespace is left in the file opened as
+    /// number n. Keeps its line for the runtime error when the file is not
+    /// open for input.
+    Eof {
+        file: usize,
+        line: usize,
+    },
+}
+
+/// Built-in functions, called like `SQR(x)`. Names match in any case.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Function {
+    Abs,
+    Sqr,
+    /// Rounds down, like C's `floor`.
+    Int,
+    Sgn,
+    /// Remainder with the sign of the first argument, like C's `fmod`.
+    Mod,
+    Sin,
+    Cos,
+    Tan,
+    Atn,
+    Exp,
+    Log,
+    /// A random number from zero up to but not including the argument.
+    Rnd,
+}
+
+pub const FUNCTIONS: [Function; 12] = [
+    Function::Abs,
+    Function::Sqr,
+    Function::Int,
+    Function::Sgn,
+    Function::Mod,
+    Function::Sin,
+    Function::Cos,
+    Function::Tan,
+    Function::Atn,
+    Function::Exp,
+    Function::Log,
+    Function::Rnd,
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnaryOperator {
+    Plus,
+    Minus,
+    /// 1 when the operand is zero, 0 otherwise, NaN included.
+    Not,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BinaryOperator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    /// `\`, the quotient truncated toward zero.
+    IntegerDivide,
+    /// `MOD` or `%`, the remainder with the sign of the left side like C's fmod.
+    Modulo,
+    /// `^`, right associative and binding tighter than unary minus.
+    Power,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+}
+
+impl Expression {
+    /// Names of all variables read by the expression.
+    pub fn variables(&self) -> Vec<&str> {
+        match self {
+            Expression::Number(_) | Expression::Eof { .. } => Vec::new(),
+            Expression::Variable(name) => vec![name.as_str()],
+            Expression::Unary(_, operand) => operand.variables(),
+            Expression::Binary(_, left, right) => {
+                let mut variables = left.variables();
+                variables.extend(right.variables());
+                variables
+            }
+            Expression::Call(_, arguments) => arguments
+                .iter()
+                .flat_map(|argument| argument.variables())
+                .collect(),
+        }
+    }
+}
+
+impl Expression {
+    /// Whether evaluating the expression changes state, which RND does, or
+    /// can stop the program, which EOF does when the file is not open.
+    pub fn has_side_effects(&self) -> bool {
+        match self {
+            Expression::Number(_) | Expression::Variable(_) => false,
+            Expression::Eof { .. } => true,
+            Expression::Unary(_, operand) => operand.has_side_effects(),
+            Expression::Binary(_, left, right) => {
+                left.has_side_effects() || right.has_side_effects()
+            }
+            Expression::Call(function, arguments) => {
+                *function == Function::Rnd || arguments.iter().any(Expression::has_side_effects)
+            }
+        }
+    }
+}
+
+impl Function {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Function::Abs => "ABS",
+            Function::Sqr => "SQR",
+            Function::Int => "INT",
+            Function::Sgn => "SGN",
+            Function::Mod => "MOD",
+            Function::Sin => "SIN",
+            Function::Cos => "COS",
+            Function::Tan => "TAN",
+            Function::Atn => "ATN",
+            Function::Exp => "EXP",
+            Function::Log => "LOG",
+            Function::Rnd => "RND",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Function> {
+        FUNCTIONS
+            .into_iter()
+            .find(|function| function.name().eq_ignore_ascii_case(name))
+    }
+
+    /// The C math library function with the same behaviour, if there is one.
+    pub fn libm(&self) -> Option<&'static str> {
+        match self {
+            Function::Abs => Some("fabs"),
+            Function::Sqr => Some("sqrt"),
+            Function::Int => Some("floor"),
+            Function::Mod => Some("fmod"),
+            Function::Sin => Some("sin"),
+            Function::Cos => Some("cos"),
+            Function::Tan => Some("tan"),
+            Function::Atn => Some("atan"),
+            Function::Exp => Some("exp"),
+            Function::Log => Some("log"),
+            Function::Sgn | Function::Rnd => None,
+        }
+    }
+
+    pub fn arity(&self) -> usize {
+        match self {
+            Function::Mod => 2,
+            _ => 1,
+        }
+    }
+
+    /// Applies the function the way the generated program does at runtime.
+    /// RND depends on the generator's state, so it has no value here.
+    pub fn evaluate(&self, arguments: &[f64]) -> Option<f64> {
+        let x = arguments[0];
+
+        Some(match self {
+            Function::Abs => x.abs(),
+            Function::Sqr => x.sqrt(),
+            Function::Int => x.floor(),
+            Function::Sgn => sign(x),
+            Function::Mod => x % arguments[1],
+            Function::Sin => x.sin(),
+            Function::Cos => x.cos(),
+            Function::Tan => x.tan(),
+            Function::Atn => x.atan(),
+            Function::Exp => x.exp(),
+            Function::Log => x.ln(),
+            Function::Rnd => return None,
+        })
+    }
+}
+
+/// -1, 0 or 1 like `(x > 0) - (x < 0)` in C, so NaN gives 0.
+fn sign(x: f64) -> f64 {
+    f64::from(x > 0.0) - f64::from(x < 0.0)
+}
+
+/// The generator behind RND, the same 64-bit LCG in every backend. Each call
+/// advances the state and scales its top 53 bits into `[0, 1)`.
+#[derive(Default)]
+pub struct Random {
+    pub state: u64,
+}
+
+impl Random {
+    pub const MULTIPLIER: u64 = 6364136223846793005;
+    pub const INCREMENT: u64 = 1442695040888963407;
+
+    /// Seeds like RANDOMIZE, from the seed truncated to an integer. Seeds
+    /// outside the i64 range saturate and NaN seeds with 0, as `as` does.
+    pub fn seed(&mut self, seed: f64) {
+        self.state = seed as i64 as u64;
+    }
+
+    pub fn next(&mut self, scale: f64) -> f64 {
+        self.state = self
+            .state
+            .wrapping_mul(Self::MULTIPLIER)
+            .wrapping_add(Self::INCREMENT);
+        (self.state >> 11) as f64 / (1u64 << 53) as f64 * scale
+    }
+}
+
+impl UnaryOperator {
+    pub fn evaluate(&self, operand: f64) -> f64 {
+        match self {
+            UnaryOperator::Plus => operand,
+            UnaryOperator::Minus => -operand,
+            UnaryOperator::Not => {
+                if operand == 0.0 {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            UnaryOperator::Plus => "+",
+            UnaryOperator::Minus => "-",
+            UnaryOperator::Not => "NOT",
+        }
+    }
+}
+
+impl BinaryOperator {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            BinaryOperator::Add => "+",
+            BinaryOperator::Subtract => "-",
+            BinaryOperator::Multiply => "*",
+            BinaryOperator::Divide => "/",
+            BinaryOperator::IntegerDivide => "\\",
+            BinaryOperator::Modulo => "MOD",
+            BinaryOperator::Power => "^",
+            BinaryOperator::Equal => "==",
+            BinaryOperator::NotEqual => "!=",
+            BinaryOperator::Less => "<",
+            BinaryOperator::LessEqual => "<=",
+            BinaryOperator::Greater => ">",
+            BinaryOperator::GreaterEqual => ">=",
+        }
+    }
+
+    /// Applies the operator the way the generated program does at runtime,
+    /// comparisons give 1 for true and 0 for false.
+    pub fn evaluate(&self, left: f64, right: f64) -> f64 {
+        let truth = |value: bool| if value { 1.0 } else { 0.0 };
+
+        match self {
+            BinaryOperator::Add => left + right,
+            BinaryOperator::Subtract => left - right,
+            BinaryOperator::Multiply => left * right,
+            BinaryOperator::Divide => left / right,
+            BinaryOperator::IntegerDivide => (left / right).trunc(),
+            // `%` on floats is fmod
+            BinaryOperator::Modulo => left % right,
+            BinaryOperator::Power => left.powf(right),
+            BinaryOperator::Equal => truth(left == right),
+            BinaryOperator::NotEqual => truth(left != right),
+            BinaryOperator::Less => truth(left < right),
+            BinaryOperator::LessEqual => truth(left <= right),
+            BinaryOperator::Greater => truth(left > right),
+            BinaryOperator::GreaterEqual => truth(left >= right),
+        }
+    }
+
+    /// Whether the operator compares its operands, giving 1 or 0.
+    pub fn is_comparison(&self) -> bool {
+        !matches!(
+            self,
+            BinaryOperator::Add
+                | BinaryOperator::Subtract
+                | BinaryOperator::Multiply
+                | BinaryOperator::Divide
+                | BinaryOperator::IntegerDivide
+                | BinaryOperator::Modulo
+                | BinaryOperator::Power
+        )
+    }
+
+    /// Whether a constant zero on the right is a division by zero.
+    pub fn divides(&self) -> bool {
+        matches!(self, BinaryOperator::Divide | BinaryOperator::IntegerDivide)
+    }
+
+    /// Binding strength, higher binds tighter. Matches the C operators we
+    /// emit, the ones C lacks are emitted as calls.
+    pub fn precedence(&self) -> u8 {
+        match self {
+            BinaryOperator::Equal | BinaryOperator::NotEqual => 1,
+            BinaryOperator::Less
+            | BinaryOperator::LessEqual
+            | BinaryOperator::Greater
+            | BinaryOperator::GreaterEqual => 2,
+            BinaryOperator::Add | BinaryOperator::Subtract => 3,
+            BinaryOperator::Multiply
+            | BinaryOperator::Divide
+            | BinaryOperator::IntegerDivide
+            | BinaryOperator::Modulo => 4,
+            BinaryOperator::Power => 5,
+        }
+    }
+}
+
+impl fmt::Display for Expression {
+    /// Writes the expression back in source form, grouping with parentheses
+    /// wherever the tree differs from the default precedence.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expression::Number(value) => write!(f, "{}", value),
+            Expression::Variable(name) => write!(f, "{}", name),
+            // NOT takes everything after it, comparisons included
+            Expression::Unary(UnaryOperator::Not, operand) => write!(f, "NOT {}", operand),
+            Expression::Unary(operator, operand) => match operand.as_ref() {
+                Expression::Binary(..) | Expression::Unary(UnaryOperator::Not, _) => {
+                    write!(f, "{}({})", operator.symbol(), operand)
+                }
+                _ => write!(f, "{}{}", operator.symbol(), operand),
+            },
+            // `-2 ^ 2` is -(2 ^ 2), so a signed base needs parentheses
+            Expression::Binary(BinaryOperator::Power, left, right) => {
+                match left.as_ref() {
+                    Expression::Unary(..) => write!(f, "({})", left)?,
+                    Expression::Number(value) if value.is_sign_negative() => {
+                        write!(f, "({})", left)?
+                    }
+                    _ => write_operand(f, left, BinaryOperator::Power.precedence() + 1)?,
+                }
+                write!(f, " ^ ")?;
+                write_operand(f, right, BinaryOperator::Power.precedence())
+            }
+            Expression::Binary(operator, left, right) => {
+                let precedence = operator.precedence();
+                write_operand(f, left, precedence)?;
+                write!(f, " {} ", operator.symbol())?;
+                write_operand(f, right, precedence + 1)
+            }
+            Expression::Call(function, arguments) => {
+                let arguments: Vec<String> = arguments.iter().map(|a| a.to_string()).collect();
+                write!(f, "{}({})", function.name(), arguments.join(", "))
+            }
+            Expression::Eof { file, .. } => write!(f, "EOF({})", file),
+        }
+    }
+}
+
+fn write_operand(
+    f: &mut fmt::Formatter<'_>,
+    expression: &Expression,
+    min_precedence: u8,
+) -> fmt::Result {
+    match expression {
+        Expression::Binary(operator, _, _) if operator.precedence() < min_precedence => {
+            write!(f, "({})", expression)
+        }
+        Expression::Unary(UnaryOperator::Not, _) => write!(f, "({})", expression),
+        _ => write!(f, "{}", expression),
+    }
+}
@@ -0,0 +1,845 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::ast::{
+    BinaryOperator, Expression, FileMode, Function, NumberFormat, PrintItem, Program, Statement,
+    StatementKind, UnaryOperator, FILES, FUNCTIONS,
+};
+use crate::ir::{binary_name, unary_name};
+
+/// Identifies a bytecode file, followed by the format version.
+const MAGIC: &[u8; 4] = b"TBBC";
+pub const VERSION: u16 = 9;
+
+/// Code for the stack machine in `vm`. Expressions push their result, while
+/// stores, prints and conditional jumps pop what they use. Jump targets are
+/// instruction indices.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Chunk {
+    pub variables: Vec<String>,
+    pub strings: Vec<String>,
+    pub code: Vec<Instruction>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Instruction {
+    Push(f64),
+    Load(u32),
+    Store(u32),
+    Unary(UnaryOperator),
+    Binary(BinaryOperator),
+    /// Pops the function's arguments, the last one on top, and pushes its result.
+    Call(Function),
+    /// Pops the seed for RND.
+    Randomize,
+    Jump(u32),
+    /// Pops the condition and jumps when it is zero.
+    JumpIfZero(u32),
+    /// Jumps to the target, keeping the next position for `Return`. The
+    /// line is reported when GOSUBs nest too deeply.
+    Gosub(u32, u32),
+    /// Jumps back to after the latest `Gosub`, the line is reported when
+    /// there is none.
+    Return(u32),
+    /// Prints without ending the line, `Newline` does that.
+    PrintNumber(NumberFormat),
+    PrintString(u32),
+    Newline,
+    /// Reads into the variable, the line is reported when input runs out.
+    Input(u32, u32),
+    /// File number, path string, mode and line. The file instructions all
+    /// end with the line, reported when the file is not open or runs out.
+    Open(u32, u32, FileMode, u32),
+    Close(u32, u32),
+    /// Pops a number and prints it to the file.
+    FilePrintNumber(u32, NumberFormat, u32),
+    /// File number, string and line.
+    FilePrintString(u32, u32, u32),
+    FileNewline(u32, u32),
+    /// File number, variable and line.
+    FileInput(u32, u32, u32),
+    /// Pushes EOF of the file.
+    Eof(u32, u32),
+    Halt,
+}
+
+const MODES: [FileMode; 3] = [FileMode::Input, FileMode::Output, FileMode::Append];
+const UNARY: [UnaryOperator; 3] = [
+    UnaryOperator::Plus,
+    UnaryOperator::Minus,
+    UnaryOperator::Not,
+];
+const BINARY: [BinaryOperator; 13] = [
+    BinaryOperator::Add,
+    BinaryOperator::Subtract,
+    BinaryOperator::Multiply,
+    BinaryOperator::Divide,
+    BinaryOperator::Equal,
+    BinaryOperator::NotEqual,
+    BinaryOperator::Less,
+    BinaryOperator::LessEqual,
+    BinaryOperator::Greater,
+    BinaryOperator::GreaterEqual,
+    BinaryOperator::IntegerDivide,
+    BinaryOperator::Modulo,
+    BinaryOperator::Power,
+];
+
+// Opcodes in the on-disk format. Operators and built-in functions take one
+// opcode each, in the order of `UNARY`, `BINARY` and `FUNCTIONS`. File modes
+// are written as their index in `MODES`.
+const PUSH: u8 = 0x01;
+const LOAD: u8 = 0x02;
+const STORE: u8 = 0x03;
+const JUMP: u8 = 0x04;
+const JUMP_IF_ZERO: u8 = 0x05;
+const PRINT_NUMBER: u8 = 0x06;
+const PRINT_STRING: u8 = 0x07;
+const INPUT: u8 = 0x08;
+const HALT: u8 = 0x09;
+const NEWLINE: u8 = 0x0a;
+const RANDOMIZE: u8 = 0x0b;
+const GOSUB: u8 = 0x0c;
+const RETURN: u8 = 0x0d;
+const OPEN: u8 = 0x40;
+const CLOSE: u8 = 0x41;
+const FILE_PRINT_NUMBER: u8 = 0x42;
+const FILE_PRINT_STRING: u8 = 0x43;
+const FILE_NEWLINE: u8 = 0x44;
+const FILE_INPUT: u8 = 0x45;
+const EOF: u8 = 0x46;
+const FIRST_UNARY: u8 = 0x10;
+const FIRST_BINARY: u8 = 0x20;
+const FIRST_CALL: u8 = 0x30;
+
+pub fn compile(program: &Program) -> Chunk {
+    let mut compiler = Compiler {
+        chunk: Chunk {
+            variables: program
+                .variables()
+                .iter()
+                .map(|name| name.to_string())
+                .collect(),
+            ..Chunk::default()
+        },
+        labels: HashMap::new(),
+        gotos: Vec::new(),
+        breaks: Vec::new(),
+    };
+
+    compiler.statements(&program.statements);
+    compiler.chunk.code.push(Instruction::Halt);
+
+    // Labels are checked by the parser, so every GOTO has somewhere to go
+    for (position, label) in compiler.gotos {
+        let target = compiler.labels[&label];
+        compiler.chunk.code[position] = match compiler.chunk.code[position] {
+            Instruction::Gosub(_, line) => Instruction::Gosub(target, line),
+            _ => Instruction::Jump(target),
+        };
+    }
+
+    compiler.chunk
+}
+
+struct Compiler {
+    chunk: Chunk,
+    labels: HashMap<String, u32>,
+    /// GOTOs and GOSUBs waiting for their label's position.
+    gotos: Vec<(usize, String)>,
+    /// BREAK jumps waiting for the end of their loop, innermost WHILE last.
+    breaks: Vec<Vec<usize>>,
+}
+
+impl Compiler {
+    fn position(&self) -> u32 {
+        self.chunk.code.len() as u32
+    }
+
+    fn push(&mut self, instruction: Instruction) {
+        self.chunk.code.push(instruction);
+    }
+
+    fn variable(&self, name: &str) -> u32 {
+        self.chunk
+            .variables
+            .iter()
+            .position(|variable| variable == name)
+            .unwrap() as u32
+    }
+
+    fn string(&mut self, text: &str) -> u32 {
+        match self.chunk.strings.iter().position(|string| string == text) {
+            Some(index) => index as u32,
+            None => {
+                self.chunk.strings.push(text.to_string());
+                self.chunk.strings.len() as u32 - 1
+            }
+        }
+    }
+
+    fn statements(&mut self, statements: &[Statement]) {
+        for statement in statements {
+            self.statement(statement);
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match &statement.kind {
+            StatementKind::Print {
+                file,
+                items,
+                format,
+                newline,
+            } => {
+                let line = statement.line as u32;
+                let file = file.map(|file| file as u32);
+
+                for item in items {
+                    let text = match item {
+                        PrintItem::Text(text) => text,
+                        PrintItem::Tab => "\t",
+                        PrintItem::Number(expression) => {
+                            self.expression(expression);
+                            self.push(match file {
+                                Some(file) => Instruction::FilePrintNumber(file, *format, line),
+                                None => Instruction::PrintNumber(*format),
+                            });
+                            continue;
+                        }
+                    };
+
+                    let index = self.string(text);
+                    self.push(match file {
+                        Some(file) => Instruction::FilePrintString(file, index, line),
+                        None => Instruction::PrintString(index),
+                    });
+                }
+                if *newline {
+                    self.push(match file {
+                        Some(file) => Instruction::FileNewline(file, line),
+                        None => Instruction::Newline,
+                    });
+                }
+            }
+            StatementKind::If { condition, body } => {
+                self.expression(condition);
+                let skip = self.chunk.code.len();
+                self.push(Instruction::JumpIfZero(0));
+
+                self.statements(body);
+                self.chunk.code[skip] = Instruction::JumpIfZero(self.position());
+            }
+            StatementKind::While { condition, body } => {
+                let start = self.position();
+                self.expression(condition);
+                let exit = self.chunk.code.len();
+                self.push(Instruction::JumpIfZero(0));
+
+                self.breaks.push(Vec::new());
+                self.statements(body);
+                self.push(Instruction::Jump(start));
+                self.chunk.code[exit] = Instruction::JumpIfZero(self.position());
+
+                for position in self.breaks.pop().unwrap() {
+                    self.chunk.code[position] = Instruction::Jump(self.position());
+                }
+            }
+            StatementKind::Label(label) => {
+                self.labels.insert(label.clone(), self.position());
+            }
+            StatementKind::Goto(label) => {
+                self.gotos.push((self.chunk.code.len(), label.clone()));
+                self.push(Instruction::Jump(0));
+            }
+            StatementKind::Gosub(label) => {
+                self.gotos.push((self.chunk.code.len(), label.clone()));
+                self.push(Instruction::Gosub(0, statement.line as u32));
+            }
+            StatementKind::Return => self.push(Instruction::Return(statement.line as u32)),
+            StatementKind::Let(name, expression) => {
+                self.expression(expression);
+                self.push(Instruction::Store(self.variable(name)));
+            }
+            StatementKind::Input(file, name) => {
+                let variable = self.variable(name);
+                let line = statement.line as u32;
+                self.push(match file {
+                    Some(file) => Instruction::FileInput(*file as u32, variable, line),
+                    None => Instruction::Input(variable, line),
+                })
+            }
+            StatementKind::Open { path, mode, file } => {
+                let path = self.string(path);
+                self.push(Instruction::Open(
+                    *file as u32,
+                    path,
+                    *mode,
+                    statement.line as u32,
+                ))
+            }
+            StatementKind::Close(file) => {
+                self.push(Instruction::Close(*file as u32, statement.line as u32))
+            }
+            StatementKind::Randomize(seed) => {
+                self.expression(seed);
+                self.push(Instruction::Randomize);
+            }
+            StatementKind::Break => {
+                self.breaks.last_mut().unwrap().push(self.chunk.code.len());
+                self.push(Instruction::Jump(0));
+            }
+        }
+    }
+
+    fn expression(&mut self, expression: &Expression) {
+        match expression {
+            Expression::Number(value) => self.push(Instruction::Push(*value)),
+            Expression::Variable(name) => self.push(Instruction::Load(self.variable(name))),
+            Expression::Unary(operator, operand) => {
+                self.expression(operand);
+                self.push(Instruction::Unary(*operator));
+            }
+            Expression::Binary(operator, left, right) => {
+                self.expression(left);
+                self.expression(right);
+                self.push(Instruction::Binary(*operator));
+            }
+            Expression::Call(function, arguments) => {
+                for argument in arguments {
+                    self.expression(argument);
+                }
+                self.push(Instruction::Call(*function));
+            }
+            Expression::Eof { file, line } => {
+                self.push(Instruction::Eof(*file as u32, *line as u32))
+            }
+        }
+    }
+}
+
+impl Chunk {
+    /// Serializes the chunk. All integers are little endian, names and strings
+    /// are UTF-8 prefixed with their length:
+    ///
+    /// ```text
+    /// "TBBC" version:u16
+    /// variables:u32 (length:u32 bytes)*
+    /// strings:u32 (length:u32 bytes)*
+    /// instructions:u32 (opcode:u8 operand*)*
+    /// ```
+    ///
+    /// Operands are u32s except for the f64 pushed by `push`. `print` takes the
+    /// width and decimals, `input` the variable and the line it was read on.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(VERSION.to_le_bytes());
+
+        for names in [&self.variables, &self.strings] {
+            bytes.extend((names.len() as u32).to_le_bytes());
+            for name in names {
+                bytes.extend((name.len() as u32).to_le_bytes());
+                bytes.extend(name.as_bytes());
+            }
+        }
+
+        bytes.extend((self.code.len() as u32).to_le_bytes());
+        for instruction in &self.code {
+            let (opcode, operands) = match *instruction {
+                Instruction::Push(_) => (PUSH, vec![]),
+                Instruction::Load(variable) => (LOAD, vec![variable]),
+                Instruction::Store(variable) => (STORE, vec![variable]),
+                Instruction::Unary(operator) => (FIRST_UNARY + index(&UNARY, operator), vec![]),
+                Instruction::Binary(operator) => (FIRST_BINARY + index(&BINARY, operator), vec![]),
+                Instruction::Call(function) => (FIRST_CALL + index(&FUNCTIONS, function), vec![]),
+                Instruction::Randomize => (RANDOMIZE, vec![]),
+                Instruction::Jump(target) => (JUMP, vec![target]),
+                Instruction::JumpIfZero(target) => (JUMP_IF_ZERO, vec![target]),
+                Instruction::Gosub(target, line) => (GOSUB, vec![target, line]),
+                Instruction::Return(line) => (RETURN, vec![line]),
+                Instruction::PrintNumber(format) => (
+                    PRINT_NUMBER,
+                    vec![format.width as u32, format.decimals as u32],
+                ),
+                Instruction::PrintString(string) => (PRINT_STRING, vec![string]),
+                Instruction::Input(variable, line) => (INPUT, vec![variable, line]),
+                Instruction::Open(file, path, mode, line) => {
+                    (OPEN, vec![file, path, index(&MODES, mode) as u32, line])
+                }
+                Instruction::Close(file, line) => (CLOSE, vec![file, line]),
+                Instruction::FilePrintNumber(file, format, line) => (
+                    FILE_PRINT_NUMBER,
+                    vec![file, format.width as u32, format.decimals as u32, line],
+                ),
+                Instruction::FilePrintString(file, string, line) => {
+                    (FILE_PRINT_STRING, vec![file, string, line])
+                }
+                Instruction::FileNewline(file, line) => (FILE_NEWLINE, vec![file, line]),
+                Instruction::FileInput(file, variable, line) => {
+                    (FILE_INPUT, vec![file, variable, line])
+                }
+                Instruction::Eof(file, line) => (EOF, vec![file, line]),
+                Instruction::Newline => (NEWLINE, vec![]),
+                Instruction::Halt => (HALT, vec![]),
+            };
+
+            bytes.push(opcode);
+            if let Instruction::Push(value) = instruction {
+                bytes.extend(value.to_bits().to_le_bytes());
+            }
+            for operand in operands {
+                bytes.extend(operand.to_le_bytes());
+            }
+        }
+
+        bytes
+    }
+
+    /// Reads a chunk written by `to_bytes`, checking that every index, jump
+    /// target and number format is in range and that no path can pop an empty
+    /// stack, so the VM can trust it.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Chunk, String> {
+        let mut reader = Reader { bytes, position: 0 };
+
+        if reader.take(4)? != MAGIC {
+            return Err(String::from("Not a bytecode file"));
+        }
+        let version = u16::from_le_bytes(reader.take(2)?.try_into().unwrap());
+        if version != VERSION {
+            return Err(format!(
+                "Unsupported bytecode version {}, expected {}",
+                version, VERSION
+            ));
+        }
+
+        let variables = reader.names()?;
+        let strings = reader.names()?;
+
+        let length = reader.u32()?;
+        let mut code = Vec::new();
+        for _ in 0..length {
+            let instruction = match reader.take(1)?[0] {
+                PUSH => Instruction::Push(f64::from_bits(u64::from_le_bytes(
+                    reader.take(8)?.try_into().unwrap(),
+                ))),
+                LOAD => Instruction::Load(reader.u32()?),
+                STORE => Instruction::Store(reader.u32()?),
+                JUMP => Instruction::Jump(reader.u32()?),
+                JUMP_IF_ZERO => Instruction::JumpIfZero(reader.u32()?),
+                GOSUB => Instruction::Gosub(reader.u32()?, reader.u32()?),
+                RETURN => Instruction::Return(reader.u32()?),
+                PRINT_NUMBER => Instruction::PrintNumber(NumberFormat {
+                    width: reader.u32()? as usize,
+                    decimals: reader.u32()? as usize,
+                }),
+                PRINT_STRING => Instruction::PrintString(reader.u32()?),
+                INPUT => Instruction::Input(reader.u32()?, reader.u32()?),
+                OPEN => {
+                    let (file, path) = (reader.u32()?, reader.u32()?);
+                    let mode = match MODES.get(reader.u32()? as usize) {
+                        Some(mode) => *mode,
+                        None => return Err(String::from("Unknown file mode")),
+                    };
+                    Instruction::Open(file, path, mode, reader.u32()?)
+                }
+                CLOSE => Instruction::Close(reader.u32()?, reader.u32()?),
+                FILE_PRINT_NUMBER => Instruction::FilePrintNumber(
+                    reader.u32()?,
+                    NumberFormat {
+                        width: reader.u32()? as usize,
+                        decimals: reader.u32()? as usize,
+                    },
+                    reader.u32()?,
+                ),
+                FILE_PRINT_STRING => {
+                    Instruction::FilePrintString(reader.u32()?, reader.u32()?, reader.u32()?)
+                }
+                FILE_NEWLINE => Instruction::FileNewline(reader.u32()?, reader.u32()?),
+                FILE_INPUT => Instruction::FileInput(reader.u32()?, reader.u32()?, reader.u32()?),
+                EOF => Instruction::Eof(reader.u32()?, reader.u32()?),
+                NEWLINE => Instruction::Newline,
+                RANDOMIZE => Instruction::Randomize,
+                HALT => Instruction::Halt,
+                opcode if (FIRST_UNARY..FIRST_UNARY + UNARY.len() as u8).contains(&opcode) => {
+                    Instruction::Unary(UNARY[(opcode - FIRST_UNARY) as usize])
+                }
+                opcode if (FIRST_BINARY..FIRST_BINARY + BINARY.len() as u8).contains(&opcode) => {
+                    Instruction::Binary(BINARY[(opcode - FIRST_BINARY) as usize])
+                }
+                opcode if (FIRST_CALL..FIRST_CALL + FUNCTIONS.len() as u8).contains(&opcode) => {
+                    Instruction::Call(FUNCTIONS[(opcode - FIRST_CALL) as usize])
+                }
+                opcode => return Err(format!("Unknown opcode {:#04x}", opcode)),
+            };
+            code.push(instruction);
+        }
+
+        if reader.position != bytes.len() {
+            return Err(String::from("Trailing bytes after code"));
+        }
+
+        let chunk = Chunk {
+            variables,
+            strings,
+            code,
+        };
+        chunk.check()?;
+        Ok(chunk)
+    }
+
+    fn check(&self) -> Result<(), String> {
+        for (position, instruction) in self.code.iter().enumerate() {
+            let in_range = match *instruction {
+                Instruction::Load(variable)
+                | Instruction::Store(variable)
+                | Instruction::Input(variable, _) => (variable as usize) < self.variables.len(),
+                Instruction::PrintString(string) => (string as usize) < self.strings.len(),
+                Instruction::FileInput(file, variable, _) => {
+                    file_number(file) && (variable as usize) < self.variables.len()
+                }
+                Instruction::Open(file, string, _, _)
+                | Instruction::FilePrintString(file, string, _) => {
+                    file_number(file) && (string as usize) < self.strings.len()
+                }
+                Instruction::Close(file, _)
+                | Instruction::FilePrintNumber(file, _, _)
+                | Instruction::FileNewline(file, _)
+                | Instruction::Eof(file, _) => file_number(file),
+                Instruction::Jump(target)
+                | Instruction::JumpIfZero(target)
+                | Instruction::Gosub(target, _) => (target as usize) < self.code.len(),
+                _ => true,
+            };
+
+            if !in_range {
+                return Err(format!("Operand out of range at {}", position));
+            }
+
+            let format = match instruction {
+                Instruction::PrintNumber(format) | Instruction::FilePrintNumber(_, format, _) => {
+                    Some(format)
+                }
+                _ => None,
+            };
+            if format.is_some_and(|format| {
+                format.width > NumberFormat::MAX_WIDTH
+                    || format.decimals > NumberFormat::MAX_DECIMALS
+            }) {
+                return Err(format!("Number format out of range at {}", position));
+            }
+        }
+
+        // Execution must not run off the end of the code
+        match self.code.last() {
+            Some(Instruction::Halt | Instruction::Jump(_)) => (),
+            _ => return Err(String::from("Code does not end with halt")),
+        }
+
+        self.check_stack()
+    }
+
+    /// Follows every path through the code, so no instruction pops more than
+    /// is on the stack and paths that meet agree on its depth. GOSUB and
+    /// RETURN, like statements, only run with an empty stack, so the depth
+    /// after a GOSUB is the same whichever RETURN comes back to it.
+    fn check_stack(&self) -> Result<(), String> {
+        let mut depths: Vec<Option<usize>> = vec![None; self.code.len()];
+        let mut work = vec![(0, 0)];
+
+        while let Some((position, depth)) = work.pop() {
+            match depths[position] {
+                Some(known) if known == depth => continue,
+                Some(_) => return Err(format!("Stack depths differ at {}", position)),
+                None => depths[position] = Some(depth),
+            }
+
+            let instruction = self.code[position];
+            let (pops, pushes) = match instruction {
+                Instruction::Push(_) | Instruction::Load(_) | Instruction::Eof(_, _) => (0, 1),
+                Instruction::Unary(_) => (1, 1),
+                Instruction::Binary(_) => (2, 1),
+                Instruction::Call(function) => (function.arity(), 1),
+                Instruction::Store(_)
+                | Instruction::Randomize
+                | Instruction::JumpIfZero(_)
+                | Instruction::PrintNumber(_)
+                | Instruction::FilePrintNumber(_, _, _) => (1, 0),
+                _ => (0, 0),
+            };
+            if depth < pops {
+                return Err(format!("Stack underflow at {}", position));
+            }
+            let depth = depth - pops + pushes;
+
+            match instruction {
+                Instruction::Gosub(_, _) | Instruction::Return(_) if depth != 0 => {
+                    return Err(format!("Stack not empty at {}", position));
+                }
+                Instruction::Jump(target) => work.push((target as usize, depth)),
+                Instruction::JumpIfZero(target) | Instruction::Gosub(target, _) => {
+                    work.push((target as usize, depth));
+                    work.push((position + 1, depth));
+                }
+                Instruction::Return(_) | Instruction::Halt => (),
+                _ => work.push((position + 1, depth)),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn file_number(file: u32) -> bool {
+    (1..=FILES as u32).contains(&file)
+}
+
+fn index<T: PartialEq>(operators: &[T], operator: T) -> u8 {
+    operators
+        .iter()
+        .position(|other| *other == operator)
+        .unwrap() as u8
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], String> {
+        let end = self.position + count;
+        if end > self.bytes.len() {
+            return Err(String::from("Unexpected end of bytecode"));
+        }
+
+        let bytes = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn names(&mut self) -> Result<Vec<String>, String> {
+        let count = self.u32()?;
+        let mut names = Vec::new();
+
+        for _ in 0..count {
+            let length = self.u32()? as usize;
+            match String::from_utf8(self.take(length)?.to_vec()) {
+                Ok(name) => names.push(name),
+                Err(_) => return Err(String::from("Invalid UTF-8 in bytecode")),
+            }
+        }
+        Ok(names)
+    }
+}
+
+/// Disassembly, one instruction per line prefixed with its index.
+impl fmt::Display for Chunk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "; bytecode version {}", VERSION)?;
+        for (index, name) in self.variables.iter().enumerate() {
+            writeln!(f, "var v{} {}", index, name)?;
+        }
+        for (index, text) in self.strings.iter().enumerate() {
+            writeln!(f, "str s{} {:?}", index, text)?;
+        }
+
+        for (position, instruction) in self.code.iter().enumerate() {
+            write!(f, "{:4}  ", position)?;
+            match *instruction {
+                Instruction::Push(value) => writeln!(f, "push {:?}", value)?,
+                Instruction::Load(variable) => writeln!(
+                    f,
+                    "load v{} ; {}",
+                    variable, self.variables[variable as usize]
+                )?,
+                Instruction::Store(variable) => writeln!(
+                    f,
+                    "store v{} ; {}",
+                    variable, self.variables[variable as usize]
+                )?,
+                Instruction::Unary(operator) => writeln!(f, "{}", unary_name(operator))?,
+                Instruction::Binary(operator) => writeln!(f, "{}", binary_name(operator))?,
+                Instruction::Call(function) => {
+                    writeln!(f, "call {}", function.name().to_lowercase())?
+                }
+                Instruction::Randomize => writeln!(f, "randomize")?,
+                Instruction::Jump(target) => writeln!(f, "jump {}", target)?,
+                Instruction::JumpIfZero(target) => writeln!(f, "jz {}", target)?,
+                Instruction::Gosub(target, _) => writeln!(f, "gosub {}", target)?,
+                Instruction::Return(_) => writeln!(f, "return")?,
+                Instruction::PrintNumber(format) if format == NumberFormat::default() => {
+                    writeln!(f, "print")?
+                }
+                Instruction::PrintNumber(format) => {
+                    writeln!(f, "print using {:?}", format.picture())?
+                }
+                Instruction::PrintString(string) => writeln!(f, "print s{}", string)?,
+                Instruction::Input(variable, line) => writeln!(
+                    f,
+                    "input v{} ; {}, line {}",
+                    variable, self.variables[variable as usize], line
+                )?,
+                Instruction::Newline => writeln!(f, "newline")?,
+                Instruction::Open(file, path, mode, _) => writeln!(
+                    f,
+                    "open #{} s{} for {}",
+                    file,
+                    path,
+                    mode.keyword().to_lowercase()
+                )?,
+                Instruction::Close(file, _) => writeln!(f, "close #{}", file)?,
+                Instruction::FilePrintNumber(file, format, _)
+                    if format == NumberFormat::default() =>
+                {
+                    writeln!(f, "print #{}", file)?
+                }
+                Instruction::FilePrintNumber(file, format, _) => {
+                    writeln!(f, "print #{} using {:?}", file, format.picture())?
+                }
+                Instruction::FilePrintString(file, string, _) => {
+                    writeln!(f, "print #{} s{}", file, string)?
+                }
+                Instruction::FileNewline(file, _) => writeln!(f, "newline #{}", file)?,
+                Instruction::FileInput(file, variable, line) => writeln!(
+                    f,
+                    "input #{} v{} ; {}, line {}",
+                    file, variable, self.variables[variable as usize], line
+                )?,
+                Instruction::Eof(file, _) => writeln!(f, "eof #{}", file)?,
+                Instruction::Halt => writeln!(f, "halt")?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lex::Lexer;
+    use crate::parser::Parser;
+
+    fn compiled(source: &str) -> Chunk {
+        compile(&Parser::new(Lexer::new(format!("{}\n\0", source))).program())
+    }
+
+    #[test]
+    fn it_disassembles_loops() {
+        let chunk = compiled("LET a = 0\nWHILE a < 3 REPEAT\nPRINT -a\nLET a = a + 1\nENDWHILE");
+        assert_eq!(
+            chunk.to_string(),
+            "; bytecode version 9
+var v0 a
+   0  push 0.0
+   1  store v0 ; a
+   2  load v0 ; a
+   3  push 3.0
+   4  lt
+   5  jz 15
+   6  load v0 ; a
+   7  neg
+   8  print
+   9  newline
+  10  load v0 ; a
+  11  push 1.0
+  12  add
+  13  store v0 ; a
+  14  jump 2
+  15  halt
+"
+        );
+    }
+
+    #[test]
+    fn it_round_trips_through_bytes() {
+        let chunk = compiled(
+            "PRINT \"hi\"\nLABEL top\nINPUT x\nPRINT USING \"##.#\"; x\nIF x >= 1.5 THEN\nGOTO top\nENDIF",
+        );
+        assert_eq!(Chunk::from_bytes(&chunk.to_bytes()), Ok(chunk));
+    }
+
+    #[test]
+    fn it_round_trips_file_instructions() {
+        let chunk = compiled(
+            "OPEN \"data.txt\" FOR APPEND AS #3\nPRINT #3, USING \"#.#\"; 1, \"a\"\nCLOSE #3\nOPEN \"data.txt\" FOR INPUT AS #3\nWHILE EOF(3) == 0 REPEAT\nINPUT #3, x\nENDWHILE",
+        );
+        assert_eq!(Chunk::from_bytes(&chunk.to_bytes()), Ok(chunk));
+    }
+
+    #[test]
+    fn it_rejects_other_versions_and_bad_jumps() {
+        let mut bytes = compiled("PRINT 1").to_bytes();
+        bytes[4] = 1;
+        assert_eq!(
+            Chunk::from_bytes(&bytes),
+            Err(String::from("Unsupported bytecode version 1, expected 9"))
+        );
+
+        let chunk = Chunk {
+            code: vec![Instruction::Jump(5)],
+            ..Chunk::default()
+        };
+        assert!(Chunk::from_bytes(&chunk.to_bytes()).is_err());
+    }
+
+    #[test]
+    fn it_rejects_chunks_that_misuse_the_stack() {
+        let rejected = |code: Vec<Instruction>| {
+            let chunk = Chunk {
+                code,
+                ..Chunk::default()
+            };
+            Chunk::from_bytes(&chunk.to_bytes()).unwrap_err()
+        };
+
+        assert_eq!(
+            rejected(vec![
+                Instruction::Binary(BinaryOperator::Add),
+                Instruction::Halt
+            ]),
+            "Stack underflow at 0"
+        );
+        // The loop pushes one more value every time around
+        assert_eq!(
+            rejected(vec![Instruction::Push(1.0), Instruction::Jump(0)]),
+            "Stack depths differ at 0"
+        );
+        assert_eq!(
+            rejected(vec![
+                Instruction::Push(1.0),
+                Instruction::Gosub(2, 1),
+                Instruction::Halt
+            ]),
+            "Stack not empty at 1"
+        );
+        assert_eq!(
+            rejected(vec![
+                Instruction::Push(1.0),
+                Instruction::PrintNumber(NumberFormat {
+                    width: usize::MAX,
+                    decimals: 2
+                }),
+                Instruction::Halt
+            ]),
+            "Number format out of range at 1"
+        );
+    }
+
+    #[test]
+    fn it_accepts_every_compiled_program() {
+        for program in crate::testing::programs() {
+            let options = crate::Options::default();
+            let chunk = compile(&crate::front_end(program.to_str().unwrap(), &options));
+            assert!(
+                Chunk::from_bytes(&chunk.to_bytes()).is_ok(),
+                "{:?}",
+                program
+            );
+        }
+    }
+}
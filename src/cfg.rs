@@ -0,0 +1,376 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::{Program, Statement, StatementKind};
+
+/// Control-flow graph of basic blocks. IF and WHILE statements sit at the end
+/// of their block and stand for the evaluation of their condition; their first
+/// successor is taken when the condition holds, the second when it does not.
+/// A GOSUB ends its block with an edge to its label only, and a RETURN leads
+/// to the statement after every GOSUB, since any of them may be waiting.
+pub struct Cfg<'a> {
+    pub blocks: Vec<Block<'a>>,
+    pub entry: usize,
+    pub exit: usize,
+    /// Blocks ending in a GOSUB, each with the block its RETURN comes back to.
+    pub resumes: Vec<(usize, usize)>,
+}
+
+#[derive(Default)]
+pub struct Block<'a> {
+    pub statements: Vec<&'a Statement>,
+    pub successors: Vec<usize>,
+}
+
+impl<'a> Cfg<'a> {
+    pub fn build(program: &'a Program) -> Self {
+        let mut builder = Builder {
+            blocks: vec![Block::default()],
+            current: Some(0),
+            labels: HashMap::new(),
+            gotos: Vec::new(),
+            breaks: Vec::new(),
+            resumes: Vec::new(),
+            returns: Vec::new(),
+        };
+
+        builder.statements(&program.statements);
+
+        let exit = builder.add_block();
+        if let Some(current) = builder.current {
+            builder.edge(current, exit);
+        }
+
+        for (block, label) in std::mem::take(&mut builder.gotos) {
+            if let Some(&target) = builder.labels.get(label) {
+                builder.edge(block, target);
+            }
+        }
+        for block in std::mem::take(&mut builder.returns) {
+            for (_, resume) in builder.resumes.clone() {
+                builder.edge(block, resume);
+            }
+        }
+
+        compact(Cfg {
+            blocks: builder.blocks,
+            entry: 0,
+            exit,
+            resumes: builder.resumes,
+        })
+    }
+
+    pub fn reachable(&self) -> HashSet<usize> {
+        let mut seen = HashSet::new();
+        let mut stack = vec![self.entry];
+
+        while let Some(block) = stack.pop() {
+            if seen.insert(block) {
+                stack.extend(&self.blocks[block].successors);
+            }
+        }
+
+        seen
+    }
+
+    /// Statements that can never run, sorted in source order.
+    pub fn unreachable_statements(&self) -> Vec<&'a Statement> {
+        let reachable = self.reachable();
+        let mut statements: Vec<&'a Statement> = self
+            .blocks
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| !reachable.contains(index))
+            .flat_map(|(_, block)| block.statements.iter().copied())
+            .collect();
+
+        statements.sort_by_key(|statement| statement.id);
+        statements
+    }
+
+    /// Renders the graph in Graphviz DOT format.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph cfg {\n    node [shape=box, fontname=monospace];\n");
+
+        for (index, block) in self.blocks.iter().enumerate() {
+            let mut label = if index == self.entry {
+                String::from("entry\\l")
+            } else if index == self.exit {
+                String::from("exit\\l")
+            } else {
+                format!("B{}\\l", index)
+            };
+
+            for statement in &block.statements {
+                let text = format!("{}: {}", statement.line, statement);
+                label.push_str(&text.replace('\\', "\\\\").replace('"', "\\\""));
+                label.push_str("\\l");
+            }
+
+            dot.push_str(&format!("    b{} [label=\"{}\"];\n", index, label));
+        }
+
+        for (index, block) in self.blocks.iter().enumerate() {
+            let branches = matches!(
+                block.statements.last().map(|statement| &statement.kind),
+                Some(StatementKind::If { .. } | StatementKind::While { .. })
+            );
+            for (position, successor) in block.successors.iter().enumerate() {
+                let attributes = match (branches, position) {
+                    (true, 0) => " [label=\"true\"]",
+                    (true, _) => " [label=\"false\"]",
+                    _ => "",
+                };
+                dot.push_str(&format!(
+                    "    b{} -> b{}{};\n",
+                    index, successor, attributes
+                ));
+            }
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+struct Builder<'a> {
+    blocks: Vec<Block<'a>>,
+    /// Block that falls through to the next statement, `None` after a GOTO.
+    current: Option<usize>,
+    labels: HashMap<&'a str, usize>,
+    gotos: Vec<(usize, &'a str)>,
+    /// Blocks ending in a BREAK, one list per enclosing WHILE.
+    breaks: Vec<Vec<usize>>,
+    /// Blocks ending in a GOSUB, with the block starting right after it.
+    resumes: Vec<(usize, usize)>,
+    /// Blocks ending in a RETURN.
+    returns: Vec<usize>,
+}
+
+impl<'a> Builder<'a> {
+    fn add_block(&mut self) -> usize {
+        self.blocks.push(Block::default());
+        self.blocks.len() - 1
+    }
+
+    fn edge(&mut self, from: usize, to: usize) {
+        if !self.blocks[from].successors.contains(&to) {
+            self.blocks[from].successors.push(to);
+        }
+    }
+
+    /// Returns a block for a statement that can be jumped to, starting a new one
+    /// unless the current block is still empty.
+    fn leader(&mut self) -> usize {
+        match self.current {
+            Some(current) if self.blocks[current].statements.is_empty() => current,
+            current => {
+                let block = self.add_block();
+                if let Some(current) = current {
+                    self.edge(current, block);
+                }
+                self.current = Some(block);
+                block
+            }
+        }
+    }
+
+    fn push(&mut self, statement: &'a Statement) -> usize {
+        let block = match self.current {
+            Some(current) => current,
+            None => {
+                let block = self.add_block();
+                self.current = Some(block);
+                block
+            }
+        };
+
+        self.blocks[block].statements.push(statement);
+        block
+    }
+
+    fn statements(&mut self, statements: &'a [Statement]) {
+        for statement in statements {
+            self.statement(statement);
+        }
+    }
+
+    fn statement(&mut self, statement: &'a Statement) {
+        match &statement.kind {
+            StatementKind::Label(name) => {
+                let block = self.leader();
+                self.labels.insert(name, block);
+                self.push(statement);
+            }
+            StatementKind::Goto(name) => {
+                let block = self.push(statement);
+                self.gotos.push((block, name));
+                self.current = None;
+            }
+            StatementKind::Gosub(name) => {
+                let block = self.push(statement);
+                self.gotos.push((block, name));
+
+                let resume = self.add_block();
+                self.resumes.push((block, resume));
+                self.current = Some(resume);
+            }
+            StatementKind::Return => {
+                let block = self.push(statement);
+                self.returns.push(block);
+                self.current = None;
+            }
+            StatementKind::If { body, .. } => {
+                let condition = self.push(statement);
+                let then = self.add_block();
+                self.edge(condition, then);
+
+                self.current = Some(then);
+                self.statements(body);
+
+                let after = self.add_block();
+                if let Some(current) = self.current {
+                    self.edge(current, after);
+                }
+                self.edge(condition, after);
+                self.current = Some(after);
+            }
+            StatementKind::While { body, .. } => {
+                let header = self.leader();
+                self.push(statement);
+
+                let start = self.add_block();
+                self.edge(header, start);
+
+                self.current = Some(start);
+                self.breaks.push(Vec::new());
+                self.statements(body);
+                if let Some(current) = self.current {
+                    self.edge(current, header);
+                }
+
+                let after = self.add_block();
+                self.edge(header, after);
+                for block in self.breaks.pop().unwrap() {
+                    self.edge(block, after);
+                }
+                self.current = Some(after);
+            }
+            StatementKind::Break => {
+                let block = self.push(statement);
+                self.breaks.last_mut().unwrap().push(block);
+                self.current = None;
+            }
+            _ => {
+                self.push(statement);
+            }
+        }
+    }
+}
+
+/// Removes the empty blocks left behind by IF/WHILE bodies, redirecting their
+/// predecessors straight to where they lead.
+fn compact(cfg: Cfg) -> Cfg {
+    let forward = |mut block: usize| {
+        let mut steps = 0;
+        while block != cfg.entry
+            && block != cfg.exit
+            && cfg.blocks[block].statements.is_empty()
+            && cfg.blocks[block].successors.len() == 1
+            && steps < cfg.blocks.len()
+        {
+            block = cfg.blocks[block].successors[0];
+            steps += 1;
+        }
+        block
+    };
+
+    let keep: Vec<bool> = (0..cfg.blocks.len())
+        .map(|block| block == cfg.entry || block == cfg.exit || forward(block) == block)
+        .collect();
+
+    let mut numbering = vec![0; cfg.blocks.len()];
+    let mut next = 0;
+    for (block, &kept) in keep.iter().enumerate() {
+        if kept {
+            numbering[block] = next;
+            next += 1;
+        }
+    }
+
+    let mut blocks = Vec::new();
+    for (block, contents) in cfg.blocks.iter().enumerate() {
+        if !keep[block] {
+            continue;
+        }
+
+        let mut successors = Vec::new();
+        for &successor in &contents.successors {
+            let successor = numbering[forward(successor)];
+            if !successors.contains(&successor) {
+                successors.push(successor);
+            }
+        }
+
+        blocks.push(Block {
+            statements: contents.statements.clone(),
+            successors,
+        });
+    }
+
+    Cfg {
+        blocks,
+        entry: numbering[cfg.entry],
+        exit: numbering[cfg.exit],
+        resumes: cfg
+            .resumes
+            .iter()
+            .map(|&(gosub, resume)| (numbering[gosub], numbering[forward(resume)]))
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lex::Lexer;
+    use crate::parser::Parser;
+
+    fn parse(source: &str) -> Program {
+        Parser::new(Lexer::new(format!("{}\n\0", source))).program()
+    }
+
+    #[test]
+    fn it_finds_statements_after_goto() {
+        let program = parse("LABEL loop\nPRINT \"hi\"\nGOTO loop\nPRINT \"a\"\nPRINT \"b\"");
+        let cfg = Cfg::build(&program);
+        let lines: Vec<usize> = cfg
+            .unreachable_statements()
+            .iter()
+            .map(|statement| statement.line)
+            .collect();
+
+        assert_eq!(lines, vec![4, 5]);
+    }
+
+    #[test]
+    fn it_keeps_loops_reachable() {
+        let program = parse("LET a = 0\nWHILE a < 3 REPEAT\nLET a = a + 1\nENDWHILE\nPRINT a");
+        let cfg = Cfg::build(&program);
+
+        assert!(cfg.unreachable_statements().is_empty());
+        assert!(cfg.reachable().contains(&cfg.exit));
+    }
+
+    #[test]
+    fn it_returns_only_to_statements_after_gosub() {
+        let program = parse("GOSUB sub\nPRINT \"a\"\nLABEL sub\nRETURN\nPRINT \"b\"");
+        let cfg = Cfg::build(&program);
+        let lines: Vec<usize> = cfg
+            .unreachable_statements()
+            .iter()
+            .map(|statement| statement.line)
+            .collect();
+
+        assert_eq!(lines, vec![5]);
+    }
+}
@@ -0,0 +1,214 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::{Expression, Statement, StatementKind};
+use crate::cfg::Cfg;
+
+/// Known constants right before a statement runs, keyed by variable name.
+pub type Constants = HashMap<String, f64>;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Value {
+    Constant(f64),
+    Varying,
+}
+
+type State = HashMap<String, Value>;
+
+/// Forward constant propagation. Every variable starts at zero, LET assigns
+/// what its expression evaluates to and INPUT makes a variable varying. Branches
+/// with a constant condition only pass their state down the edge they take.
+///
+/// Returns the constants in effect before each statement, keyed by statement
+/// id. Statements that can never run have no entry.
+pub fn constants(cfg: &Cfg, variables: &[&str]) -> HashMap<usize, Constants> {
+    let mut states: Vec<Option<State>> = vec![None; cfg.blocks.len()];
+    states[cfg.entry] = Some(
+        variables
+            .iter()
+            .map(|name| (name.to_string(), Value::Constant(0.0)))
+            .collect(),
+    );
+
+    let mut worklist = vec![cfg.entry];
+    while let Some(block) = worklist.pop() {
+        let mut state = states[block].clone().unwrap();
+        let mut taken = None;
+
+        for statement in &cfg.blocks[block].statements {
+            if let StatementKind::If { condition, .. } | StatementKind::While { condition, .. } =
+                &statement.kind
+            {
+                taken = evaluate(condition, &state).map(|value| value != 0.0);
+            }
+            assign(statement, &mut state);
+        }
+
+        let successors = &cfg.blocks[block].successors;
+        for (position, &successor) in successors.iter().enumerate() {
+            // First successor is the true edge, second the false edge
+            if successors.len() == 2 && taken.is_some_and(|taken| taken != (position == 0)) {
+                continue;
+            }
+
+            let merged = match &states[successor] {
+                Some(previous) => meet(previous, &state),
+                None => state.clone(),
+            };
+
+            if states[successor].as_ref() != Some(&merged) {
+                states[successor] = Some(merged);
+                worklist.push(successor);
+            }
+        }
+    }
+
+    let mut constants = HashMap::new();
+    for (block, state) in states.into_iter().enumerate() {
+        let Some(mut state) = state else {
+            continue;
+        };
+
+        for statement in &cfg.blocks[block].statements {
+            let known = state
+                .iter()
+                .filter_map(|(name, value)| match value {
+                    Value::Constant(value) => Some((name.clone(), *value)),
+                    Value::Varying => None,
+                })
+                .collect();
+            constants.insert(statement.id, known);
+            assign(statement, &mut state);
+        }
+    }
+
+    constants
+}
+
+fn assign(statement: &Statement, state: &mut State) {
+    match &statement.kind {
+        StatementKind::Let(name, expression) => {
+            let value = match evaluate(expression, state) {
+                Some(value) => Value::Constant(value),
+                None => Value::Varying,
+            };
+            state.insert(name.clone(), value);
+        }
+        StatementKind::Input(_, name) => {
+            state.insert(name.clone(), Value::Varying);
+        }
+        _ => (),
+    }
+}
+
+fn meet(left: &State, right: &State) -> State {
+    let mut merged = left.clone();
+
+    for (name, value) in right {
+        let same = match (merged.get(name), value) {
+            // Compare bits so 0 and -0, which print differently, stay apart
+            (Some(Value::Constant(a)), Value::Constant(b)) => a.to_bits() == b.to_bits(),
+            _ => false,
+        };
+
+        if !same {
+            merged.insert(name.clone(), Value::Varying);
+        }
+    }
+
+    merged
+}
+
+/// Evaluates the expression if every variable it reads is constant. Division by
+/// zero and overflow are left for runtime.
+fn evaluate(expression: &Expression, state: &State) -> Option<f64> {
+    let value = match expression {
+        Expression::Number(value) => *value,
+        Expression::Variable(name) => match state.get(name)? {
+            Value::Constant(value) => *value,
+            Value::Varying => return None,
+        },
+        Expression::Unary(operator, operand) => operator.evaluate(evaluate(operand, state)?),
+        Expression::Binary(operator, left, right) => {
+            let left = evaluate(left, state)?;
+            let right = evaluate(right, state)?;
+
+            if operator.divides() && right == 0.0 {
+                return None;
+            }
+            operator.evaluate(left, right)
+        }
+        Expression::Call(function, arguments) => {
+            let arguments: Option<Vec<f64>> = arguments
+                .iter()
+                .map(|argument| evaluate(argument, state))
+                .collect();
+            function.evaluate(&arguments?)?
+        }
+        Expression::Eof { .. } => return None,
+    };
+
+    value.is_finite().then_some(value)
+}
+
+/// Backward liveness. Returns the ids of LET statements whose value is never
+/// read before being overwritten or the program ending. INPUT is never dead,
+/// it still consumes a value from stdin, and neither is a LET calling RND.
+pub fn dead_stores<'a>(cfg: &Cfg<'a>) -> HashSet<usize> {
+    let mut live_in: Vec<HashSet<&'a str>> = vec![HashSet::new(); cfg.blocks.len()];
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+
+        for block in (0..cfg.blocks.len()).rev() {
+            let mut live = live_out(cfg, &live_in, block);
+            for statement in cfg.blocks[block].statements.iter().rev() {
+                transfer(statement, &mut live);
+            }
+
+            if live != live_in[block] {
+                live_in[block] = live;
+                changed = true;
+            }
+        }
+    }
+
+    let mut dead = HashSet::new();
+    for block in 0..cfg.blocks.len() {
+        let mut live = live_out(cfg, &live_in, block);
+
+        for statement in cfg.blocks[block].statements.iter().rev() {
+            if let StatementKind::Let(name, expression) = &statement.kind {
+                // A store that calls RND still advances the generator
+                if !live.contains(name.as_str()) && !expression.has_side_effects() {
+                    dead.insert(statement.id);
+                    continue;
+                }
+            }
+            transfer(statement, &mut live);
+        }
+    }
+
+    dead
+}
+
+fn live_out<'a>(cfg: &Cfg<'a>, live_in: &[HashSet<&'a str>], block: usize) -> HashSet<&'a str> {
+    cfg.blocks[block]
+        .successors
+        .iter()
+        .flat_map(|&successor| live_in[successor].iter().copied())
+        .collect()
+}
+
+fn transfer<'a>(statement: &'a Statement, live: &mut HashSet<&'a str>) {
+    match &statement.kind {
+        StatementKind::Let(name, _) | StatementKind::Input(_, name) => {
+            live.remove(name.as_str());
+        }
+        _ => (),
+    }
+
+    for expression in statement.expressions() {
+        live.extend(expression.variables());
+    }
+}
@@ -0,0 +1,86 @@
+/// Which flavour of the language a program is checked against. `Extended`
+/// is everything this compiler accepts. `Tiny` keeps to classic Tiny BASIC:
+/// single-letter variables in any case, line numbers instead of LABEL, no
+/// WHILE loops and no PRINT USING. Both have GOSUB and RETURN.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Dialect {
+    Tiny,
+    #[default]
+    Extended,
+}
+
+/// Syntax that only some dialects accept.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Feature {
+    /// Variable names longer than one letter.
+    LongNames,
+    /// `$` and `%` at the end of a name.
+    TypeSuffixes,
+    /// LABEL, and GOTO to a name rather than a line number.
+    Labels,
+    /// WHILE, REPEAT, ENDWHILE and BREAK.
+    WhileLoops,
+    PrintUsing,
+    /// Backslash escapes in strings.
+    StringEscapes,
+    Includes,
+    /// Built-in functions other than ABS and RND, and RANDOMIZE.
+    MathFunctions,
+    /// OPEN, CLOSE, `PRINT #n`, `INPUT #n` and EOF.
+    FileIo,
+    /// `MOD` or `%`, `^`, `\\` and NOT.
+    Operators,
+}
+
+impl Dialect {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Dialect::Tiny => "tiny",
+            Dialect::Extended => "extended",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Dialect> {
+        [Dialect::Tiny, Dialect::Extended]
+            .into_iter()
+            .find(|dialect| dialect.name() == name)
+    }
+
+    /// Whether the syntax listed in `Feature` is accepted. Every feature is an
+    /// extension, so `Tiny` has none of them.
+    pub fn allows_extensions(&self) -> bool {
+        *self == Dialect::Extended
+    }
+
+    /// Classic BASIC does not tell `A` and `a` apart.
+    pub fn case_insensitive(&self) -> bool {
+        *self == Dialect::Tiny
+    }
+
+    /// The diagnostic for using a feature outside of this dialect.
+    pub fn unavailable(&self, feature: Feature, location: &str) -> String {
+        format!(
+            "Feature {} not available in dialect {} at {}",
+            feature.description(),
+            self.name(),
+            location
+        )
+    }
+}
+
+impl Feature {
+    pub fn description(&self) -> &'static str {
+        match self {
+            Feature::LongNames => "multi-letter variable names",
+            Feature::TypeSuffixes => "type suffixes",
+            Feature::Labels => "LABEL",
+            Feature::WhileLoops => "WHILE loops",
+            Feature::PrintUsing => "PRINT USING",
+            Feature::StringEscapes => "string escapes",
+            Feature::Includes => "INCLUDE",
+            Feature::MathFunctions => "math functions",
+            Feature::FileIo => "file I/O",
+            Feature::Operators => "MOD, ^, \\ and NOT operators",
+        }
+    }
+}
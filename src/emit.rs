@@ -1,9 +1,22 @@
+use std::path::Path;
 use std::{fs::File, io, io::Write};
 
+use crate::ast::{
+    BinaryOperator, Expression, Function, NumberFormat, PrintItem, Program, Statement,
+    StatementKind, UnaryOperator,
+};
+
+/// Printing, input and error helpers every generated program includes.
+pub const RUNTIME: &str = include_str!("../runtime/tb_runtime.h");
+
 pub struct Emitter {
     file_path: String,
     header: String,
     code: String,
+    /// GOSUBs in the program, each resumes at its own `r_` label.
+    gosubs: usize,
+    /// GOSUBs emitted so far.
+    resumes: usize,
 }
 
 impl Emitter {
@@ -12,6 +25,8 @@ impl Emitter {
             file_path,
             header: String::from(""),
             code: String::from(""),
+            gosubs: 0,
+            resumes: 0,
         }
     }
 
@@ -29,11 +44,337 @@ impl Emitter {
         self.header.push('\n');
     }
 
+    /// Writes the C file, with the runtime header next to it.
     pub fn write_file(&self) -> io::Result<()> {
+        let runtime = Path::new(&self.file_path).with_file_name("tb_runtime.h");
+        std::fs::write(runtime, RUNTIME)?;
+
         let mut file = File::create(&self.file_path)?;
 
-        file.write(self.header.as_bytes())?;
-        file.write(self.code.as_bytes())?;
+        file.write_all(self.header.as_bytes())?;
+        file.write_all(self.code.as_bytes())?;
         Ok(())
     }
+
+    /// Emits the whole program as a single C `main` function.
+    pub fn program(&mut self, program: &Program) {
+        // Initial lines for program
+        self.header_line("#include \"tb_runtime.h\"");
+        self.header_line("int main(void){");
+
+        // Variables start at zero so every backend agrees on reads before assignment
+        for name in program.variables() {
+            let decl = format!("double {} = 0;", variable(name));
+            self.header_line(&decl);
+        }
+        program.walk(&mut |statement| {
+            if let StatementKind::Gosub(_) = statement.kind {
+                self.gosubs += 1;
+            }
+        });
+
+        self.statements(&program.statements);
+
+        // Close file of C
+        self.emit_line("return 0;");
+        self.emit_line("}");
+    }
+
+    fn statements(&mut self, statements: &[Statement]) {
+        for statement in statements {
+            self.statement(statement);
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match &statement.kind {
+            StatementKind::Print {
+                file: Some(file),
+                items,
+                format,
+                newline,
+            } => {
+                for item in items {
+                    let line = match item {
+                        PrintItem::Text(text) => format!(
+                            "tb_fwrite_str({}, {}, {});",
+                            file,
+                            c_string(text),
+                            statement.line
+                        ),
+                        PrintItem::Tab => format!(
+                            "tb_fwrite_str({}, {}, {});",
+                            file,
+                            c_string("\t"),
+                            statement.line
+                        ),
+                        PrintItem::Number(expression) => format!(
+                            "tb_fwrite_num({}, {}, {}, {}, {});",
+                            file,
+                            c_expression(expression),
+                            format.width,
+                            format.decimals,
+                            statement.line
+                        ),
+                    };
+                    self.emit_line(&line);
+                }
+                if *newline {
+                    let line = format!("tb_fwrite_str({}, \"\\n\", {});", file, statement.line);
+                    self.emit_line(&line);
+                }
+            }
+            StatementKind::Print {
+                file: None,
+                items,
+                format,
+                newline,
+            } => {
+                if items.is_empty() && *newline {
+                    self.emit_line("tb_print_str(\"\");");
+                }
+
+                // The tb_print functions end the line, tb_write ones leave it open
+                for (index, item) in items.iter().enumerate() {
+                    let ends = *newline && index == items.len() - 1;
+                    let prefix = if ends { "tb_print" } else { "tb_write" };
+
+                    let line = match item {
+                        PrintItem::Text(text) => format!("{}_str({});", prefix, c_string(text)),
+                        PrintItem::Tab => format!("{}_str({});", prefix, c_string("\t")),
+                        PrintItem::Number(expression) if *format == NumberFormat::default() => {
+                            format!("{}_num({});", prefix, c_expression(expression))
+                        }
+                        PrintItem::Number(expression) => format!(
+                            "{}_using({}, {}, {});",
+                            prefix,
+                            c_expression(expression),
+                            format.width,
+                            format.decimals
+                        ),
+                    };
+                    self.emit_line(&line);
+                }
+            }
+            StatementKind::If { condition, body } => {
+                self.emit("if(");
+                self.expression(condition);
+                self.emit_line("){");
+                self.statements(body);
+                self.emit_line("}");
+            }
+            StatementKind::While { condition, body } => {
+                self.emit("while(");
+                self.expression(condition);
+                self.emit_line("){");
+                self.statements(body);
+                self.emit_line("}");
+            }
+            StatementKind::Label(name) => {
+                // The empty statement keeps a label at the end of a block valid C
+                let line = format!("{}:;", label(name));
+                self.emit_line(&line);
+            }
+            StatementKind::Goto(name) => {
+                let line = format!("goto {};", label(name));
+                self.emit_line(&line);
+            }
+            StatementKind::Gosub(name) => {
+                let line = format!(
+                    "tb_gosub({}, {});\ngoto {};\nr_{}:;",
+                    self.resumes,
+                    statement.line,
+                    label(name),
+                    self.resumes
+                );
+                self.emit_line(&line);
+                self.resumes += 1;
+            }
+            // Goes back through a switch, C has no computed goto
+            StatementKind::Return => {
+                let line = format!("switch(tb_return({})){{", statement.line);
+                self.emit_line(&line);
+                for resume in 0..self.gosubs {
+                    let line = format!("case {}: goto r_{};", resume, resume);
+                    self.emit_line(&line);
+                }
+                self.emit_line("}");
+            }
+            StatementKind::Let(name, expression) => {
+                let asgn = format!("{} = ", variable(name));
+                self.emit(&asgn);
+                self.expression(expression);
+                self.emit_line(";");
+            }
+            StatementKind::Input(None, name) => {
+                let line = format!("tb_input_num(&{}, {});", variable(name), statement.line);
+                self.emit_line(&line);
+            }
+            StatementKind::Input(Some(file), name) => {
+                let line = format!(
+                    "tb_finput_num({}, &{}, {});",
+                    file,
+                    variable(name),
+                    statement.line
+                );
+                self.emit_line(&line);
+            }
+            StatementKind::Break => self.emit_line("break;"),
+            StatementKind::Randomize(seed) => {
+                let line = format!("tb_randomize({});", c_expression(seed));
+                self.emit_line(&line);
+            }
+            StatementKind::Open { path, mode, file } => {
+                let line = format!(
+                    "tb_open({}, {}, \"{}\", {});",
+                    file,
+                    c_string(path),
+                    mode.c_mode(),
+                    statement.line
+                );
+                self.emit_line(&line);
+            }
+            StatementKind::Close(file) => {
+                let line = format!("tb_close({}, {});", file, statement.line);
+                self.emit_line(&line);
+            }
+        }
+    }
+
+    fn expression(&mut self, expression: &Expression) {
+        let text = c_expression(expression);
+        self.emit(&text);
+    }
+}
+
+/// Names a BASIC variable in C. Every user symbol gets a prefix, so none can
+/// collide with a C keyword, a libc function or the `tb_` runtime.
+fn variable(name: &str) -> String {
+    symbol("v", name)
+}
+
+/// Names a BASIC label in C, prefixed for the same reason as variables.
+fn label(name: &str) -> String {
+    symbol("l", name)
+}
+
+/// C has no `$` or `%` in names, so a type suffix moves into the prefix:
+/// `a`, `a$` and `a%` become `v_a`, `vs_a` and `vi_a`.
+fn symbol(prefix: &str, name: &str) -> String {
+    match name.chars().last() {
+        Some('$') => format!("{}s_{}", prefix, &name[..name.len() - 1]),
+        Some('%') => format!("{}i_{}", prefix, &name[..name.len() - 1]),
+        _ => format!("{}_{}", prefix, name),
+    }
+}
+
+/// Quotes text as a C string literal. Bytes outside printable ASCII become
+/// octal escapes, so the literal holds the same UTF-8 under any compiler. The
+/// runtime prints it with `puts` or `fputs`, never as a format string.
+fn c_string(text: &str) -> String {
+    let mut literal = String::from("\"");
+    let mut previous = 0;
+
+    for byte in text.bytes() {
+        match byte {
+            b'"' | b'\\' => {
+                literal.push('\\');
+                literal.push(byte as char);
+            }
+            b'\n' => literal.push_str("\\n"),
+            b'\t' => literal.push_str("\\t"),
+            // Keeps "??" from starting a trigraph
+            b'?' if previous == b'?' => literal.push_str("\\?"),
+            0x20..=0x7e => literal.push(byte as char),
+            _ => literal.push_str(&format!("\\{:03o}", byte)),
+        }
+        previous = byte;
+    }
+
+    literal.push('"');
+    literal
+}
+
+/// Renders an expression as C, adding parentheses only where C precedence
+/// would otherwise regroup the tree.
+fn c_expression(expression: &Expression) -> String {
+    match expression {
+        // Debug formatting always keeps a decimal point, so C never does integer division
+        Expression::Number(value) => format!("{:?}", value),
+        Expression::Variable(name) => variable(name),
+        // `!` gives an int, so it is cast back before any division sees it
+        Expression::Unary(UnaryOperator::Not, operand) => {
+            format!("(double)!{}", c_operand(operand, u8::MAX))
+        }
+        Expression::Unary(operator, operand) => {
+            format!(
+                "{}{}",
+                operator.symbol(),
+                signed(c_operand(operand, u8::MAX))
+            )
+        }
+        Expression::Binary(BinaryOperator::IntegerDivide, left, right) => {
+            let precedence = BinaryOperator::Divide.precedence();
+            format!(
+                "trunc({}/{})",
+                c_operand(left, precedence),
+                c_operand(right, precedence + 1)
+            )
+        }
+        Expression::Binary(BinaryOperator::Modulo, left, right) => {
+            format!("fmod({}, {})", c_expression(left), c_expression(right))
+        }
+        Expression::Binary(BinaryOperator::Power, left, right) => {
+            format!("pow({}, {})", c_expression(left), c_expression(right))
+        }
+        Expression::Binary(operator, left, right) => {
+            let precedence = operator.precedence();
+            format!(
+                "{}{}{}",
+                c_operand(left, precedence),
+                operator.symbol(),
+                // Operators are left associative, so an equal-precedence right side needs grouping
+                signed(c_operand(right, precedence + 1))
+            )
+        }
+        Expression::Call(function, arguments) => {
+            let arguments: Vec<String> = arguments.iter().map(c_expression).collect();
+            format!("{}({})", c_function(*function), arguments.join(", "))
+        }
+        Expression::Eof { file, line } => format!("tb_eof({}, {})", file, line),
+    }
+}
+
+/// Spaces an operand that starts with a sign away from the operator before it,
+/// so `- -x` never becomes the decrement `--x`.
+fn signed(operand: String) -> String {
+    if operand.starts_with(['-', '+']) {
+        format!(" {}", operand)
+    } else {
+        operand
+    }
+}
+
+/// The `math.h` or runtime function behind a built-in.
+fn c_function(function: Function) -> &'static str {
+    match function {
+        Function::Sgn => "tb_sgn",
+        Function::Rnd => "tb_rnd",
+        _ => function.libm().unwrap(),
+    }
+}
+
+fn c_operand(expression: &Expression, min_precedence: u8) -> String {
+    match expression {
+        // Written as calls, which need no grouping
+        Expression::Binary(
+            BinaryOperator::IntegerDivide | BinaryOperator::Modulo | BinaryOperator::Power,
+            _,
+            _,
+        ) => c_expression(expression),
+        Expression::Binary(operator, _, _) if operator.precedence() < min_precedence => {
+            format!("({})", c_expression(expression))
+        }
+        _ => c_expression(expression),
+    }
 }
@@ -0,0 +1,929 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::ast::{
+    BinaryOperator, Expression, FileMode, Function, NumberFormat, PrintItem, Program, Statement,
+    StatementKind, UnaryOperator,
+};
+
+/// Three-address code for a whole program, shared by the backends that do not
+/// work from the syntax tree. Variables live in memory slots that start at zero
+/// and are only touched through `Load`, `Store` and `Input`. Everything else
+/// works on virtual registers, each assigned once and only read inside the
+/// block that assigns it.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Module {
+    pub variables: Vec<String>,
+    pub strings: Vec<String>,
+    pub blocks: Vec<Block>,
+    pub registers: usize,
+}
+
+pub type BlockId = usize;
+pub type Register = usize;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Block {
+    /// Source label the block starts at, if any. Only used for readability.
+    pub label: Option<String>,
+    pub instructions: Vec<Instruction>,
+    pub terminator: Terminator,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operand {
+    Register(Register),
+    Constant(f64),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Instruction {
+    Load {
+        dest: Register,
+        variable: usize,
+    },
+    Store {
+        variable: usize,
+        value: Operand,
+    },
+    Unary {
+        dest: Register,
+        operator: UnaryOperator,
+        operand: Operand,
+    },
+    Binary {
+        dest: Register,
+        operator: BinaryOperator,
+        left: Operand,
+        right: Operand,
+    },
+    /// Calls a built-in function. RND also advances the random generator, so
+    /// calls are never dropped or reordered.
+    Call {
+        dest: Register,
+        function: Function,
+        arguments: Vec<Operand>,
+    },
+    /// Seeds the random generator.
+    Randomize(Operand),
+    /// Prints without ending the line, PRINT ends with `PrintNewline` unless
+    /// its items ended with a separator.
+    PrintNumber(Operand, NumberFormat),
+    PrintString(usize),
+    PrintNewline,
+    /// Reads a number into the variable, stopping the program with an error
+    /// naming the line when input has run out.
+    Input {
+        variable: usize,
+        line: usize,
+    },
+    /// File instructions take the file number as written in the source. Each
+    /// one stops the program with an error naming the line when the file is not
+    /// open the right way.
+    Open {
+        file: usize,
+        path: usize,
+        mode: FileMode,
+        line: usize,
+    },
+    Close {
+        file: usize,
+        line: usize,
+    },
+    FilePrintNumber {
+        file: usize,
+        value: Operand,
+        format: NumberFormat,
+        line: usize,
+    },
+    FilePrintString {
+        file: usize,
+        string: usize,
+        line: usize,
+    },
+    FilePrintNewline {
+        file: usize,
+        line: usize,
+    },
+    FileInput {
+        file: usize,
+        variable: usize,
+        line: usize,
+    },
+    /// 1 when only whitespace is left in an input file, 0 otherwise.
+    Eof {
+        dest: Register,
+        file: usize,
+        line: usize,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Terminator {
+    Jump(BlockId),
+    /// Goes to `then` when the condition is non-zero.
+    Branch {
+        condition: Operand,
+        then: BlockId,
+        otherwise: BlockId,
+    },
+    /// Goes to `target`, keeping `resume` for the next `Resume`. Stops the
+    /// program with an error naming the line when GOSUBs nest too deeply.
+    Gosub {
+        target: BlockId,
+        resume: BlockId,
+        line: usize,
+    },
+    /// Goes back to the `resume` block of the latest `Gosub` still waiting,
+    /// stopping the program with an error naming the line when there is none.
+    Resume {
+        line: usize,
+    },
+    Return,
+}
+
+/// Runtime errors for files as printf formats after the line number, worded
+/// like the C runtime's. All but `cannot_open` take the file number.
+pub const FILE_ERRORS: [(&str, &str); 6] = [
+    ("not_open", "file #%d is not open"),
+    ("not_input", "file #%d is not open for input"),
+    ("not_output", "file #%d is not open for output"),
+    ("already_open", "file #%d is already open"),
+    ("cannot_open", "cannot open \"%s\""),
+    ("end_of_file", "end of file #%d"),
+];
+
+impl Module {
+    /// The blocks a `Resume` can go back to, one per `Gosub`.
+    pub fn resumes(&self) -> Vec<BlockId> {
+        self.blocks
+            .iter()
+            .filter_map(|block| match block.terminator {
+                Terminator::Gosub { resume, .. } => Some(resume),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Whether any block ends in a GOSUB or RETURN, so backends can leave out
+    /// their stack of resume blocks otherwise.
+    pub fn uses_gosub(&self) -> bool {
+        self.blocks.iter().any(|block| {
+            matches!(
+                block.terminator,
+                Terminator::Gosub { .. } | Terminator::Resume { .. }
+            )
+        })
+    }
+
+    /// Whether any instruction works on files, so backends can leave out their
+    /// file support otherwise.
+    pub fn uses_files(&self) -> bool {
+        self.blocks.iter().any(|block| {
+            block.instructions.iter().any(|instruction| {
+                matches!(
+                    instruction,
+                    Instruction::Open { .. }
+                        | Instruction::Close { .. }
+                        | Instruction::FilePrintNumber { .. }
+                        | Instruction::FilePrintString { .. }
+                        | Instruction::FilePrintNewline { .. }
+                        | Instruction::FileInput { .. }
+                        | Instruction::Eof { .. }
+                )
+            })
+        })
+    }
+}
+
+impl Instruction {
+    pub fn dest(&self) -> Option<Register> {
+        match self {
+            Instruction::Load { dest, .. }
+            | Instruction::Unary { dest, .. }
+            | Instruction::Binary { dest, .. }
+            | Instruction::Call { dest, .. }
+            | Instruction::Eof { dest, .. } => Some(*dest),
+            _ => None,
+        }
+    }
+
+    pub fn operands(&self) -> Vec<Operand> {
+        match self {
+            Instruction::Store { value, .. } => vec![*value],
+            Instruction::Unary { operand, .. } => vec![*operand],
+            Instruction::Binary { left, right, .. } => vec![*left, *right],
+            Instruction::Call { arguments, .. } => arguments.clone(),
+            Instruction::PrintNumber(value, _)
+            | Instruction::Randomize(value)
+            | Instruction::FilePrintNumber { value, .. } => vec![*value],
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl Terminator {
+    pub fn successors(&self) -> Vec<BlockId> {
+        match self {
+            Terminator::Jump(target) => vec![*target],
+            Terminator::Branch {
+                then, otherwise, ..
+            } => vec![*then, *otherwise],
+            Terminator::Gosub { target, resume, .. } => vec![*target, *resume],
+            // Where a `Resume` goes is only known at runtime, see `Module::resumes`
+            Terminator::Resume { .. } | Terminator::Return => Vec::new(),
+        }
+    }
+}
+
+/// Lowers the syntax tree to IR. Block 0 is the entry block.
+pub fn lower(program: &Program) -> Module {
+    let mut lowering = Lowering {
+        module: Module {
+            variables: program
+                .variables()
+                .iter()
+                .map(|name| name.to_string())
+                .collect(),
+            ..Module::default()
+        },
+        current: 0,
+        labels: HashMap::new(),
+        gotos: Vec::new(),
+        exits: Vec::new(),
+        order: vec![0],
+    };
+
+    lowering.add_block(None);
+
+    // Labels can be jumped to before they appear, so give each its block up front
+    program.walk(&mut |statement| {
+        if let StatementKind::Label(name) = &statement.kind {
+            let block = lowering.add_block(Some(name.clone()));
+            lowering.labels.insert(name.clone(), block);
+        }
+    });
+
+    lowering.statements(&program.statements);
+    lowering.terminate(Terminator::Return);
+
+    for (block, label) in std::mem::take(&mut lowering.gotos) {
+        let target = lowering.labels[&label];
+        let terminator = &mut lowering.module.blocks[block].terminator;
+        *terminator = match *terminator {
+            Terminator::Gosub { resume, line, .. } => Terminator::Gosub {
+                target,
+                resume,
+                line,
+            },
+            _ => Terminator::Jump(target),
+        };
+    }
+
+    // Label blocks were made first, put every block back in source order
+    let mut module = lowering.module;
+    let mut numbering = vec![0; module.blocks.len()];
+    for (position, &block) in lowering.order.iter().enumerate() {
+        numbering[block] = position;
+    }
+
+    let mut blocks: Vec<(usize, Block)> = module.blocks.drain(..).enumerate().collect();
+    blocks.sort_by_key(|(block, _)| numbering[*block]);
+    module.blocks = blocks
+        .into_iter()
+        .map(|(_, mut block)| {
+            block.terminator = match block.terminator {
+                Terminator::Jump(target) => Terminator::Jump(numbering[target]),
+                Terminator::Branch {
+                    condition,
+                    then,
+                    otherwise,
+                } => Terminator::Branch {
+                    condition,
+                    then: numbering[then],
+                    otherwise: numbering[otherwise],
+                },
+                Terminator::Gosub {
+                    target,
+                    resume,
+                    line,
+                } => Terminator::Gosub {
+                    target: numbering[target],
+                    resume: numbering[resume],
+                    line,
+                },
+                terminator => terminator,
+            };
+            block
+        })
+        .collect();
+
+    module
+}
+
+struct Lowering {
+    module: Module,
+    current: BlockId,
+    labels: HashMap<String, BlockId>,
+    /// Blocks ending in a GOTO or GOSUB, patched once every label has a block.
+    gotos: Vec<(BlockId, String)>,
+    /// Where BREAK goes, innermost WHILE last.
+    exits: Vec<BlockId>,
+    /// Blocks in the order lowering starts filling them.
+    order: Vec<BlockId>,
+}
+
+impl Lowering {
+    fn add_block(&mut self, label: Option<String>) -> BlockId {
+        self.module.blocks.push(Block {
+            label,
+            instructions: Vec::new(),
+            terminator: Terminator::Return,
+        });
+        self.module.blocks.len() - 1
+    }
+
+    fn register(&mut self) -> Register {
+        self.module.registers += 1;
+        self.module.registers - 1
+    }
+
+    fn variable(&self, name: &str) -> usize {
+        self.module
+            .variables
+            .iter()
+            .position(|variable| variable == name)
+            .unwrap()
+    }
+
+    fn push(&mut self, instruction: Instruction) {
+        self.module.blocks[self.current]
+            .instructions
+            .push(instruction);
+    }
+
+    fn terminate(&mut self, terminator: Terminator) {
+        self.module.blocks[self.current].terminator = terminator;
+    }
+
+    fn switch_to(&mut self, block: BlockId) {
+        self.current = block;
+        self.order.push(block);
+    }
+
+    fn statements(&mut self, statements: &[Statement]) {
+        for statement in statements {
+            self.statement(statement);
+        }
+    }
+
+    /// Index of the string in the module, adding it the first time.
+    fn string(&mut self, text: &str) -> usize {
+        match self.module.strings.iter().position(|s| s == text) {
+            Some(index) => index,
+            None => {
+                self.module.strings.push(text.to_string());
+                self.module.strings.len() - 1
+            }
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match &statement.kind {
+            StatementKind::Print {
+                file,
+                items,
+                format,
+                newline,
+            } => {
+                let line = statement.line;
+                for item in items {
+                    let instruction = match item {
+                        PrintItem::Text(text) => {
+                            let string = self.string(text);
+                            match file {
+                                Some(file) => Instruction::FilePrintString {
+                                    file: *file,
+                                    string,
+                                    line,
+                                },
+                                None => Instruction::PrintString(string),
+                            }
+                        }
+                        PrintItem::Tab => {
+                            let string = self.string("\t");
+                            match file {
+                                Some(file) => Instruction::FilePrintString {
+                                    file: *file,
+                                    string,
+                                    line,
+                                },
+                                None => Instruction::PrintString(string),
+                            }
+                        }
+                        PrintItem::Number(expression) => {
+                            let value = self.expression(expression);
+                            match file {
+                                Some(file) => Instruction::FilePrintNumber {
+                                    file: *file,
+                                    value,
+                                    format: *format,
+                                    line,
+                                },
+                                None => Instruction::PrintNumber(value, *format),
+                            }
+                        }
+                    };
+                    self.push(instruction);
+                }
+                if *newline {
+                    self.push(match file {
+                        Some(file) => Instruction::FilePrintNewline { file: *file, line },
+                        None => Instruction::PrintNewline,
+                    });
+                }
+            }
+            StatementKind::If { condition, body } => {
+                let condition = self.expression(condition);
+                let then = self.add_block(None);
+                let after = self.add_block(None);
+                self.terminate(Terminator::Branch {
+                    condition,
+                    then,
+                    otherwise: after,
+                });
+
+                self.switch_to(then);
+                self.statements(body);
+                self.terminate(Terminator::Jump(after));
+                self.switch_to(after);
+            }
+            StatementKind::While { condition, body } => {
+                let header = self.add_block(None);
+                let start = self.add_block(None);
+                let after = self.add_block(None);
+                self.terminate(Terminator::Jump(header));
+
+                self.switch_to(header);
+                let condition = self.expression(condition);
+                self.terminate(Terminator::Branch {
+                    condition,
+                    then: start,
+                    otherwise: after,
+                });
+
+                self.switch_to(start);
+                self.exits.push(after);
+                self.statements(body);
+                self.exits.pop();
+                self.terminate(Terminator::Jump(header));
+                self.switch_to(after);
+            }
+            StatementKind::Label(name) => {
+                let block = self.labels[name];
+                self.terminate(Terminator::Jump(block));
+                self.switch_to(block);
+            }
+            StatementKind::Goto(name) => {
+                self.gotos.push((self.current, name.clone()));
+
+                // Anything after a GOTO is unreachable until the next label
+                let next = self.add_block(None);
+                self.switch_to(next);
+            }
+            StatementKind::Gosub(name) => {
+                let resume = self.add_block(None);
+                self.terminate(Terminator::Gosub {
+                    target: resume,
+                    resume,
+                    line: statement.line,
+                });
+                self.gotos.push((self.current, name.clone()));
+                self.switch_to(resume);
+            }
+            StatementKind::Return => {
+                self.terminate(Terminator::Resume {
+                    line: statement.line,
+                });
+
+                let next = self.add_block(None);
+                self.switch_to(next);
+            }
+            StatementKind::Let(name, expression) => {
+                let value = self.expression(expression);
+                let variable = self.variable(name);
+                self.push(Instruction::Store { variable, value });
+            }
+            StatementKind::Input(file, name) => {
+                let variable = self.variable(name);
+                let line = statement.line;
+                self.push(match file {
+                    Some(file) => Instruction::FileInput {
+                        file: *file,
+                        variable,
+                        line,
+                    },
+                    None => Instruction::Input { variable, line },
+                });
+            }
+            StatementKind::Open { path, mode, file } => {
+                let path = self.string(path);
+                self.push(Instruction::Open {
+                    file: *file,
+                    path,
+                    mode: *mode,
+                    line: statement.line,
+                });
+            }
+            StatementKind::Close(file) => self.push(Instruction::Close {
+                file: *file,
+                line: statement.line,
+            }),
+            StatementKind::Break => {
+                self.terminate(Terminator::Jump(*self.exits.last().unwrap()));
+
+                let next = self.add_block(None);
+                self.switch_to(next);
+            }
+            StatementKind::Randomize(seed) => {
+                let seed = self.expression(seed);
+                self.push(Instruction::Randomize(seed));
+            }
+        }
+    }
+
+    fn expression(&mut self, expression: &Expression) -> Operand {
+        match expression {
+            Expression::Number(value) => Operand::Constant(*value),
+            Expression::Variable(name) => {
+                let dest = self.register();
+                let variable = self.variable(name);
+                self.push(Instruction::Load { dest, variable });
+                Operand::Register(dest)
+            }
+            Expression::Unary(operator, operand) => {
+                let operand = self.expression(operand);
+                let dest = self.register();
+                self.push(Instruction::Unary {
+                    dest,
+                    operator: *operator,
+                    operand,
+                });
+                Operand::Register(dest)
+            }
+            Expression::Binary(operator, left, right) => {
+                let left = self.expression(left);
+                let right = self.expression(right);
+                let dest = self.register();
+                self.push(Instruction::Binary {
+                    dest,
+                    operator: *operator,
+                    left,
+                    right,
+                });
+                Operand::Register(dest)
+            }
+            Expression::Call(function, arguments) => {
+                let arguments = arguments
+                    .iter()
+                    .map(|argument| self.expression(argument))
+                    .collect();
+                let dest = self.register();
+                self.push(Instruction::Call {
+                    dest,
+                    function: *function,
+                    arguments,
+                });
+                Operand::Register(dest)
+            }
+            Expression::Eof { file, line } => {
+                let dest = self.register();
+                self.push(Instruction::Eof {
+                    dest,
+                    file: *file,
+                    line: *line,
+                });
+                Operand::Register(dest)
+            }
+        }
+    }
+}
+
+/// Checks the invariants backends rely on: every register is assigned once and
+/// read later in the same block, and every block, variable and string index
+/// points at something that exists.
+pub fn verify(module: &Module) -> Result<(), String> {
+    if module.blocks.is_empty() {
+        return Err(String::from("Module has no entry block"));
+    }
+
+    let mut assigned = vec![false; module.registers];
+
+    for (index, block) in module.blocks.iter().enumerate() {
+        let mut defined = Vec::new();
+        let check_operand = |operand: &Operand, defined: &Vec<Register>| match operand {
+            Operand::Register(register) if !defined.contains(register) => Err(format!(
+                "bb{}: t{} is read before it is assigned in this block",
+                index, register
+            )),
+            _ => Ok(()),
+        };
+
+        for instruction in &block.instructions {
+            for operand in instruction.operands() {
+                check_operand(&operand, &defined)?;
+            }
+
+            match instruction {
+                Instruction::Load { variable, .. }
+                | Instruction::Store { variable, .. }
+                | Instruction::Input { variable, .. }
+                | Instruction::FileInput { variable, .. }
+                    if *variable >= module.variables.len() =>
+                {
+                    return Err(format!("bb{}: unknown variable v{}", index, variable));
+                }
+                Instruction::PrintString(string)
+                | Instruction::FilePrintString { string, .. }
+                | Instruction::Open { path: string, .. }
+                    if *string >= module.strings.len() =>
+                {
+                    return Err(format!("bb{}: unknown string s{}", index, string));
+                }
+                Instruction::Call {
+                    function,
+                    arguments,
+                    ..
+                } if arguments.len() != function.arity() => {
+                    return Err(format!(
+                        "bb{}: {} called with {} arguments",
+                        index,
+                        function.name(),
+                        arguments.len()
+                    ));
+                }
+                _ => (),
+            }
+
+            if let Some(dest) = instruction.dest() {
+                if dest >= module.registers {
+                    return Err(format!("bb{}: register t{} out of range", index, dest));
+                }
+                if assigned[dest] {
+                    return Err(format!("bb{}: t{} is assigned more than once", index, dest));
+                }
+                assigned[dest] = true;
+                defined.push(dest);
+            }
+        }
+
+        if let Terminator::Branch { condition, .. } = &block.terminator {
+            check_operand(condition, &defined)?;
+        }
+
+        for successor in block.terminator.successors() {
+            if successor >= module.blocks.len() {
+                return Err(format!(
+                    "bb{}: jump to missing block bb{}",
+                    index, successor
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operand::Register(register) => write!(f, "t{}", register),
+            Operand::Constant(value) => write!(f, "{:?}", value),
+        }
+    }
+}
+
+impl fmt::Display for Module {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, name) in self.variables.iter().enumerate() {
+            writeln!(f, "var v{} {}", index, name)?;
+        }
+        for (index, text) in self.strings.iter().enumerate() {
+            writeln!(f, "str s{} {:?}", index, text)?;
+        }
+
+        for (index, block) in self.blocks.iter().enumerate() {
+            match &block.label {
+                Some(label) => writeln!(f, "\nbb{}: ; {}", index, label)?,
+                None => writeln!(f, "\nbb{}:", index)?,
+            }
+
+            for instruction in &block.instructions {
+                let variable = |index: &usize| &self.variables[*index];
+                match instruction {
+                    Instruction::Load { dest, variable: v } => {
+                        writeln!(f, "    t{} = load {}", dest, variable(v))?
+                    }
+                    Instruction::Store { variable: v, value } => {
+                        writeln!(f, "    store {}, {}", variable(v), value)?
+                    }
+                    Instruction::Unary {
+                        dest,
+                        operator,
+                        operand,
+                    } => writeln!(f, "    t{} = {} {}", dest, unary_name(*operator), operand)?,
+                    Instruction::Binary {
+                        dest,
+                        operator,
+                        left,
+                        right,
+                    } => writeln!(
+                        f,
+                        "    t{} = {} {}, {}",
+                        dest,
+                        binary_name(*operator),
+                        left,
+                        right
+                    )?,
+                    Instruction::Call {
+                        dest,
+                        function,
+                        arguments,
+                    } => {
+                        let arguments: Vec<String> =
+                            arguments.iter().map(|a| a.to_string()).collect();
+                        writeln!(
+                            f,
+                            "    t{} = call {} {}",
+                            dest,
+                            function.name().to_lowercase(),
+                            arguments.join(", ")
+                        )?
+                    }
+                    Instruction::Randomize(seed) => writeln!(f, "    randomize {}", seed)?,
+                    Instruction::PrintNumber(value, format) => {
+                        if *format == NumberFormat::default() {
+                            writeln!(f, "    print {}", value)?
+                        } else {
+                            writeln!(f, "    print {} using {:?}", value, format.picture())?
+                        }
+                    }
+                    Instruction::PrintString(string) => writeln!(f, "    print s{}", string)?,
+                    Instruction::PrintNewline => writeln!(f, "    newline")?,
+                    Instruction::Input { variable: v, .. } => {
+                        writeln!(f, "    input {}", variable(v))?
+                    }
+                    Instruction::Open {
+                        file, path, mode, ..
+                    } => writeln!(
+                        f,
+                        "    open #{} s{} for {}",
+                        file,
+                        path,
+                        mode.keyword().to_lowercase()
+                    )?,
+                    Instruction::Close { file, .. } => writeln!(f, "    close #{}", file)?,
+                    Instruction::FilePrintNumber {
+                        file,
+                        value,
+                        format,
+                        ..
+                    } => {
+                        if *format == NumberFormat::default() {
+                            writeln!(f, "    print #{} {}", file, value)?
+                        } else {
+                            writeln!(
+                                f,
+                                "    print #{} {} using {:?}",
+                                file,
+                                value,
+                                format.picture()
+                            )?
+                        }
+                    }
+                    Instruction::FilePrintString { file, string, .. } => {
+                        writeln!(f, "    print #{} s{}", file, string)?
+                    }
+                    Instruction::FilePrintNewline { file, .. } => {
+                        writeln!(f, "    newline #{}", file)?
+                    }
+                    Instruction::FileInput {
+                        file, variable: v, ..
+                    } => writeln!(f, "    input #{} {}", file, variable(v))?,
+                    Instruction::Eof { dest, file, .. } => {
+                        writeln!(f, "    t{} = eof #{}", dest, file)?
+                    }
+                }
+            }
+
+            match &block.terminator {
+                Terminator::Jump(target) => writeln!(f, "    jump bb{}", target)?,
+                Terminator::Branch {
+                    condition,
+                    then,
+                    otherwise,
+                } => writeln!(f, "    branch {}, bb{}, bb{}", condition, then, otherwise)?,
+                Terminator::Gosub { target, resume, .. } => {
+                    writeln!(f, "    gosub bb{}, resume bb{}", target, resume)?
+                }
+                Terminator::Resume { .. } => writeln!(f, "    resume")?,
+                Terminator::Return => writeln!(f, "    return")?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub fn unary_name(operator: UnaryOperator) -> &'static str {
+    match operator {
+        UnaryOperator::Plus => "copy",
+        UnaryOperator::Minus => "neg",
+        UnaryOperator::Not => "not",
+    }
+}
+
+pub fn binary_name(operator: BinaryOperator) -> &'static str {
+    match operator {
+        BinaryOperator::Add => "add",
+        BinaryOperator::Subtract => "sub",
+        BinaryOperator::Multiply => "mul",
+        BinaryOperator::Divide => "div",
+        BinaryOperator::IntegerDivide => "idiv",
+        BinaryOperator::Modulo => "mod",
+        BinaryOperator::Power => "pow",
+        BinaryOperator::Equal => "eq",
+        BinaryOperator::NotEqual => "ne",
+        BinaryOperator::Less => "lt",
+        BinaryOperator::LessEqual => "le",
+        BinaryOperator::Greater => "gt",
+        BinaryOperator::GreaterEqual => "ge",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lex::Lexer;
+    use crate::parser::Parser;
+
+    fn lowered(source: &str) -> Module {
+        let program = Parser::new(Lexer::new(format!("{}\n\0", source))).program();
+        lower(&program)
+    }
+
+    #[test]
+    fn it_prints_three_address_code() {
+        let module =
+            lowered("LET a = 1\nWHILE a < 3 REPEAT\nLET a = a + 1\nENDWHILE\nPRINT \"done\"");
+        let expected = "var v0 a
+str s0 \"done\"
+
+bb0:
+    store a, 1.0
+    jump bb1
+
+bb1:
+    t0 = load a
+    t1 = lt t0, 3.0
+    branch t1, bb2, bb3
+
+bb2:
+    t2 = load a
+    t3 = add t2, 1.0
+    store a, t3
+    jump bb1
+
+bb3:
+    print s0
+    newline
+    return
+";
+        assert_eq!(module.to_string(), expected);
+        assert!(verify(&module).is_ok());
+    }
+
+    #[test]
+    fn it_lowers_goto_to_label_blocks() {
+        let module = lowered("GOTO end\nPRINT \"skipped\"\nLABEL end\nPRINT \"done\"");
+        assert!(verify(&module).is_ok());
+
+        let end = module
+            .blocks
+            .iter()
+            .position(|block| block.label.as_deref() == Some("end"))
+            .unwrap();
+        assert_eq!(module.blocks[0].terminator, Terminator::Jump(end));
+    }
+
+    #[test]
+    fn it_rejects_registers_used_across_blocks() {
+        let mut module = lowered("LET a = 1\nIF a > 0 THEN\nPRINT a\nENDIF");
+        module.blocks[1].instructions.insert(
+            0,
+            Instruction::PrintNumber(Operand::Register(0), NumberFormat::default()),
+        );
+
+        assert!(verify(&module).is_err());
+    }
+}
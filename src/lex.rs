@@ -1,7 +1,18 @@
+use crate::ast::location;
+use crate::dialect::{Dialect, Feature};
+
 pub struct Lexer {
     pub data: String,
     pub current_pos: i64,
     pub current_char: Option<char>,
+    pub line: usize,
+    /// Keywords and string escapes outside the dialect are errors.
+    pub dialect: Dialect,
+    /// Path of the source file, when it came from one.
+    pub file: Option<String>,
+    /// Whether the last token was PRINT, INPUT, CLOSE or AS, the only places
+    /// a `#` starts a file number rather than a comment.
+    file_number_next: bool,
 }
 
 #[allow(dead_code)]
@@ -11,6 +22,10 @@ impl Lexer {
             data,
             current_pos: -1,
             current_char: None,
+            line: 1,
+            dialect: Dialect::default(),
+            file: None,
+            file_number_next: false,
         }
     }
 
@@ -28,11 +43,11 @@ impl Lexer {
         self.skip_comment();
 
         if let Some(char) = self.current_char {
-            let token: Token;
+            let mut token: Token;
 
-            if char.is_digit(10) {
+            if char.is_ascii_digit() {
                 token = Token::new(Some(self.get_number()), TokenType::NUMBER);
-            } else if char.is_alphabetic() {
+            } else if char.is_ascii_alphabetic() || char == '_' {
                 token = self.get_keyword_token();
             } else {
                 token = match char {
@@ -40,10 +55,27 @@ impl Lexer {
                     '-' => Token::new(Some(self.current_char?.to_string()), TokenType::MINUS),
                     '/' => Token::new(Some(self.current_char?.to_string()), TokenType::SLASH),
                     '*' => Token::new(Some(self.current_char?.to_string()), TokenType::ASTERISK),
+                    '%' | '^' | '\\' => {
+                        self.require(Feature::Operators);
+                        let token_type = match char {
+                            '%' => TokenType::PERCENT,
+                            '^' => TokenType::CARET,
+                            _ => TokenType::BACKSLASH,
+                        };
+                        Token::new(Some(char.to_string()), token_type)
+                    }
+                    ';' => Token::new(Some(self.current_char?.to_string()), TokenType::SEMICOLON),
+                    ',' => Token::new(Some(self.current_char?.to_string()), TokenType::COMMA),
+                    '(' => Token::new(Some(self.current_char?.to_string()), TokenType::LPAREN),
+                    ')' => Token::new(Some(self.current_char?.to_string()), TokenType::RPAREN),
+                    '#' => {
+                        self.require(Feature::FileIo);
+                        Token::new(Some(self.current_char?.to_string()), TokenType::HASH)
+                    }
                     '=' => {
                         if self.peek() == Some('=') {
                             self.next_char();
-                            Token::new(None, TokenType::EQEQ)
+                            Token::new(Some("==".to_string()), TokenType::EQEQ)
                         } else {
                             Token::new(Some(self.current_char?.to_string()), TokenType::EQ)
                         }
@@ -79,11 +111,24 @@ impl Lexer {
                 };
             }
 
+            token.line = self.line;
+            self.file_number_next = match token.token_type {
+                TokenType::PRINT | TokenType::INPUT | TokenType::CLOSE => true,
+                TokenType::IDENT => token
+                    .token_text
+                    .as_ref()
+                    .is_some_and(|text| text.eq_ignore_ascii_case("AS")),
+                _ => false,
+            };
+            if char == '\n' {
+                self.line += 1;
+            }
+
             self.next_char();
             Some(token)
         } else {
             self.next_char();
-            return None;
+            None
         }
     }
 
@@ -96,10 +141,15 @@ impl Lexer {
         }
     }
 
+    /// Skips a `#` comment. A `#` right before a digit after PRINT, INPUT,
+    /// CLOSE or AS is a file number, as in `PRINT #1, x`, and is left for
+    /// `get_token`.
     fn skip_comment(&mut self) {
         let char = self.current_char.unwrap_or('\0');
+        let file_number =
+            self.file_number_next && self.peek().is_some_and(|next| next.is_ascii_digit());
 
-        if char == '#' {
+        if char == '#' && !file_number {
             while let Some(char) = self.current_char {
                 match char {
                     '\n' => break,
@@ -109,16 +159,24 @@ impl Lexer {
         }
     }
 
+    /// Reads a string literal, returning its text with escape sequences
+    /// replaced: `\"`, `\\`, `\n`, `\t` and `\u{...}` with a hex code point.
     fn get_string(&mut self) -> String {
         self.next_char(); // Move cursor to first line of string instead of " char
         let mut string_val = String::new();
 
         while let Some(char) = self.current_char {
             match char {
-                '\n' | '\r' | '%' | '\t' | '\\' => {
-                    panic!("Unexpected character in string: {}", char); // Don't allow escape characters, newlines, tabs, or %
+                '\n' | '\r' | '\0' => {
+                    panic!("Unterminated string at {}", self.location(self.line));
                 }
                 '"' => break,
+                '\\' => {
+                    self.require(Feature::StringEscapes);
+                    self.next_char();
+                    string_val.push(self.get_escape());
+                    self.next_char();
+                }
                 _ => {
                     string_val.push(char);
                     self.next_char();
@@ -129,13 +187,51 @@ impl Lexer {
         string_val
     }
 
+    /// Reads the escape sequence after a backslash, leaving the cursor on its
+    /// last character.
+    fn get_escape(&mut self) -> char {
+        match self.current_char {
+            Some('"') => '"',
+            Some('\\') => '\\',
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('u') if self.peek() == Some('{') => {
+                self.next_char();
+                let mut digits = String::new();
+                while let Some(char) = self.peek() {
+                    self.next_char();
+                    if char == '}' {
+                        break;
+                    }
+                    digits.push(char);
+                }
+
+                let code = u32::from_str_radix(&digits, 16).ok();
+                match code.and_then(char::from_u32) {
+                    // Generated C ends strings at the first NUL
+                    Some(char) if char != '\0' && digits.len() <= 6 => char,
+                    _ => panic!(
+                        "Invalid unicode escape \\u{{{}}} at {}",
+                        digits,
+                        self.location(self.line)
+                    ),
+                }
+            }
+            char => panic!(
+                "Unknown escape sequence \\{} in string at {}",
+                char.unwrap_or(' '),
+                self.location(self.line)
+            ),
+        }
+    }
+
     fn get_number(&mut self) -> String {
         let mut num_val = String::new();
 
         while let Some(char) = self.peek() {
             num_val.push(self.current_char.expect("ERROR: Unable to parse number"));
 
-            match char.is_digit(10) || char == '.' {
+            match char.is_ascii_digit() || char == '.' {
                 true => {
                     self.next_char();
                 }
@@ -146,23 +242,54 @@ impl Lexer {
         num_val
     }
 
+    /// Reads a keyword or an identifier, `[A-Za-z_][A-Za-z0-9_]*` with an
+    /// optional `$` or `%` type suffix that is part of the name. Keywords match
+    /// in any case, identifiers are case sensitive: `total` and `Total` differ.
+    /// A `%` straight before an operand is the operator instead, so `a%b` is
+    /// `a MOD b` while `a% + 1` reads `a%`.
     fn get_keyword_token(&mut self) -> Token {
         let mut word = String::new();
+        word.push(self.current_char.expect("ERROR: Unable to parse keyword"));
 
         while let Some(char) = self.peek() {
-            word.push(self.current_char.expect("ERROR: Unable to parse keyword"));
-
-            match char.is_alphabetic() {
-                true => {
-                    self.next_char();
-                }
-                false => {
-                    break;
-                }
+            if !(char.is_ascii_alphanumeric() || char == '_') {
+                break;
             }
+            word.push(char);
+            self.next_char();
+        }
+
+        let after = self.data.chars().nth((self.current_pos + 2) as usize);
+        let operand_follows = after
+            .is_some_and(|next| next.is_ascii_alphanumeric() || matches!(next, '_' | '.' | '('));
+        let suffix = match self.peek() {
+            Some('$') => Some('$'),
+            Some('%') if !operand_follows => Some('%'),
+            _ => None,
+        };
+        if let Some(suffix) = suffix {
+            word.push(suffix);
+            self.next_char();
         }
 
-        TokenType::from_string(word)
+        let token = TokenType::from_string(word);
+        if let Some(feature) = token.token_type.feature() {
+            self.require(feature);
+        }
+        token
+    }
+
+    fn require(&self, feature: Feature) {
+        if !self.dialect.allows_extensions() {
+            panic!(
+                "{}",
+                self.dialect.unavailable(feature, &self.location(self.line))
+            );
+        }
+    }
+
+    pub fn location(&self, line: usize) -> String {
+        location(self.file.as_deref(), line)
     }
 }
 
@@ -170,6 +297,7 @@ impl Lexer {
 pub struct Token {
     pub token_text: Option<String>,
     pub token_type: TokenType,
+    pub line: usize,
 }
 
 impl Token {
@@ -177,10 +305,12 @@ impl Token {
         Token {
             token_text,
             token_type,
+            line: 0,
         }
     }
 }
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(PartialEq, Debug)]
 pub enum TokenType {
     EOF,
@@ -191,6 +321,7 @@ pub enum TokenType {
     //Keywords
     LABEL,
     GOTO,
+    GOSUB,
     PRINT,
     INPUT,
     LET,
@@ -200,6 +331,13 @@ pub enum TokenType {
     WHILE,
     REPEAT,
     ENDWHILE,
+    BREAK,
+    USING,
+    INCLUDE,
+    RANDOMIZE,
+    OPEN,
+    CLOSE,
+    NOT,
     //Operators
     EQ,
     PLUS,
@@ -212,6 +350,15 @@ pub enum TokenType {
     LTEQ,
     GT,
     GTEQ,
+    //Punctuation
+    SEMICOLON,
+    COMMA,
+    LPAREN,
+    RPAREN,
+    HASH,
+    PERCENT,
+    CARET,
+    BACKSLASH,
 }
 
 impl TokenType {
@@ -219,6 +366,7 @@ impl TokenType {
         let token_type = match &input.to_lowercase()[..] {
             "label" => TokenType::LABEL,
             "goto" => TokenType::GOTO,
+            "gosub" => TokenType::GOSUB,
             "print" => TokenType::PRINT,
             "input" => TokenType::INPUT,
             "let" => TokenType::LET,
@@ -228,11 +376,34 @@ impl TokenType {
             "while" => TokenType::WHILE,
             "repeat" => TokenType::REPEAT,
             "endwhile" => TokenType::ENDWHILE,
+            "break" => TokenType::BREAK,
+            "using" => TokenType::USING,
+            "include" => TokenType::INCLUDE,
+            "randomize" => TokenType::RANDOMIZE,
+            "open" => TokenType::OPEN,
+            "close" => TokenType::CLOSE,
+            "not" => TokenType::NOT,
             _ => TokenType::IDENT,
         };
 
         Token::new(Some(input), token_type)
     }
+
+    /// The feature a keyword belongs to, when not every dialect has it.
+    fn feature(&self) -> Option<Feature> {
+        match self {
+            TokenType::LABEL => Some(Feature::Labels),
+            TokenType::WHILE | TokenType::REPEAT | TokenType::ENDWHILE | TokenType::BREAK => {
+                Some(Feature::WhileLoops)
+            }
+            TokenType::USING => Some(Feature::PrintUsing),
+            TokenType::INCLUDE => Some(Feature::Includes),
+            TokenType::RANDOMIZE => Some(Feature::MathFunctions),
+            TokenType::OPEN | TokenType::CLOSE => Some(Feature::FileIo),
+            TokenType::NOT => Some(Feature::Operators),
+            _ => None,
+        }
+    }
 }
 
 impl Clone for TokenType {
@@ -245,6 +416,7 @@ impl Clone for TokenType {
             Self::STRING => Self::STRING,
             Self::LABEL => Self::LABEL,
             Self::GOTO => Self::GOTO,
+            Self::GOSUB => Self::GOSUB,
             Self::PRINT => Self::PRINT,
             Self::INPUT => Self::INPUT,
             Self::LET => Self::LET,
@@ -254,6 +426,13 @@ impl Clone for TokenType {
             Self::WHILE => Self::WHILE,
             Self::REPEAT => Self::REPEAT,
             Self::ENDWHILE => Self::ENDWHILE,
+            Self::BREAK => Self::BREAK,
+            Self::USING => Self::USING,
+            Self::INCLUDE => Self::INCLUDE,
+            Self::RANDOMIZE => Self::RANDOMIZE,
+            Self::OPEN => Self::OPEN,
+            Self::CLOSE => Self::CLOSE,
+            Self::NOT => Self::NOT,
             Self::EQ => Self::EQ,
             Self::PLUS => Self::PLUS,
             Self::MINUS => Self::MINUS,
@@ -265,6 +444,14 @@ impl Clone for TokenType {
             Self::LTEQ => Self::LTEQ,
             Self::GT => Self::GT,
             Self::GTEQ => Self::GTEQ,
+            Self::SEMICOLON => Self::SEMICOLON,
+            Self::COMMA => Self::COMMA,
+            Self::LPAREN => Self::LPAREN,
+            Self::RPAREN => Self::RPAREN,
+            Self::HASH => Self::HASH,
+            Self::PERCENT => Self::PERCENT,
+            Self::CARET => Self::CARET,
+            Self::BACKSLASH => Self::BACKSLASH,
         }
     }
 }
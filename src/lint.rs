@@ -0,0 +1,272 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::{Program, Statement, StatementKind};
+use crate::cfg::Cfg;
+
+/// Checks that flag suspicious but valid programs. Each has a code used by the
+/// `--warn`, `--deny` and `--allow` command line flags.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Lint {
+    UnreachableCode,
+    UnusedLabel,
+    UnusedVariable,
+    UnusedInput,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Level {
+    Allow,
+    Warn,
+    Deny,
+}
+
+pub const ALL_LINTS: [Lint; 4] = [
+    Lint::UnreachableCode,
+    Lint::UnusedLabel,
+    Lint::UnusedVariable,
+    Lint::UnusedInput,
+];
+
+impl Lint {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Lint::UnreachableCode => "unreachable-code",
+            Lint::UnusedLabel => "unused-label",
+            Lint::UnusedVariable => "unused-variable",
+            Lint::UnusedInput => "unused-input",
+        }
+    }
+
+    pub fn from_code(code: &str) -> Option<Lint> {
+        ALL_LINTS.into_iter().find(|lint| lint.code() == code)
+    }
+}
+
+/// Level for every lint, all of them warn unless told otherwise.
+#[derive(Clone, Debug, Default)]
+pub struct LintLevels {
+    levels: HashMap<Lint, Level>,
+}
+
+impl LintLevels {
+    pub fn set(&mut self, lint: Lint, level: Level) {
+        self.levels.insert(lint, level);
+    }
+
+    /// Sets the level from a command line code, `all` applies to every lint.
+    pub fn set_code(&mut self, code: &str, level: Level) -> Result<(), String> {
+        if code == "all" {
+            for lint in ALL_LINTS {
+                self.set(lint, level);
+            }
+            return Ok(());
+        }
+
+        match Lint::from_code(code) {
+            Some(lint) => {
+                self.set(lint, level);
+                Ok(())
+            }
+            None => Err(format!("Unknown lint: {}", code)),
+        }
+    }
+
+    pub fn level(&self, lint: Lint) -> Level {
+        *self.levels.get(&lint).unwrap_or(&Level::Warn)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub lint: Lint,
+    pub line: usize,
+    /// Index into `Program::files`.
+    pub file: usize,
+    pub message: String,
+}
+
+/// Runs every lint over the program, returning findings in source order.
+pub fn check(program: &Program, cfg: &Cfg) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+
+    unreachable_code(cfg, &mut diagnostics);
+    unused_labels(program, &mut diagnostics);
+    unused_variables(program, &mut diagnostics);
+
+    diagnostics.sort_by_key(|diagnostic| (diagnostic.file, diagnostic.line));
+    diagnostics
+}
+
+/// Prints the diagnostics that are not allowed. Returns false if any of them
+/// were denied.
+pub fn report(diagnostics: &[Diagnostic], program: &Program, levels: &LintLevels) -> bool {
+    let mut passed = true;
+
+    for diagnostic in diagnostics {
+        let kind = match levels.level(diagnostic.lint) {
+            Level::Allow => continue,
+            Level::Warn => "Warning",
+            Level::Deny => {
+                passed = false;
+                "Error"
+            }
+        };
+
+        println!(
+            "{} [{}]: {} at {}",
+            kind,
+            diagnostic.lint.code(),
+            diagnostic.message,
+            program.location(diagnostic.file, diagnostic.line)
+        );
+    }
+
+    passed
+}
+
+fn unreachable_code(cfg: &Cfg, diagnostics: &mut Vec<Diagnostic>) {
+    // Only report the first statement of each dead run, the rest follow from it
+    let mut previous: Option<usize> = None;
+
+    for statement in cfg.unreachable_statements() {
+        if previous.map(|id| id + 1) != Some(statement.id) {
+            diagnostics.push(Diagnostic {
+                lint: Lint::UnreachableCode,
+                line: statement.line,
+                file: statement.file,
+                message: String::from("Unreachable statement"),
+            });
+        }
+        previous = Some(statement.id);
+    }
+}
+
+fn unused_labels(program: &Program, diagnostics: &mut Vec<Diagnostic>) {
+    let mut targets = HashSet::new();
+    let mut labels = Vec::new();
+
+    program.walk(&mut |statement| match &statement.kind {
+        StatementKind::Goto(name) | StatementKind::Gosub(name) => {
+            targets.insert(name.as_str());
+        }
+        StatementKind::Label(name) => labels.push((name.as_str(), statement)),
+        _ => (),
+    });
+
+    for (name, statement) in labels {
+        // Line numbers label every line whether or not anything jumps there
+        if !targets.contains(name) && !name.starts_with(|c: char| c.is_ascii_digit()) {
+            diagnostics.push(Diagnostic {
+                lint: Lint::UnusedLabel,
+                line: statement.line,
+                file: statement.file,
+                message: format!("Label declared but never used: {}", name),
+            });
+        }
+    }
+}
+
+fn unused_variables(program: &Program, diagnostics: &mut Vec<Diagnostic>) {
+    let mut read = HashSet::new();
+    let mut assigned: Vec<&Statement> = Vec::new();
+
+    program.walk(&mut |statement| {
+        for expression in statement.expressions() {
+            read.extend(expression.variables());
+        }
+
+        if let StatementKind::Let(..) | StatementKind::Input(..) = &statement.kind {
+            assigned.push(statement);
+        }
+    });
+
+    // A variable read nowhere is reported once, at its first INPUT if it has one
+    let mut reported = HashSet::new();
+    let inputs: Vec<&Statement> = assigned
+        .iter()
+        .copied()
+        .filter(|statement| matches!(statement.kind, StatementKind::Input(..)))
+        .collect();
+
+    for statement in inputs.into_iter().chain(assigned.iter().copied()) {
+        let (lint, name, message) = match &statement.kind {
+            StatementKind::Input(_, name) => (
+                Lint::UnusedInput,
+                name,
+                format!("Input stored but never read: {}", name),
+            ),
+            StatementKind::Let(name, _) => (
+                Lint::UnusedVariable,
+                name,
+                format!("Variable assigned but never read: {}", name),
+            ),
+            _ => continue,
+        };
+
+        if !read.contains(name.as_str()) && reported.insert(name.as_str()) {
+            diagnostics.push(Diagnostic {
+                lint,
+                line: statement.line,
+                file: statement.file,
+                message,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lex::Lexer;
+    use crate::parser::Parser;
+
+    fn lint(source: &str) -> Vec<(Lint, usize)> {
+        let program = Parser::new(Lexer::new(format!("{}\n\0", source))).program();
+        let cfg = Cfg::build(&program);
+
+        check(&program, &cfg)
+            .into_iter()
+            .map(|diagnostic| (diagnostic.lint, diagnostic.line))
+            .collect()
+    }
+
+    #[test]
+    fn it_reports_once_per_dead_run() {
+        let found = lint("LABEL loop\nPRINT \"hi\"\nGOTO loop\nPRINT \"a\"\nPRINT \"b\"");
+        assert_eq!(found, vec![(Lint::UnreachableCode, 4)]);
+    }
+
+    #[test]
+    fn it_reports_unused_labels_and_variables() {
+        let found = lint("LABEL start\nLET a = 1\nLET b = 2\nPRINT b");
+        assert_eq!(
+            found,
+            vec![(Lint::UnusedLabel, 1), (Lint::UnusedVariable, 2)]
+        );
+    }
+
+    #[test]
+    fn it_reports_unused_input_over_assignment() {
+        let found = lint("LET a = 0\nINPUT a\nINPUT b\nPRINT b");
+        assert_eq!(found, vec![(Lint::UnusedInput, 2)]);
+    }
+
+    #[test]
+    fn it_applies_levels_by_code() {
+        let mut levels = LintLevels::default();
+        assert!(levels.set_code("unused-label", Level::Deny).is_ok());
+        assert!(levels.set_code("no-such-lint", Level::Deny).is_err());
+
+        let diagnostic = Diagnostic {
+            lint: Lint::UnusedLabel,
+            line: 1,
+            file: 0,
+            message: String::from("Label declared but never used: start"),
+        };
+        let diagnostics = vec![diagnostic];
+        assert!(!report(&diagnostics, &Program::default(), &levels));
+
+        levels.set(Lint::UnusedLabel, Level::Allow);
+        assert!(report(&diagnostics, &Program::default(), &levels));
+    }
+}
@@ -0,0 +1,804 @@
+use crate::ast::{
+    BinaryOperator, FileMode, Function, Random, UnaryOperator, FILES, FUNCTIONS, GOSUB_DEPTH,
+};
+use crate::ir::{Instruction, Module, Operand, Terminator, FILE_ERRORS};
+
+/// Generates textual LLVM IR. Variables are `alloca`s in `main`, blocks map one
+/// to one onto IR blocks and printing and input call libc like the C backend.
+/// Pointers are typed so the output also loads in LLVM releases before opaque
+/// pointers became the default.
+pub fn generate(module: &Module) -> String {
+    let mut llvm = Llvm::default();
+
+    llvm.line("; ModuleID = 'tiny-basic'");
+    llvm.constant("format_number", b"%*.*f");
+    llvm.constant("format_input", b"%lf");
+    llvm.constant("format_skip", b"%*s");
+    llvm.constant(
+        "format_end_of_input",
+        b"Runtime error at line %d: end of input\n",
+    );
+    let resumes = module.resumes();
+    let gosubs = module.uses_gosub();
+    if gosubs {
+        llvm.constant(
+            "format_gosub",
+            b"Runtime error at line %d: GOSUB nested too deeply\n",
+        );
+        llvm.constant(
+            "format_return",
+            b"Runtime error at line %d: RETURN without GOSUB\n",
+        );
+    }
+    let files = module.uses_files();
+    if files {
+        for (name, message) in FILE_ERRORS {
+            llvm.constant(
+                &format!("format_{}", name),
+                format!("Runtime error at line %d: {}\n", message).as_bytes(),
+            );
+        }
+        for mode in [FileMode::Input, FileMode::Output, FileMode::Append] {
+            llvm.constant(&format!("mode_{}", mode.c_mode()), mode.c_mode().as_bytes());
+        }
+    }
+    for (index, text) in module.strings.iter().enumerate() {
+        llvm.constant(&format!("string{}", index), text.as_bytes());
+    }
+
+    llvm.line("\ndeclare i32 @printf(i8*, ...)");
+    llvm.line("declare i32 @scanf(i8*, ...)");
+    llvm.line("declare i32 @fputs(i8*, i8*)");
+    llvm.line("declare i32 @putchar(i32)");
+    llvm.line("declare i32 @fflush(i8*)");
+    llvm.line("declare i32 @fprintf(i8*, i8*, ...)");
+    llvm.line("declare void @exit(i32)");
+    llvm.line("declare double @llvm.pow.f64(double, double)");
+    llvm.line("declare double @llvm.trunc.f64(double)");
+    llvm.line("declare i64 @llvm.fptosi.sat.i64.f64(double)");
+    llvm.line("declare double @llvm.fabs.f64(double)");
+    llvm.line("@stdout = external global i8*");
+    llvm.line("@stderr = external global i8*");
+    for function in FUNCTIONS {
+        if let Some(name) = function.libm() {
+            let parameters = vec!["double"; function.arity()].join(", ");
+            llvm.line(&format!("declare double @{}({})", name, parameters));
+        }
+    }
+
+    // SGN and RND, the same as tb_sgn and tb_rnd in the C runtime
+    llvm.line(
+        "
+define internal double @sgn(double %value) {",
+    );
+    llvm.line("  %positive = fcmp ogt double %value, 0.0");
+    llvm.line("  %negative = fcmp olt double %value, 0.0");
+    llvm.line("  %one = uitofp i1 %positive to double");
+    llvm.line("  %minus_one = uitofp i1 %negative to double");
+    llvm.line("  %sign = fsub double %one, %minus_one\n  ret double %sign\n}");
+
+    llvm.line("\n@random_state = internal global i64 0");
+    llvm.line("\ndefine internal double @rnd(double %scale) {");
+    llvm.line("  %state = load i64, i64* @random_state");
+    llvm.line(&format!(
+        "  %multiplied = mul i64 %state, {}",
+        Random::MULTIPLIER
+    ));
+    llvm.line(&format!(
+        "  %next = add i64 %multiplied, {}",
+        Random::INCREMENT
+    ));
+    llvm.line("  store i64 %next, i64* @random_state");
+    llvm.line("  %bits = lshr i64 %next, 11\n  %whole = uitofp i64 %bits to double");
+    llvm.line(&format!(
+        "  %unit = fmul double %whole, 0x{:X}",
+        (1.0f64 / (1u64 << 53) as f64).to_bits()
+    ));
+    llvm.line("  %random = fmul double %unit, %scale\n  ret double %random\n}");
+
+    // Called by every INPUT that runs out of input, like tb_error in the C runtime
+    let format = llvm.pointer("format_end_of_input");
+    llvm.line("\ndefine internal void @end_of_input(i32 %line) {");
+    llvm.line("  %flushed = call i32 @fflush(i8* null)");
+    llvm.line("  %stream = load i8*, i8** @stderr");
+    llvm.line(&format!(
+        "  %printed = call i32 (i8*, i8*, ...) @fprintf(i8* %stream, {}, i32 %line)",
+        format
+    ));
+    llvm.line("  call void @exit(i32 1)\n  unreachable\n}");
+
+    if files {
+        llvm.files();
+    }
+    if gosubs {
+        llvm.gosubs();
+    }
+
+    llvm.line("\ndefine i32 @main() {\nentry:");
+    for (index, name) in module.variables.iter().enumerate() {
+        llvm.line(&format!("  %v{} = alloca double ; {}", index, name));
+        llvm.line(&format!("  store double 0.0, double* %v{}", index));
+    }
+    llvm.line("  br label %bb0");
+
+    for (index, block) in module.blocks.iter().enumerate() {
+        match &block.label {
+            Some(label) => llvm.line(&format!("\nbb{}: ; {}", index, label)),
+            None => llvm.line(&format!("\nbb{}:", index)),
+        }
+
+        for instruction in &block.instructions {
+            llvm.instruction(module, instruction);
+        }
+
+        match &block.terminator {
+            Terminator::Jump(target) => llvm.line(&format!("  br label %bb{}", target)),
+            Terminator::Branch {
+                condition,
+                then,
+                otherwise,
+            } => {
+                let name = llvm.temporary();
+                // Unordered so NaN counts as true, like in C
+                llvm.line(&format!(
+                    "  {} = fcmp une double {}, 0.0",
+                    name,
+                    operand(condition)
+                ));
+                llvm.line(&format!(
+                    "  br i1 {}, label %bb{}, label %bb{}",
+                    name, then, otherwise
+                ));
+            }
+            Terminator::Gosub {
+                target,
+                resume,
+                line,
+            } => {
+                llvm.line(&format!("  call void @gosub(i32 {}, i32 {})", resume, line));
+                llvm.line(&format!("  br label %bb{}", target));
+            }
+            Terminator::Resume { line } => match resumes.split_first() {
+                Some((first, rest)) => {
+                    let name = llvm.temporary();
+                    llvm.line(&format!("  {} = call i32 @resume(i32 {})", name, line));
+                    let cases: Vec<String> = rest
+                        .iter()
+                        .map(|resume| format!("i32 {0}, label %bb{0}", resume))
+                        .collect();
+                    llvm.line(&format!(
+                        "  switch i32 {}, label %bb{} [{}]",
+                        name,
+                        first,
+                        cases.join(" ")
+                    ));
+                }
+                // Without a GOSUB, every RETURN stops the program
+                None => {
+                    let format = llvm.pointer("format_return");
+                    llvm.line(&format!(
+                        "  call void @gosub_error({}, i32 {})\n  unreachable",
+                        format, line
+                    ));
+                }
+            },
+            Terminator::Return => llvm.line("  ret i32 0"),
+        }
+    }
+
+    llvm.line("}");
+    llvm.text
+}
+
+#[derive(Default)]
+struct Llvm {
+    text: String,
+    /// Lengths of the string constants, needed to index into them.
+    lengths: Vec<(String, usize)>,
+    /// Counter for values and blocks local to a single instruction.
+    temporaries: usize,
+}
+
+impl Llvm {
+    fn line(&mut self, line: &str) {
+        self.text.push_str(line);
+        self.text.push('\n');
+    }
+
+    fn temporary(&mut self) -> String {
+        self.temporaries += 1;
+        format!("%x{}", self.temporaries - 1)
+    }
+
+    /// The value with the sign of a NaN cleared, so printf writes "nan" like
+    /// every other runtime.
+    fn unsigned_nan(&mut self, value: &str) -> String {
+        let (nan, magnitude, result) = (self.temporary(), self.temporary(), self.temporary());
+        self.line(&format!("  {} = fcmp uno double {}, 0.0", nan, value));
+        self.line(&format!(
+            "  {} = call double @llvm.fabs.f64(double {})",
+            magnitude, value
+        ));
+        self.line(&format!(
+            "  {} = select i1 {}, double {}, double {}",
+            result, nan, magnitude, value
+        ));
+        result
+    }
+
+    fn constant(&mut self, name: &str, bytes: &[u8]) {
+        let length = bytes.len() + 1;
+        self.line(&format!(
+            "@.{} = private unnamed_addr constant [{} x i8] c\"{}\\00\"",
+            name,
+            length,
+            escape(bytes)
+        ));
+        self.lengths.push((name.to_string(), length));
+    }
+
+    /// A pointer to the first character of a string constant.
+    fn pointer(&self, name: &str) -> String {
+        let (_, length) = self
+            .lengths
+            .iter()
+            .find(|(other, _)| other == name)
+            .unwrap();
+        format!(
+            "i8* getelementptr inbounds ([{0} x i8], [{0} x i8]* @.{1}, i64 0, i64 0)",
+            length, name
+        )
+    }
+
+    /// The stack of resume blocks and the helpers GOSUB and RETURN call, the
+    /// same as `tb_gosub` and `tb_return` in the C runtime.
+    fn gosubs(&mut self) {
+        let stack = format!("[{} x i32]", GOSUB_DEPTH);
+        self.line(&format!(
+            "\n@returns = internal global {} zeroinitializer",
+            stack
+        ));
+        self.line("@return_depth = internal global i32 0");
+
+        self.line("\ndefine internal void @gosub_error(i8* %format, i32 %line) {");
+        self.line("  %flushed = call i32 @fflush(i8* null)");
+        self.line("  %stream = load i8*, i8** @stderr");
+        self.line(
+            "  %printed = call i32 (i8*, i8*, ...) @fprintf(i8* %stream, i8* %format, i32 %line)",
+        );
+        self.line("  call void @exit(i32 1)\n  unreachable\n}");
+
+        let format = self.pointer("format_gosub");
+        self.line("\ndefine internal void @gosub(i32 %resume, i32 %line) {");
+        self.line("  %depth = load i32, i32* @return_depth");
+        self.line(&format!(
+            "  %full = icmp eq i32 %depth, {}\n  br i1 %full, label %error, label %push",
+            GOSUB_DEPTH
+        ));
+        self.line(&format!(
+            "error:\n  call void @gosub_error({}, i32 %line)\n  unreachable",
+            format
+        ));
+        self.line(&format!(
+            "push:\n  %slot = getelementptr inbounds {0}, {0}* @returns, i64 0, i32 %depth",
+            stack
+        ));
+        self.line("  store i32 %resume, i32* %slot");
+        self.line("  %next = add i32 %depth, 1\n  store i32 %next, i32* @return_depth");
+        self.line("  ret void\n}");
+
+        let format = self.pointer("format_return");
+        self.line("\ndefine internal i32 @resume(i32 %line) {");
+        self.line("  %depth = load i32, i32* @return_depth");
+        self.line("  %empty = icmp eq i32 %depth, 0\n  br i1 %empty, label %error, label %pop");
+        self.line(&format!(
+            "error:\n  call void @gosub_error({}, i32 %line)\n  unreachable",
+            format
+        ));
+        self.line(
+            "pop:\n  %previous = sub i32 %depth, 1\n  store i32 %previous, i32* @return_depth",
+        );
+        self.line(&format!(
+            "  %slot = getelementptr inbounds {0}, {0}* @returns, i64 0, i32 %previous",
+            stack
+        ));
+        self.line("  %resume = load i32, i32* %slot\n  ret i32 %resume\n}");
+    }
+
+    /// The file table and the helpers file instructions call, checking files
+    /// the same way as `tb_file` in the C runtime.
+    fn files(&mut self) {
+        let table = format!("[{} x i8*]", FILES + 1);
+        let inputs = format!("[{} x i1]", FILES + 1);
+        let format = |llvm: &Self, name: &str| llvm.pointer(&format!("format_{}", name));
+
+        self.line("\ndeclare i8* @fopen(i8*, i8*)");
+        self.line("declare i32 @fclose(i8*)");
+        self.line("declare i32 @fscanf(i8*, i8*, ...)");
+        self.line("declare i32 @fputc(i32, i8*)");
+        self.line("declare i32 @fgetc(i8*)");
+        self.line("declare i32 @ungetc(i32, i8*)");
+        self.line(&format!(
+            "\n@files = internal global {} zeroinitializer",
+            table
+        ));
+        self.line(&format!(
+            "@file_inputs = internal global {} zeroinitializer",
+            inputs
+        ));
+
+        self.line("\ndefine internal void @file_error(i8* %format, i32 %line, i32 %file) {");
+        self.line("  %flushed = call i32 @fflush(i8* null)");
+        self.line("  %stream = load i8*, i8** @stderr");
+        self.line("  %printed = call i32 (i8*, i8*, ...) @fprintf(i8* %stream, i8* %format, i32 %line, i32 %file)");
+        self.line("  call void @exit(i32 1)\n  unreachable\n}");
+
+        // The open stream, for input or for output
+        self.line("\ndefine internal i8* @file(i32 %file, i1 %input, i32 %line) {");
+        self.line(&format!(
+            "  %slot = getelementptr inbounds {0}, {0}* @files, i64 0, i32 %file",
+            table
+        ));
+        self.line("  %stream = load i8*, i8** %slot");
+        self.line("  %closed = icmp eq i8* %stream, null");
+        self.line("  br i1 %closed, label %not_open, label %open");
+        self.line("\nnot_open:");
+        self.line(&format!(
+            "  call void @file_error({}, i32 %line, i32 %file)\n  unreachable",
+            format(self, "not_open")
+        ));
+        self.line("\nopen:");
+        self.line(&format!(
+            "  %kind = getelementptr inbounds {0}, {0}* @file_inputs, i64 0, i32 %file",
+            inputs
+        ));
+        self.line("  %opened_for_input = load i1, i1* %kind");
+        self.line("  %right = icmp eq i1 %opened_for_input, %input");
+        self.line("  br i1 %right, label %checked, label %wrong");
+        self.line("\nwrong:\n  br i1 %input, label %not_input, label %not_output");
+        for error in ["not_input", "not_output"] {
+            self.line(&format!("\n{}:", error));
+            self.line(&format!(
+                "  call void @file_error({}, i32 %line, i32 %file)\n  unreachable",
+                format(self, error)
+            ));
+        }
+        self.line("\nchecked:\n  ret i8* %stream\n}");
+
+        self.line(
+            "\ndefine internal void @open(i32 %file, i8* %path, i8* %mode, i1 %input, i32 %line) {",
+        );
+        self.line(&format!(
+            "  %slot = getelementptr inbounds {0}, {0}* @files, i64 0, i32 %file",
+            table
+        ));
+        self.line("  %stream = load i8*, i8** %slot");
+        self.line("  %taken = icmp ne i8* %stream, null");
+        self.line("  br i1 %taken, label %already_open, label %free");
+        self.line("\nalready_open:");
+        self.line(&format!(
+            "  call void @file_error({}, i32 %line, i32 %file)\n  unreachable",
+            format(self, "already_open")
+        ));
+        self.line("\nfree:");
+        self.line("  %opened = call i8* @fopen(i8* %path, i8* %mode)");
+        self.line("  store i8* %opened, i8** %slot");
+        self.line("  %failed = icmp eq i8* %opened, null");
+        self.line("  br i1 %failed, label %cannot_open, label %done");
+        self.line("\ncannot_open:");
+        self.line("  %flushed = call i32 @fflush(i8* null)");
+        self.line("  %error = load i8*, i8** @stderr");
+        self.line(&format!(
+            "  %printed = call i32 (i8*, i8*, ...) @fprintf(i8* %error, {}, i32 %line, i8* %path)",
+            format(self, "cannot_open")
+        ));
+        self.line("  call void @exit(i32 1)\n  unreachable");
+        self.line("\ndone:");
+        self.line(&format!(
+            "  %kind = getelementptr inbounds {0}, {0}* @file_inputs, i64 0, i32 %file",
+            inputs
+        ));
+        self.line("  store i1 %input, i1* %kind\n  ret void\n}");
+
+        self.line("\ndefine internal void @close(i32 %file, i32 %line) {");
+        self.line(&format!(
+            "  %slot = getelementptr inbounds {0}, {0}* @files, i64 0, i32 %file",
+            table
+        ));
+        self.line("  %stream = load i8*, i8** %slot");
+        self.line("  %closed = icmp eq i8* %stream, null");
+        self.line("  br i1 %closed, label %not_open, label %open");
+        self.line("\nnot_open:");
+        self.line(&format!(
+            "  call void @file_error({}, i32 %line, i32 %file)\n  unreachable",
+            format(self, "not_open")
+        ));
+        self.line("\nopen:");
+        self.line("  %result = call i32 @fclose(i8* %stream)");
+        self.line("  store i8* null, i8** %slot\n  ret void\n}");
+
+        // Like tb_finput_num: a failed match stores 0 and skips a word
+        self.line("\ndefine internal void @file_input(i32 %file, double* %variable, i32 %line) {");
+        self.line("  %stream = call i8* @file(i32 %file, i1 true, i32 %line)");
+        self.line(&format!(
+            "  %read = call i32 (i8*, i8*, ...) @fscanf(i8* %stream, {}, double* %variable)",
+            self.pointer("format_input")
+        ));
+        self.line("  switch i32 %read, label %done [i32 0, label %skip i32 -1, label %end]");
+        self.line("\nend:");
+        self.line(&format!(
+            "  call void @file_error({}, i32 %line, i32 %file)\n  unreachable",
+            format(self, "end_of_file")
+        ));
+        self.line("\nskip:\n  store double 0.0, double* %variable");
+        self.line(&format!(
+            "  %skipped = call i32 (i8*, i8*, ...) @fscanf(i8* %stream, {})",
+            self.pointer("format_skip")
+        ));
+        self.line("  br label %done\n\ndone:\n  ret void\n}");
+
+        // Like tb_eof: skip whitespace, then put back whatever follows
+        self.line("\ndefine internal double @eof(i32 %file, i32 %line) {\nentry:");
+        self.line("  %stream = call i8* @file(i32 %file, i1 true, i32 %line)");
+        self.line("  br label %next\n\nnext:");
+        self.line("  %c = call i32 @fgetc(i8* %stream)");
+        self.line("  %space = icmp eq i32 %c, 32");
+        self.line("  %low = icmp sge i32 %c, 9\n  %high = icmp sle i32 %c, 13");
+        self.line("  %control = and i1 %low, %high\n  %blank = or i1 %space, %control");
+        self.line("  br i1 %blank, label %next, label %other");
+        self.line("\nother:\n  %end = icmp eq i32 %c, -1");
+        self.line("  br i1 %end, label %done, label %back");
+        self.line("\nback:\n  %pushed = call i32 @ungetc(i32 %c, i8* %stream)");
+        self.line("  br label %done\n\ndone:");
+        self.line("  %result = select i1 %end, double 1.0, double 0.0\n  ret double %result\n}");
+    }
+
+    /// The checked stream for a file instruction.
+    fn stream(&mut self, file: usize, input: bool, line: usize) -> String {
+        let stream = self.temporary();
+        self.line(&format!(
+            "  {} = call i8* @file(i32 {}, i1 {}, i32 {})",
+            stream, file, input, line
+        ));
+        stream
+    }
+
+    fn instruction(&mut self, module: &Module, instruction: &Instruction) {
+        match instruction {
+            Instruction::Load { dest, variable } => self.line(&format!(
+                "  %t{} = load double, double* %v{}",
+                dest, variable
+            )),
+            Instruction::Store { variable, value } => self.line(&format!(
+                "  store double {}, double* %v{}",
+                operand(value),
+                variable
+            )),
+            Instruction::Unary {
+                dest,
+                operator,
+                operand: value,
+            } => match operator {
+                UnaryOperator::Minus => {
+                    self.line(&format!("  %t{} = fneg double {}", dest, operand(value)))
+                }
+                // Adding -0.0 leaves every value, including -0.0, unchanged
+                UnaryOperator::Plus => self.line(&format!(
+                    "  %t{} = fadd double {}, -0.0",
+                    dest,
+                    operand(value)
+                )),
+                UnaryOperator::Not => {
+                    let flag = self.temporary();
+                    self.line(&format!(
+                        "  {} = fcmp oeq double {}, 0.0",
+                        flag,
+                        operand(value)
+                    ));
+                    self.line(&format!("  %t{} = uitofp i1 {} to double", dest, flag));
+                }
+            },
+            Instruction::Binary {
+                dest,
+                operator,
+                left,
+                right,
+            } => {
+                let (left, right) = (operand(left), operand(right));
+
+                match (operator, arithmetic(*operator)) {
+                    (BinaryOperator::Power, _) => self.line(&format!(
+                        "  %t{} = call double @llvm.pow.f64(double {}, double {})",
+                        dest, left, right
+                    )),
+                    (BinaryOperator::IntegerDivide, _) => {
+                        let quotient = self.temporary();
+                        self.line(&format!("  {} = fdiv double {}, {}", quotient, left, right));
+                        self.line(&format!(
+                            "  %t{} = call double @llvm.trunc.f64(double {})",
+                            dest, quotient
+                        ));
+                    }
+                    (_, Some(name)) => self.line(&format!(
+                        "  %t{} = {} double {}, {}",
+                        dest, name, left, right
+                    )),
+                    (_, None) => {
+                        let flag = self.temporary();
+                        self.line(&format!(
+                            "  {} = fcmp {} double {}, {}",
+                            flag,
+                            predicate(*operator),
+                            left,
+                            right
+                        ));
+                        self.line(&format!("  %t{} = uitofp i1 {} to double", dest, flag));
+                    }
+                }
+            }
+            Instruction::Call {
+                dest,
+                function,
+                arguments,
+            } => {
+                let name = match function {
+                    Function::Sgn => "sgn",
+                    Function::Rnd => "rnd",
+                    _ => function.libm().unwrap(),
+                };
+                let arguments: Vec<String> = arguments
+                    .iter()
+                    .map(|argument| format!("double {}", operand(argument)))
+                    .collect();
+                self.line(&format!(
+                    "  %t{} = call double @{}({})",
+                    dest,
+                    name,
+                    arguments.join(", ")
+                ));
+            }
+            Instruction::Randomize(seed) => {
+                let state = self.temporary();
+                // Saturates like `as`, where a plain fptosi would be poison out of range
+                self.line(&format!(
+                    "  {} = call i64 @llvm.fptosi.sat.i64.f64(double {})",
+                    state,
+                    operand(seed)
+                ));
+                self.line(&format!("  store i64 {}, i64* @random_state", state));
+            }
+            Instruction::PrintNumber(value, number_format) => {
+                let value = self.unsigned_nan(&operand(value));
+                let format = self.pointer("format_number");
+                let result = self.temporary();
+                self.line(&format!(
+                    "  {} = call i32 (i8*, ...) @printf({}, i32 {}, i32 {}, double {})",
+                    result, format, number_format.width, number_format.decimals, value
+                ));
+            }
+            Instruction::PrintString(string) => {
+                let text = self.pointer(&format!("string{}", string));
+                let stream = self.temporary();
+                let result = self.temporary();
+                self.line(&format!("  {} = load i8*, i8** @stdout", stream));
+                self.line(&format!(
+                    "  {} = call i32 @fputs({}, i8* {})",
+                    result, text, stream
+                ));
+            }
+            Instruction::PrintNewline => {
+                let result = self.temporary();
+                self.line(&format!("  {} = call i32 @putchar(i32 10)", result));
+            }
+            Instruction::Input { variable, line } => {
+                // Same as the C runtime: a failed match stores 0 and skips a word
+                let format = self.pointer("format_input");
+                let skip = self.pointer("format_skip");
+                let read = self.temporary();
+                let label = self.temporary();
+                let label = &label[1..];
+
+                self.line(&format!(
+                    "  {} = call i32 (i8*, ...) @scanf({}, double* %v{}) ; {}",
+                    read, format, variable, module.variables[*variable]
+                ));
+                self.line(&format!(
+                    "  switch i32 {}, label %{}.done [i32 0, label %{}.skip i32 -1, label %{}.eof]",
+                    read, label, label, label
+                ));
+
+                self.line(&format!("\n{}.eof:", label));
+                self.line(&format!("  call void @end_of_input(i32 {})", line));
+                self.line("  unreachable");
+
+                self.line(&format!("\n{}.skip:", label));
+                self.line(&format!("  store double 0.0, double* %v{}", variable));
+                let skipped = self.temporary();
+                self.line(&format!(
+                    "  {} = call i32 (i8*, ...) @scanf({})",
+                    skipped, skip
+                ));
+                self.line(&format!("  br label %{}.done", label));
+                self.line(&format!("\n{}.done:", label));
+            }
+            Instruction::Open {
+                file,
+                path,
+                mode,
+                line,
+            } => {
+                let path = self.pointer(&format!("string{}", path));
+                let mode_name = self.pointer(&format!("mode_{}", mode.c_mode()));
+                self.line(&format!(
+                    "  call void @open(i32 {}, {}, {}, i1 {}, i32 {})",
+                    file,
+                    path,
+                    mode_name,
+                    *mode == FileMode::Input,
+                    line
+                ));
+            }
+            Instruction::Close { file, line } => {
+                self.line(&format!("  call void @close(i32 {}, i32 {})", file, line))
+            }
+            Instruction::FilePrintNumber {
+                file,
+                value,
+                format: number_format,
+                line,
+            } => {
+                let stream = self.stream(*file, false, *line);
+                let value = self.unsigned_nan(&operand(value));
+                let format = self.pointer("format_number");
+                let result = self.temporary();
+                self.line(&format!(
+                    "  {} = call i32 (i8*, i8*, ...) @fprintf(i8* {}, {}, i32 {}, i32 {}, double {})",
+                    result, stream, format, number_format.width, number_format.decimals, value
+                ));
+            }
+            Instruction::FilePrintString { file, string, line } => {
+                let stream = self.stream(*file, false, *line);
+                let text = self.pointer(&format!("string{}", string));
+                let result = self.temporary();
+                self.line(&format!(
+                    "  {} = call i32 @fputs({}, i8* {})",
+                    result, text, stream
+                ));
+            }
+            Instruction::FilePrintNewline { file, line } => {
+                let stream = self.stream(*file, false, *line);
+                let result = self.temporary();
+                self.line(&format!(
+                    "  {} = call i32 @fputc(i32 10, i8* {})",
+                    result, stream
+                ));
+            }
+            Instruction::FileInput {
+                file,
+                variable,
+                line,
+            } => self.line(&format!(
+                "  call void @file_input(i32 {}, double* %v{}, i32 {}) ; {}",
+                file, variable, line, module.variables[*variable]
+            )),
+            Instruction::Eof { dest, file, line } => self.line(&format!(
+                "  %t{} = call double @eof(i32 {}, i32 {})",
+                dest, file, line
+            )),
+        }
+    }
+}
+
+fn operand(operand: &Operand) -> String {
+    match operand {
+        Operand::Register(register) => format!("%t{}", register),
+        Operand::Constant(value) => {
+            let decimal = format!("{:?}", value);
+
+            // LLVM needs a decimal point and no exponent shorthand, anything
+            // else, like infinity, goes in as the hexadecimal bit pattern
+            if value.is_finite() && decimal.contains('.') && !decimal.contains('e') {
+                decimal
+            } else {
+                format!("0x{:016X}", value.to_bits())
+            }
+        }
+    }
+}
+
+fn arithmetic(operator: BinaryOperator) -> Option<&'static str> {
+    match operator {
+        BinaryOperator::Add => Some("fadd"),
+        BinaryOperator::Subtract => Some("fsub"),
+        BinaryOperator::Multiply => Some("fmul"),
+        BinaryOperator::Divide => Some("fdiv"),
+        // frem is fmod, the same as MOD in the C backend
+        BinaryOperator::Modulo => Some("frem"),
+        _ => None,
+    }
+}
+
+/// Comparisons are false for NaN except `<>`, as in C.
+fn predicate(operator: BinaryOperator) -> &'static str {
+    match operator {
+        BinaryOperator::Equal => "oeq",
+        BinaryOperator::NotEqual => "une",
+        BinaryOperator::Less => "olt",
+        BinaryOperator::LessEqual => "ole",
+        BinaryOperator::Greater => "ogt",
+        BinaryOperator::GreaterEqual => "oge",
+        _ => unreachable!("{:?} is not a comparison", operator),
+    }
+}
+
+fn escape(bytes: &[u8]) -> String {
+    let mut escaped = String::new();
+    for &byte in bytes {
+        if byte.is_ascii_graphic() && byte != b'"' && byte != b'\\' || byte == b' ' {
+            escaped.push(byte as char);
+        } else {
+            escaped.push_str(&format!("\\{:02X}", byte));
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{build, cc_available, programs, run, run_command, INPUT, SHORT_INPUT};
+    use crate::Target;
+    use std::process::Command;
+
+    fn available(tool: &str) -> bool {
+        Command::new(tool).arg("--version").output().is_ok()
+    }
+
+    #[test]
+    fn it_writes_constants_llvm_can_parse() {
+        assert_eq!(operand(&Operand::Constant(2.5)), "2.5");
+        assert_eq!(operand(&Operand::Constant(1e300)), "0x7E37E43C8800759C");
+        assert_eq!(
+            operand(&Operand::Constant(f64::INFINITY)),
+            "0x7FF0000000000000"
+        );
+        assert_eq!(escape(b"say \"hi\"\n"), "say \\22hi\\22\\0A");
+    }
+
+    #[test]
+    fn it_passes_llvm_as_and_matches_the_c_backend() {
+        if !available("llvm-as") || !cc_available() {
+            eprintln!("Skipping, llvm-as or cc not found");
+            return;
+        }
+
+        for program in programs() {
+            let name = program.file_stem().unwrap().to_str().unwrap();
+            let c = build(&program, &format!("llvm-c-{}", name), Target::C);
+
+            let llvm = format!("llvm-{}", name);
+            let options = crate::Options {
+                target: Target::LlvmIr,
+                ..crate::Options::default()
+            };
+            crate::compile(program.to_str().unwrap(), &llvm, &options).unwrap();
+
+            let path = format!("output/{}.ll", llvm);
+            let status = Command::new("llvm-as")
+                .arg(&path)
+                .arg("-o")
+                .arg(format!("output/{}.bc", llvm))
+                .status()
+                .unwrap();
+            assert!(status.success(), "llvm-as rejected {}", path);
+
+            if available("lli") {
+                for input in [INPUT, SHORT_INPUT] {
+                    let mut lli = Command::new("lli");
+                    lli.arg(format!("output/{}.bc", llvm));
+                    assert_eq!(run_command(lli, input), run(&c, input), "{}", name);
+                }
+            }
+        }
+    }
+}
@@ -1,29 +1,206 @@
+mod asm;
+mod ast;
+mod bytecode;
+mod cfg;
+mod dataflow;
+mod dialect;
 mod emit;
+mod ir;
 mod lex;
+mod lint;
+mod llvm;
+mod opt;
 mod parser;
+mod rust;
+mod structure;
+#[cfg(test)]
+mod testing;
+mod vm;
+mod wasm;
 
+use crate::bytecode::Chunk;
+use crate::cfg::Cfg;
+use crate::dialect::Dialect;
 use crate::emit::Emitter;
 use crate::lex::Lexer;
+use crate::lint::{Level, LintLevels};
 use crate::parser::Parser;
-use std::fs::read_to_string;
+use std::fs::{create_dir_all, read, read_to_string, write};
 use std::io;
+use std::path::PathBuf;
 use std::process::abort;
 
+/// What `parse` writes to the output directory.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum EmitKind {
+    #[default]
+    Code,
+    Cfg,
+    Ir,
+}
+
+/// Which backend generates code when emitting code.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Target {
+    #[default]
+    C,
+    X86_64,
+    Wasm,
+    Bytecode,
+    LlvmIr,
+    Rust,
+}
+
+impl Target {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Target::C => "c",
+            Target::X86_64 => "s",
+            Target::Wasm => "wasm",
+            Target::Bytecode => "tbc",
+            Target::LlvmIr => "ll",
+            Target::Rust => "rs",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    pub emit: EmitKind,
+    pub target: Target,
+    pub lints: LintLevels,
+    pub opt_level: u8,
+    /// Replace GOTO with structured control flow before generating code. Code
+    /// that cannot be structured runs in a dispatcher loop instead.
+    pub structure: bool,
+    /// Treat `A` and `a` as the same variable or label.
+    pub case_insensitive: bool,
+    pub dialect: Dialect,
+    /// Directories searched for INCLUDEd files.
+    pub include_paths: Vec<String>,
+}
+
+/// What `main` does with its input file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Mode {
+    Compile,
+    /// Run in the bytecode VM instead of writing output.
+    Run,
+    /// Print the bytecode listing.
+    Disassemble,
+    /// Write the program back as source without GOTO.
+    Modernize,
+}
+
 fn main() {
     let args: Vec<String> = std::env::args().collect();
-    if args.len() != 3 {
-        println!("Usage: [file_path] [output_file_name]");
-        abort();
+    let mut options = Options::default();
+    let mut files = Vec::new();
+    let mut mode = Mode::Compile;
+
+    for arg in &args[1..] {
+        match arg.trim_end() {
+            "--emit=code" => options.emit = EmitKind::Code,
+            "--emit=cfg" => options.emit = EmitKind::Cfg,
+            "--emit=ir" => options.emit = EmitKind::Ir,
+            "--target=c" => options.target = Target::C,
+            "--target=x86-64" => options.target = Target::X86_64,
+            "--target=wasm" => options.target = Target::Wasm,
+            "--target=bytecode" => options.target = Target::Bytecode,
+            "--target=llvm-ir" => options.target = Target::LlvmIr,
+            "--target=rust" => options.target = Target::Rust,
+            "--run" => mode = Mode::Run,
+            "--disassemble" => mode = Mode::Disassemble,
+            "--modernize" => mode = Mode::Modernize,
+            "--structure" => options.structure = true,
+            "--case-insensitive" => options.case_insensitive = true,
+            "-O" => options.opt_level = 1,
+            "-O0" => options.opt_level = 0,
+            "-O1" => options.opt_level = 1,
+            "-O2" => options.opt_level = 2,
+            flag if flag.starts_with("--dialect=") => set_dialect(&mut options, flag),
+            flag if flag.starts_with("--include-path=") => options
+                .include_paths
+                .push(flag["--include-path=".len()..].to_string()),
+            flag if flag.starts_with("--warn=") => set_lint(&mut options, flag, Level::Warn),
+            flag if flag.starts_with("--deny=") => set_lint(&mut options, flag, Level::Deny),
+            flag if flag.starts_with("--allow=") => set_lint(&mut options, flag, Level::Allow),
+            flag if flag.starts_with('-') => {
+                println!("Unknown option: {}", flag);
+                abort();
+            }
+            file => files.push(file.to_string()),
+        }
     }
 
-    // Trim newline from input
-    match parse(args[1].trim_end(), args[2].trim_end()) {
-        Ok(_) => (),
-        Err(_) => panic!("Unable to output file"),
+    let expected = match mode {
+        Mode::Compile | Mode::Modernize => 2,
+        Mode::Run | Mode::Disassemble => 1,
     };
+    if files.len() != expected {
+        println!(
+            "Usage: [file_path] [output_file_name] [-O0|-O1|-O2] [--emit=code|cfg|ir] [--target=c|x86-64|wasm|bytecode|llvm-ir|rust] [--structure] [--case-insensitive] [--dialect=tiny|extended] [--include-path=dir] [--warn|--deny|--allow=lint]"
+        );
+        println!("       [file_path] [output_file_name] --modernize");
+        println!("       [file_path|bytecode_file] [--run|--disassemble]");
+        abort();
+    }
+
+    match mode {
+        Mode::Compile => {
+            if compile(&files[0], &files[1], &options).is_err() {
+                panic!("Unable to output file");
+            }
+        }
+        Mode::Run => {
+            let chunk = load_chunk(&files[0], &options);
+            match vm::run(&chunk, &mut io::stdin().lock(), &mut io::stdout().lock()) {
+                Ok(()) => (),
+                // Same report and exit status as the compiled programs
+                Err(error) if vm::is_runtime_error(&error) => {
+                    eprintln!("{}", error);
+                    std::process::exit(1);
+                }
+                Err(_) => panic!("Unable to run program"),
+            }
+        }
+        Mode::Disassemble => print!("{}", load_chunk(&files[0], &options)),
+        Mode::Modernize => {
+            if modernize(&files[0], &files[1], &options).is_err() {
+                panic!("Unable to output file");
+            }
+        }
+    }
+}
+
+fn set_dialect(options: &mut Options, flag: &str) {
+    let name = &flag[flag.find('=').unwrap() + 1..];
+
+    match Dialect::from_name(name) {
+        Some(dialect) => options.dialect = dialect,
+        None => {
+            println!("Unknown dialect: {}", name);
+            abort();
+        }
+    }
 }
 
+fn set_lint(options: &mut Options, flag: &str, level: Level) {
+    let code = &flag[flag.find('=').unwrap() + 1..];
+
+    if let Err(message) = options.lints.set_code(code, level) {
+        println!("{}", message);
+        abort();
+    }
+}
+
+#[cfg(test)]
 fn parse(file_name: &str, output_file_name: &str) -> io::Result<()> {
+    compile(file_name, output_file_name, &Options::default())
+}
+
+/// Reads, parses, lints and optimizes a source file.
+fn front_end(file_name: &str, options: &Options) -> ast::Program {
     let mut file = match read_to_string(file_name) {
         Ok(f) => f,
         Err(_) => panic!("Unable to read file: {}", file_name),
@@ -33,22 +210,125 @@ fn parse(file_name: &str, output_file_name: &str) -> io::Result<()> {
     file += "\n\0";
 
     // Initialize Lexer, Parser
-    let lex = Lexer::new(file);
-    let emit = Emitter::new(String::from(format!("output/{}.c", output_file_name)));
+    let mut lex = Lexer::new(file);
+    lex.dialect = options.dialect;
+    lex.file = Some(file_name.to_string());
 
     // Being parsing
-    let mut parser = Parser::new(lex, emit);
+    let mut parser = Parser::new(lex);
+    parser.case_insensitive = options.case_insensitive;
+    parser.search_paths = options.include_paths.iter().map(PathBuf::from).collect();
+    let mut program = parser.program();
 
-    parser.program();
-    parser.emit.write_file()?;
+    let cfg = Cfg::build(&program);
+    let diagnostics = lint::check(&program, &cfg);
+    if !lint::report(&diagnostics, &program, &options.lints) {
+        println!("Compilation stopped by denied lints");
+        #[cfg(not(test))]
+        abort();
+
+        #[cfg(test)] // Panic during testing
+        panic!();
+    }
+
+    opt::optimize(&mut program, options.opt_level);
+
+    if options.structure {
+        program = structure::structure(&program);
+    }
+    program
+}
+
+/// Writes the program back as Tiny BASIC with its GOTOs structured away.
+fn modernize(file_name: &str, output_file_name: &str, options: &Options) -> io::Result<()> {
+    let program = structure::structure(&front_end(file_name, options));
+
+    create_dir_all("output")?;
+    write(
+        format!("output/{}.teeny", output_file_name),
+        program.to_string(),
+    )
+}
+
+fn compile(file_name: &str, output_file_name: &str, options: &Options) -> io::Result<()> {
+    let program = front_end(file_name, options);
+
+    create_dir_all("output")?;
+    match options.emit {
+        EmitKind::Code => {
+            let path = format!("output/{}.{}", output_file_name, options.target.extension());
+
+            match options.target {
+                Target::C => {
+                    let mut emit = Emitter::new(path);
+                    emit.program(&program);
+                    emit.write_file()?;
+                }
+                Target::X86_64 => write(path, asm::generate(&lower(&program)))?,
+                Target::Wasm => {
+                    let (text, binary) = wasm::generate(&lower(&program));
+                    write(format!("output/{}.wat", output_file_name), text)?;
+                    write(path, binary)?;
+                }
+                Target::Bytecode => write(path, bytecode::compile(&program).to_bytes())?,
+                Target::LlvmIr => write(path, llvm::generate(&lower(&program)))?,
+                Target::Rust => write(path, rust::generate(&program))?,
+            }
+        }
+        EmitKind::Cfg => {
+            let dot = Cfg::build(&program).to_dot();
+            write(format!("output/{}.dot", output_file_name), dot)?
+        }
+        EmitKind::Ir => {
+            let module = ir::lower(&program);
+            if let Err(message) = ir::verify(&module) {
+                panic!("Invalid IR generated: {}", message);
+            }
+            write(
+                format!("output/{}.ir", output_file_name),
+                module.to_string(),
+            )?
+        }
+    }
 
     println!("Parsing complete");
     Ok(())
 }
 
+/// Loads a bytecode file, or compiles a source file to bytecode.
+fn load_chunk(file_name: &str, options: &Options) -> Chunk {
+    if !file_name.ends_with(".tbc") {
+        return bytecode::compile(&front_end(file_name, options));
+    }
+
+    let bytes = match read(file_name) {
+        Ok(bytes) => bytes,
+        Err(_) => panic!("Unable to read file: {}", file_name),
+    };
+
+    match Chunk::from_bytes(&bytes) {
+        Ok(chunk) => chunk,
+        Err(message) => {
+            println!("{}: {}", file_name, message);
+            abort();
+        }
+    }
+}
+
+fn lower(program: &ast::Program) -> ir::Module {
+    let module = ir::lower(program);
+
+    if let Err(message) = ir::verify(&module) {
+        panic!("Invalid IR generated: {}", message);
+    }
+    module
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::io::Write;
+    use std::path::Path;
 
     #[test]
     fn it_handles_hello_world() {
@@ -75,6 +355,419 @@ mod tests {
         assert!(parse("test_files/complex.teeny", "complex").is_ok());
     }
 
+    #[test]
+    fn it_emits_cfg_dot() {
+        let options = Options {
+            emit: EmitKind::Cfg,
+            ..Options::default()
+        };
+        assert!(compile("test_files/unreachable.teeny", "unreachable", &options).is_ok());
+
+        let dot = read_to_string("output/unreachable.dot").unwrap();
+        assert!(dot.starts_with("digraph cfg {"));
+        assert!(dot.contains("GOTO done"));
+    }
+
+    #[test]
+    fn it_emits_ir() {
+        let options = Options {
+            emit: EmitKind::Ir,
+            ..Options::default()
+        };
+        assert!(compile("test_files/complex.teeny", "complex", &options).is_ok());
+
+        let ir = read_to_string("output/complex.ir").unwrap();
+        assert!(ir.contains("; composite"));
+        assert!(ir.contains("input limit"));
+    }
+
+    #[test]
+    fn it_folds_constants_when_optimizing() {
+        let options = Options {
+            opt_level: 1,
+            ..Options::default()
+        };
+        assert!(compile("test_files/nested-loop.teeny", "nested-loop-O1", &options).is_ok());
+
+        let c = read_to_string("output/nested-loop-O1.c").unwrap();
+        assert!(c.contains("v_foo = v_bar*3.0+2.0;"));
+        assert!(!c.contains("if(100.0<100.0)"));
+        assert!(!c.contains("tb_print_num(v_bar);"));
+    }
+
+    #[test]
+    fn it_keeps_negative_zero_when_optimizing() {
+        if !testing::cc_available() {
+            eprintln!("Skipping, cc not found");
+            return;
+        }
+
+        create_dir_all("output").unwrap();
+        write(
+            "output/negative-zero.teeny",
+            "LET z = -0\nPRINT z + 0\nPRINT 0 + z\nPRINT z - 0\nPRINT z + -0\nPRINT z - -0\n",
+        )
+        .unwrap();
+
+        let source = Path::new("output/negative-zero.teeny");
+        let outputs: Vec<String> = [0, 1]
+            .into_iter()
+            .map(|opt_level| {
+                let options = Options {
+                    opt_level,
+                    ..Options::default()
+                };
+                let name = format!("negative-zero-O{}", opt_level);
+                testing::run(&testing::build_with(source, &name, &options), "")
+            })
+            .collect();
+        assert_eq!(outputs[0], "0.00\n0.00\n-0.00\n-0.00\n0.00\n");
+        assert_eq!(outputs[1], outputs[0]);
+    }
+
+    #[test]
+    fn it_uses_the_c_runtime() {
+        assert!(parse("test_files/avg.teeny", "avg-runtime").is_ok());
+
+        let c = read_to_string("output/avg-runtime.c").unwrap();
+        assert!(c.starts_with("#include \"tb_runtime.h\""));
+        assert!(c.contains("tb_input_num(&v_a, 6);"));
+        assert!(c.contains("tb_print_num(v_s/v_a);"));
+        assert!(!c.contains("scanf"));
+        assert_eq!(
+            read_to_string("output/tb_runtime.h").unwrap(),
+            emit::RUNTIME
+        );
+    }
+
+    #[test]
+    fn it_prefixes_c_names() {
+        assert!(parse("test_files/keywords.teeny", "keywords-prefixed").is_ok());
+
+        let c = read_to_string("output/keywords-prefixed.c").unwrap();
+        assert!(c.contains("double v_int = 0;"));
+        assert!(c.contains("v_main = v_int+v_printf;"));
+        assert!(c.contains("goto l_return;"));
+        assert!(c.contains("\nl_return:;\n"));
+    }
+
+    #[test]
+    fn it_reads_identifiers_with_digits_and_suffixes() {
+        assert!(parse("test_files/identifiers.teeny", "identifiers").is_ok());
+
+        let c = read_to_string("output/identifiers.c").unwrap();
+        assert!(c.contains("v_my_var = v_total2*10.0;"));
+        assert!(c.contains("v_a = 1.0;\nv_A = 2.0;\nvs_a = 3.0;\nvi_a = 4.0;"));
+        assert!(c.contains("tb_input_num(&v_count_, 13);"));
+    }
+
+    #[test]
+    fn it_groups_arithmetic_operators() {
+        let source = String::from("LET n% = 5\nPRINT n%%2 + -n% ^ 2 ^ 3 \\ 2 mod n% * 4\n\0");
+        let program = Parser::new(Lexer::new(source)).program();
+        assert_eq!(
+            program.statements[1].to_string(),
+            "PRINT n% MOD 2 + -(n% ^ 2 ^ 3) \\ 2 MOD n% * 4"
+        );
+    }
+
+    #[test]
+    fn it_matches_names_in_any_case_when_case_insensitive() {
+        let source = String::from("LET Total = 1\nLABEL Top\nPRINT TOTAL\nGOTO top\n\0");
+        let mut parser = Parser::new(Lexer::new(source));
+        parser.case_insensitive = true;
+        let program = parser.program();
+
+        let lines: Vec<String> = program.statements.iter().map(|s| s.to_string()).collect();
+        assert_eq!(
+            lines,
+            ["LET total = 1", "LABEL top", "PRINT total", "GOTO top"]
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn it_keeps_names_case_sensitive_by_default() {
+        let source = String::from("LET Total = 1\nPRINT total\n\0");
+        Parser::new(Lexer::new(source)).program();
+    }
+
+    #[test]
+    fn it_accepts_classic_programs_in_the_tiny_dialect() {
+        let options = Options {
+            dialect: Dialect::Tiny,
+            ..Options::default()
+        };
+        assert!(compile("test_files/tiny.teeny", "tiny-dialect", &options).is_ok());
+
+        let c = read_to_string("output/tiny-dialect.c").unwrap();
+        assert!(c.contains("l_40:;\nif(v_i>v_n){\ngoto l_90;\n}"));
+        assert!(c.contains("tb_gosub(0, 9);\ngoto l_100;\nr_0:;"));
+    }
+
+    #[test]
+    #[should_panic(expected = "Feature WHILE loops not available in dialect tiny at line 2")]
+    fn it_breaks_on_keywords_outside_the_dialect() {
+        let source = String::from("LET a = 1\nWHILE a < 2 REPEAT\nENDWHILE\n\0");
+        let mut lex = Lexer::new(source);
+        lex.dialect = Dialect::Tiny;
+        Parser::new(lex).program();
+    }
+
+    #[test]
+    #[should_panic]
+    fn it_breaks_on_long_names_in_the_tiny_dialect() {
+        let mut lex = Lexer::new(String::from("LET total = 1\n\0"));
+        lex.dialect = Dialect::Tiny;
+        Parser::new(lex).program();
+    }
+
+    #[test]
+    #[should_panic]
+    fn it_breaks_on_invalid_file_numbers() {
+        Parser::new(Lexer::new(String::from("PRINT #9, 1\n\0"))).program();
+    }
+
+    #[test]
+    fn it_keeps_hashes_before_digits_as_comments() {
+        for dialect in [Dialect::Extended, Dialect::Tiny] {
+            let source = String::from(
+                "#1. read input
+LET a = 1 #2 lines
+PRINT a
+\0",
+            );
+            let mut lex = Lexer::new(source);
+            lex.dialect = dialect;
+            let program = Parser::new(lex).program();
+            assert_eq!(program.statements.len(), 2);
+        }
+
+        let source = String::from("OPEN \"f\" FOR OUTPUT AS #1\nPRINT #1, 2\nCLOSE #1\n\0");
+        let program = Parser::new(Lexer::new(source)).program();
+        assert_eq!(program.statements[1].to_string(), "PRINT #1, 2");
+    }
+
+    #[test]
+    fn it_reads_included_files_once() {
+        let program = front_end("test_files/include.teeny", &Options::default());
+
+        assert_eq!(
+            program.files,
+            [
+                "test_files/include.teeny",
+                "test_files/include/banner.teeny"
+            ]
+        );
+        let files: Vec<usize> = program.statements.iter().map(|s| s.file).collect();
+        assert_eq!(files, [1, 1, 0, 0]);
+        assert_eq!(
+            program.location(1, 3),
+            "line 3 of test_files/include/banner.teeny"
+        );
+    }
+
+    #[test]
+    fn it_searches_include_paths() {
+        create_dir_all("output").unwrap();
+        write(
+            "output/search.teeny",
+            "INCLUDE \"banner.teeny\"\nPRINT width\n",
+        )
+        .unwrap();
+
+        let options = Options {
+            include_paths: vec![String::from("test_files/include")],
+            ..Options::default()
+        };
+        let program = front_end("output/search.teeny", &options);
+        assert_eq!(program.statements.len(), 3);
+    }
+
+    #[test]
+    #[should_panic]
+    fn it_breaks_on_include_cycles() {
+        front_end("test_files/include/cycle-a.teeny", &Options::default());
+    }
+
+    #[test]
+    fn it_escapes_strings() {
+        assert!(parse("test_files/strings.teeny", "strings-escaped").is_ok());
+
+        let c = read_to_string("output/strings-escaped.c").unwrap();
+        assert!(c.contains(r#"tb_print_str("100% sure, \"quoted\" and a back\\slash");"#));
+        assert!(c.contains(r#"tb_print_str("caf\303\251 and \360\237\230\200, or caf\303\251"#));
+        assert!(c.contains(r#"tb_print_str("?\?= ?\?/ are not trigraphs");"#));
+
+        // Printing a statement gives source that lexes back to the same text
+        let source = String::from("PRINT \"a\\\\b \\\"c\\\"\\n\\td\\u{7}\"\n\0");
+        let program = Parser::new(Lexer::new(source)).program();
+        let printed = program.statements[0].to_string();
+        assert_eq!(printed, "PRINT \"a\\\\b \\\"c\\\"\\n\\td\\u{7}\"");
+        let reparsed = Parser::new(Lexer::new(format!("{}\n\0", printed))).program();
+        assert_eq!(reparsed.statements[0].to_string(), printed);
+    }
+
+    #[test]
+    fn it_stops_at_the_end_of_input() {
+        if !testing::cc_available() {
+            eprintln!("Skipping, cc not found");
+            return;
+        }
+
+        let binary = testing::build(Path::new("test_files/avg.teeny"), "avg-eof", Target::C);
+        let mut child = std::process::Command::new(binary)
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(testing::SHORT_INPUT.as_bytes())
+            .unwrap();
+        let output = child.wait_with_output().unwrap();
+
+        assert_eq!(output.status.code(), Some(1));
+        assert!(String::from_utf8_lossy(&output.stdout).ends_with("one value at a time: "));
+        assert_eq!(
+            String::from_utf8_lossy(&output.stderr),
+            "Runtime error at line 13: end of input\n"
+        );
+    }
+
+    #[test]
+    fn it_parses_nested_unary_operators() {
+        let source = String::from("LET x = 1\nPRINT --x + - -x - (NOT -x)\n\0");
+        let program = Parser::new(Lexer::new(source)).program();
+        assert_eq!(
+            program.statements[1].to_string(),
+            "PRINT --x + --x - (NOT -x)"
+        );
+    }
+
+    #[test]
+    fn it_applies_not_to_whole_comparisons() {
+        let source = String::from("LET a = 1\nIF NOT a < 2 THEN\nENDIF\nPRINT NOT a + 1\n\0");
+        let program = Parser::new(Lexer::new(source)).program();
+        let ast::StatementKind::If { condition, .. } = &program.statements[1].kind else {
+            panic!("Expected IF");
+        };
+        assert_eq!(
+            *condition,
+            ast::Expression::Unary(
+                ast::UnaryOperator::Not,
+                Box::new(ast::Expression::Binary(
+                    ast::BinaryOperator::Less,
+                    Box::new(ast::Expression::Variable(String::from("a"))),
+                    Box::new(ast::Expression::Number(2.0)),
+                ))
+            )
+        );
+        assert_eq!(program.statements[2].to_string(), "PRINT NOT a + 1");
+    }
+
+    /// A random tree of every arithmetic and unary operator over `VALUES`.
+    fn random_expression(random: &mut ast::Random, depth: usize) -> ast::Expression {
+        use ast::{BinaryOperator, Expression, UnaryOperator};
+        const UNARY: [UnaryOperator; 3] = [
+            UnaryOperator::Plus,
+            UnaryOperator::Minus,
+            UnaryOperator::Not,
+        ];
+        const BINARY: [BinaryOperator; 7] = [
+            BinaryOperator::Add,
+            BinaryOperator::Subtract,
+            BinaryOperator::Multiply,
+            BinaryOperator::Divide,
+            BinaryOperator::IntegerDivide,
+            BinaryOperator::Modulo,
+            BinaryOperator::Power,
+        ];
+        let mut pick = |count: usize| random.next(count as f64) as usize;
+
+        if depth == 0 || pick(4) == 0 {
+            return Expression::Variable(VALUES[pick(VALUES.len())].0.to_string());
+        }
+        if pick(3) == 0 {
+            let operator = UNARY[pick(UNARY.len())];
+            return Expression::Unary(operator, Box::new(random_expression(random, depth - 1)));
+        }
+        let operator = BINARY[pick(BINARY.len())];
+        Expression::Binary(
+            operator,
+            Box::new(random_expression(random, depth - 1)),
+            Box::new(random_expression(random, depth - 1)),
+        )
+    }
+
+    /// Variables are set with LET rather than written as literals, so
+    /// neither optimizer sees a constant unless it propagates one.
+    const VALUES: [(&str, f64); 5] = [("a", 3.0), ("b", -2.0), ("c", 0.5), ("d", 0.0), ("e", 7.0)];
+
+    fn evaluate(expression: &ast::Expression) -> f64 {
+        use ast::Expression;
+        match expression {
+            Expression::Variable(name) => VALUES.iter().find(|(v, _)| v == name).unwrap().1,
+            Expression::Unary(operator, operand) => operator.evaluate(evaluate(operand)),
+            Expression::Binary(operator, left, right) => {
+                operator.evaluate(evaluate(left), evaluate(right))
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn it_agrees_with_an_evaluator_on_random_expressions() {
+        if !testing::cc_available() {
+            eprintln!("Skipping, cc not found");
+            return;
+        }
+
+        let mut random = ast::Random { state: 0 };
+        random.seed(2024.0);
+        let mut source = String::new();
+        let mut expected = String::new();
+        for (name, value) in VALUES {
+            source.push_str(&format!("LET {} = {}\n", name, value));
+        }
+        for _ in 0..200 {
+            let expression = random_expression(&mut random, 4);
+            source.push_str(&format!("PRINT {}\n", expression));
+            expected.push_str(&ast::NumberFormat::default().format(evaluate(&expression)));
+            expected.push('\n');
+        }
+        write("output/random-expressions.teeny", &source).unwrap();
+
+        let source = Path::new("output/random-expressions.teeny");
+        for opt_level in [0, 2] {
+            let options = Options {
+                opt_level,
+                ..Options::default()
+            };
+            let name = format!("random-expressions-O{}", opt_level);
+            let binary = testing::build_with(source, &name, &options);
+            assert_eq!(
+                testing::run(&binary, ""),
+                expected,
+                "-O{} differs on {}",
+                opt_level,
+                source.display()
+            );
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn it_breaks_on_denied_lints() {
+        let mut options = Options::default();
+        options.lints.set(lint::Lint::UnreachableCode, Level::Deny);
+
+        assert!(compile("test_files/unreachable.teeny", "denied", &options).is_err());
+    }
+
     #[test]
     #[should_panic]
     fn it_breaks_on_incorrect_syntax() {
@@ -86,4 +779,25 @@ mod tests {
     fn it_breaks_on_redeclared_labels() {
         assert!(parse("test_files/redeclare.teeny", "fail2").is_err());
     }
+
+    #[test]
+    #[should_panic]
+    fn it_breaks_on_invalid_print_using_formats() {
+        let source = String::from("PRINT USING \"#.#.#\"; 1\n\0");
+        Parser::new(Lexer::new(source)).program();
+    }
+
+    #[test]
+    #[should_panic]
+    fn it_breaks_on_wrong_argument_counts() {
+        let source = String::from("PRINT MOD(7)\n\0");
+        Parser::new(Lexer::new(source)).program();
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown escape sequence")]
+    fn it_breaks_on_unknown_escapes() {
+        let source = String::from("PRINT \"C:\\path\"\n\0");
+        Parser::new(Lexer::new(source)).program();
+    }
 }
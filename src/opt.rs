@@ -0,0 +1,388 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::{
+    has_label, BinaryOperator, Expression, PrintItem, Program, Statement, StatementKind,
+    UnaryOperator,
+};
+use crate::cfg::Cfg;
+use crate::dataflow::{self, Constants};
+
+/// Runs the optimization passes enabled at the given `-O` level.
+pub fn optimize(program: &mut Program, level: u8) {
+    if level >= 1 {
+        fold_program(program);
+    }
+
+    // Each pass can expose more work for the others, so run them until nothing changes
+    if level >= 2 {
+        loop {
+            let before = program.clone();
+
+            propagate_constants(program);
+            fold_program(program);
+            eliminate_dead_stores(program);
+
+            if *program == before {
+                break;
+            }
+        }
+    }
+}
+
+/// Replaces variables that hold a known constant with that constant, then drops
+/// LET statements that store the value the variable already holds.
+fn propagate_constants(program: &mut Program) {
+    let constants = {
+        let cfg = Cfg::build(program);
+        dataflow::constants(&cfg, &program.variables())
+    };
+
+    let mut redundant = HashSet::new();
+    let mut statements = std::mem::take(&mut program.statements);
+    substitute_statements(&mut statements, program, &constants, &mut redundant);
+    program.statements = statements;
+    remove_statements(&mut program.statements, &redundant);
+}
+
+/// `program` has its statements taken out, it is only used for locations.
+fn substitute_statements(
+    statements: &mut [Statement],
+    program: &Program,
+    constants: &HashMap<usize, Constants>,
+    redundant: &mut HashSet<usize>,
+) {
+    for statement in statements {
+        let Some(known) = constants.get(&statement.id) else {
+            continue;
+        };
+        let location = program.location(statement.file, statement.line);
+
+        for expression in statement.expressions_mut() {
+            *expression = fold(&substitute(expression, known, &location), &location);
+        }
+
+        match &mut statement.kind {
+            StatementKind::Let(name, Expression::Number(value))
+                if known.get(name).map(|known| known.to_bits()) == Some(value.to_bits()) =>
+            {
+                redundant.insert(statement.id);
+            }
+            StatementKind::If { body, .. } | StatementKind::While { body, .. } => {
+                substitute_statements(body, program, constants, redundant);
+            }
+            _ => (),
+        }
+    }
+}
+
+fn substitute(expression: &Expression, known: &Constants, location: &str) -> Expression {
+    match expression {
+        Expression::Variable(name) => match known.get(name) {
+            Some(value) => Expression::Number(*value),
+            None => expression.clone(),
+        },
+        Expression::Number(_) | Expression::Eof { .. } => expression.clone(),
+        Expression::Unary(operator, operand) => {
+            Expression::Unary(*operator, Box::new(substitute(operand, known, location)))
+        }
+        Expression::Binary(operator, left, right) => {
+            let left = substitute(left, known, location);
+            let mut substituted = substitute(right, known, location);
+
+            // A divisor that only turns out zero at runtime is not a compile error
+            if operator.divides() && constant(&fold(&substituted, location)) == Some(0.0) {
+                substituted = right.as_ref().clone();
+            }
+
+            Expression::Binary(*operator, Box::new(left), Box::new(substituted))
+        }
+        Expression::Call(function, arguments) => Expression::Call(
+            *function,
+            arguments
+                .iter()
+                .map(|argument| substitute(argument, known, location))
+                .collect(),
+        ),
+    }
+}
+
+/// Removes LET statements whose value is never read.
+fn eliminate_dead_stores(program: &mut Program) {
+    let dead = dataflow::dead_stores(&Cfg::build(program));
+    remove_statements(&mut program.statements, &dead);
+}
+
+/// Removes the given statements, then IF blocks left empty by that.
+fn remove_statements(statements: &mut Vec<Statement>, dead: &HashSet<usize>) {
+    statements.retain_mut(|statement| {
+        if dead.contains(&statement.id) {
+            return false;
+        }
+
+        match &mut statement.kind {
+            StatementKind::If { body, .. } => {
+                remove_statements(body, dead);
+                !body.is_empty()
+            }
+            StatementKind::While { body, .. } => {
+                remove_statements(body, dead);
+                true
+            }
+            _ => true,
+        }
+    });
+}
+
+/// Folds every expression and drops IF/WHILE blocks that can never run. IF
+/// blocks that always run are replaced by their body. Blocks holding a label
+/// are kept since a GOTO may still jump into them.
+fn fold_program(program: &mut Program) {
+    let mut statements = std::mem::take(&mut program.statements);
+    fold_statements(&mut statements, program);
+    program.statements = statements;
+}
+
+/// `program` has its statements taken out, it is only used for locations.
+fn fold_statements(statements: &mut Vec<Statement>, program: &Program) {
+    let mut folded = Vec::with_capacity(statements.len());
+
+    for mut statement in statements.drain(..) {
+        let location = program.location(statement.file, statement.line);
+
+        match &mut statement.kind {
+            StatementKind::Print { items, .. } => {
+                for item in items {
+                    if let PrintItem::Number(expression) = item {
+                        *expression = fold(expression, &location);
+                    }
+                }
+            }
+            StatementKind::Let(_, expression) | StatementKind::Randomize(expression) => {
+                *expression = fold(expression, &location)
+            }
+            StatementKind::If { condition, body } => {
+                *condition = fold(condition, &location);
+                fold_statements(body, program);
+
+                match constant(condition) {
+                    Some(value) if value == 0.0 && !has_label(body) => continue,
+                    Some(value) if value != 0.0 => {
+                        folded.append(body);
+                        continue;
+                    }
+                    _ => (),
+                }
+            }
+            StatementKind::While { condition, body } => {
+                *condition = fold(condition, &location);
+                fold_statements(body, program);
+
+                if constant(condition) == Some(0.0) && !has_label(body) {
+                    continue;
+                }
+            }
+            _ => (),
+        }
+
+        folded.push(statement);
+    }
+
+    *statements = folded;
+}
+
+fn constant(expression: &Expression) -> Option<f64> {
+    match expression {
+        Expression::Number(value) => Some(*value),
+        _ => None,
+    }
+}
+
+/// Folds constant arithmetic and comparisons, and removes identity operations.
+/// Dividing by a constant zero is an error at `location`.
+pub fn fold(expression: &Expression, location: &str) -> Expression {
+    match expression {
+        Expression::Number(_) | Expression::Variable(_) | Expression::Eof { .. } => {
+            expression.clone()
+        }
+        Expression::Unary(operator, operand) => {
+            let operand = fold(operand, location);
+
+            match (operator, operand) {
+                (UnaryOperator::Plus, operand) => operand,
+                (operator, Expression::Number(value)) => {
+                    Expression::Number(operator.evaluate(value))
+                }
+                (UnaryOperator::Minus, Expression::Unary(UnaryOperator::Minus, inner)) => *inner,
+                (operator, operand) => Expression::Unary(*operator, Box::new(operand)),
+            }
+        }
+        Expression::Binary(operator, left, right) => {
+            let left = fold(left, location);
+            let right = fold(right, location);
+
+            if operator.divides() && constant(&right) == Some(0.0) {
+                println!("Division by zero at {}", location);
+                #[cfg(not(test))]
+                std::process::abort();
+
+                #[cfg(test)] // Panic during testing
+                panic!();
+            }
+
+            if let (Some(left), Some(right)) = (constant(&left), constant(&right)) {
+                let value = operator.evaluate(left, right);
+
+                // Leave overflow to runtime, C has no literal for infinity
+                if value.is_finite() {
+                    return Expression::Number(value);
+                }
+            }
+
+            simplify(*operator, left, right)
+        }
+        Expression::Call(function, arguments) => {
+            let arguments: Vec<Expression> = arguments
+                .iter()
+                .map(|argument| fold(argument, location))
+                .collect();
+            let values: Option<Vec<f64>> = arguments.iter().map(constant).collect();
+
+            match values.and_then(|values| function.evaluate(&values)) {
+                Some(value) if value.is_finite() => Expression::Number(value),
+                _ => Expression::Call(*function, arguments),
+            }
+        }
+    }
+}
+
+/// Only drops operations that give back the same bits for every operand:
+/// `-0 + 0` is `0`, so adding zero is only an identity when the zero is `-0`.
+fn simplify(operator: BinaryOperator, left: Expression, right: Expression) -> Expression {
+    const ZERO: u64 = 0.0f64.to_bits();
+    const NEGATIVE_ZERO: u64 = (-0.0f64).to_bits();
+    const ONE: u64 = 1.0f64.to_bits();
+    let bits = |expression: &Expression| constant(expression).map(f64::to_bits);
+
+    match (operator, bits(&left), bits(&right)) {
+        (BinaryOperator::Add, Some(NEGATIVE_ZERO), _) => right,
+        (BinaryOperator::Add, _, Some(NEGATIVE_ZERO)) => left,
+        (BinaryOperator::Subtract, _, Some(ZERO)) => left,
+        (BinaryOperator::Multiply, Some(ONE), _) => right,
+        (BinaryOperator::Multiply | BinaryOperator::Divide, _, Some(ONE)) => left,
+        _ => Expression::Binary(operator, Box::new(left), Box::new(right)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lex::Lexer;
+    use crate::parser::Parser;
+
+    fn optimized_at(source: &str, level: u8) -> Program {
+        let mut program = Parser::new(Lexer::new(format!("{}\n\0", source))).program();
+        optimize(&mut program, level);
+        program
+    }
+
+    fn optimized(source: &str) -> Program {
+        optimized_at(source, 1)
+    }
+
+    fn lines(program: &Program) -> Vec<usize> {
+        let mut lines = Vec::new();
+        program.walk(&mut |statement| lines.push(statement.line));
+        lines
+    }
+
+    fn last_expression(program: &Program) -> String {
+        program.statements[program.statements.len() - 1].expressions()[0].to_string()
+    }
+
+    #[test]
+    fn it_folds_constant_arithmetic() {
+        let program = optimized("PRINT -2 * 3 + 4 / 8 - 1");
+        assert_eq!(last_expression(&program), "-6.5");
+    }
+
+    #[test]
+    fn it_folds_like_the_c_runtime() {
+        let program = optimized("PRINT -7 MOD 3 + 7 \\ -2 * 10 + -2 ^ 2 + 2 ^ 3 ^ 2");
+        assert_eq!(last_expression(&program), "477");
+    }
+
+    #[test]
+    fn it_removes_identities() {
+        let program = optimized("LET x = 1\nPRINT x * 1 + -0 - 0 / 5");
+        assert_eq!(last_expression(&program), "x");
+    }
+
+    #[test]
+    fn it_keeps_adding_zero_which_turns_negative_zero_positive() {
+        let program = optimized("LET x = 1\nPRINT 0 + x + 0 - -0");
+        assert_eq!(last_expression(&program), "0 + x + 0 - -0");
+    }
+
+    #[test]
+    fn it_removes_dead_and_inlines_live_ifs() {
+        let program = optimized(
+            "IF 10 * 10 < 100 THEN\nPRINT \"no\"\nENDIF\nIF 1 < 2 THEN\nPRINT \"yes\"\nENDIF",
+        );
+        assert_eq!(program.statements.len(), 1);
+        assert_eq!(program.statements[0].to_string(), "PRINT \"yes\"");
+    }
+
+    #[test]
+    fn it_keeps_dead_ifs_with_labels() {
+        let program = optimized("IF 1 > 2 THEN\nLABEL inside\nENDIF\nGOTO inside");
+        assert_eq!(program.statements.len(), 2);
+    }
+
+    #[test]
+    #[should_panic]
+    fn it_breaks_on_constant_division_by_zero() {
+        optimized("LET x = 1\nPRINT x / 0");
+    }
+
+    #[test]
+    #[should_panic]
+    fn it_breaks_on_constant_integer_division_by_zero() {
+        optimized("LET x = 1\nPRINT x \\ (1 - 1)");
+    }
+
+    #[test]
+    fn it_propagates_constants_through_let_chains() {
+        let program = optimized_at("LET bar = 2\nLET foo = bar * 3 + 2\nPRINT foo", 2);
+        assert_eq!(lines(&program), vec![3]);
+        assert_eq!(last_expression(&program), "8");
+    }
+
+    #[test]
+    fn it_stops_at_values_changed_by_goto_loops() {
+        let program = optimized_at(
+            "LET a = 0\nLABEL top\nPRINT a\nLET a = a + 1\nIF a < 3 THEN\nGOTO top\nENDIF",
+            2,
+        );
+        assert_eq!(lines(&program), vec![2, 3, 4, 5, 6]);
+        assert_eq!(program.statements[1].expressions()[0].to_string(), "a");
+    }
+
+    #[test]
+    fn it_keeps_input_and_drops_overwritten_stores() {
+        let program = optimized_at("LET a = 1\nINPUT a\nLET z = 0\nPRINT a / z", 2);
+        assert_eq!(lines(&program), vec![2, 4]);
+        assert_eq!(last_expression(&program), "a / z");
+    }
+
+    #[test]
+    fn it_folds_calls_but_keeps_rnd() {
+        let program = optimized("PRINT SQR(16) + ABS(-2) + RND(1) * INT(2.5)");
+        assert_eq!(last_expression(&program), "6 + RND(1) * 2");
+    }
+
+    #[test]
+    fn it_keeps_overwritten_stores_that_call_rnd() {
+        let program = optimized_at("LET a = RND(1)\nLET a = 2\nPRINT a", 2);
+        assert_eq!(lines(&program), vec![1, 3]);
+    }
+}
@@ -1,123 +1,349 @@
-use std::{collections::HashSet, process::abort};
-
+use std::path::{Path, PathBuf};
+use std::{collections::HashSet, fs::read_to_string, process::abort};
+
+use crate::ast::{
+    BinaryOperator, Expression, FileMode, Function, NumberFormat, PrintItem, Program, Statement,
+    StatementKind, UnaryOperator, FILES,
+};
+use crate::dialect::Feature;
 use crate::lex::{Lexer, Token, TokenType};
-use crate::Emitter;
 
 pub struct Parser {
     pub lex: Lexer,
-    pub emit: Emitter,
     pub current_token: Option<Token>,
     pub peek_token: Option<Token>,
     pub symbols: HashSet<String>,
     pub declared_labels: HashSet<String>,
     pub gotoed_labels: HashSet<String>,
+    /// Treat names that differ only in case as the same, like classic BASIC.
+    pub case_insensitive: bool,
+    /// Directories searched for INCLUDE after the including file's own.
+    pub search_paths: Vec<PathBuf>,
+    /// Every file read so far, the main file first when it has a path.
+    files: Vec<String>,
+    /// Index into `files` of the file being parsed.
+    file: usize,
+    /// Canonical paths of the files being parsed, outermost first, with their
+    /// index into `files`.
+    including: Vec<(PathBuf, usize)>,
+    /// Canonical paths of every file read, so each is only included once.
+    included: HashSet<PathBuf>,
+    next_id: usize,
+    /// How many WHILE bodies the parser is inside of.
+    loops: usize,
 }
 
 impl Parser {
-    pub fn new(lex: Lexer, emit: Emitter) -> Self {
+    pub fn new(lex: Lexer) -> Self {
         let mut parser = Parser {
             lex,
-            emit,
             current_token: None,
             peek_token: None,
             symbols: HashSet::new(),
             declared_labels: HashSet::new(),
             gotoed_labels: HashSet::new(),
+            case_insensitive: false,
+            search_paths: Vec::new(),
+            files: Vec::new(),
+            file: 0,
+            including: Vec::new(),
+            included: HashSet::new(),
+            next_id: 0,
+            loops: 0,
         };
 
+        if let Some(file) = parser.lex.file.clone() {
+            if let Ok(path) = Path::new(&file).canonicalize() {
+                parser.including.push((path.clone(), 0));
+                parser.included.insert(path);
+            }
+            parser.files.push(file);
+        }
+
         // Initialize current_token and peek_token
-        while let None = parser.current_token {
+        while parser.current_token.is_none() {
             parser.next_token();
         }
 
         parser
     }
 
-    pub fn program(&mut self) {
-        // Initial lines for program
-        self.emit.header_line("# include <stdio.h>");
-        self.emit.header_line("int main(void){");
+    pub fn program(&mut self) -> Program {
+        let mut program = Program {
+            statements: self.top_level(),
+            ..Program::default()
+        };
+
+        for goto in &self.gotoed_labels {
+            if !self.declared_labels.contains(goto) {
+                println!("Goto called on undeclared label: {}", goto);
+                #[cfg(not(test))]
+                abort();
+
+                #[cfg(test)] // Panic during testing
+                panic!();
+            }
+        }
+
+        program.files = self.files.clone();
+        program
+    }
+
+    /// Statements up to the end of the current file, with INCLUDEs read in place.
+    fn top_level(&mut self) -> Vec<Statement> {
+        let mut statements = Vec::new();
 
         while self.check_token(TokenType::NEWLINE) {
             self.next_token();
         }
 
-        while self.check_token(TokenType::EOF) == false {
-            self.statement();
+        while !self.check_token(TokenType::EOF) {
+            if self.check_token(TokenType::INCLUDE) {
+                statements.extend(self.include());
+            } else {
+                statements.push(self.statement());
+            }
         }
 
-        // Close file of C
-        self.emit.emit_line("return 0;");
-        self.emit.emit_line("}");
+        statements
+    }
 
-        for goto in &self.gotoed_labels {
-            if !self.declared_labels.contains(goto) {
-                println!("Goto called on undeclared label: {}", goto);
-                #[cfg(not(test))]
+    /// Parses an INCLUDE, returning the included file's statements. Every file
+    /// shares one namespace for variables and labels, and a file included a
+    /// second time adds nothing.
+    fn include(&mut self) -> Vec<Statement> {
+        let line = self.current_token.as_ref().unwrap().line;
+        self.next_token();
+
+        let name = self.current_token_text();
+        self.match_token(TokenType::STRING);
+        self.nl();
+
+        let path = self.resolve(&name, line);
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.clone());
+
+        if let Some(start) = self
+            .including
+            .iter()
+            .position(|(open, _)| *open == canonical)
+        {
+            let mut cycle: Vec<String> = self.including[start..]
+                .iter()
+                .map(|(_, file)| self.files[*file].clone())
+                .collect();
+            cycle.push(path.display().to_string());
+            println!(
+                "Include cycle: {} at {}",
+                cycle.join(" -> "),
+                self.lex.location(line)
+            );
+            #[cfg(not(test))]
+            abort();
+
+            #[cfg(test)] // Panic during testing
+            panic!();
+        }
+        if !self.included.insert(canonical.clone()) {
+            return Vec::new();
+        }
+
+        let mut source = match read_to_string(&path) {
+            Ok(source) => source,
+            Err(_) => {
+                println!("Unable to read included file: {}", path.display());
                 abort();
+            }
+        };
+        source += "\n\0";
 
-                #[cfg(test)] // Panic during testing
-                panic!();
+        let mut lex = Lexer::new(source);
+        lex.dialect = self.lex.dialect;
+        lex.file = Some(path.display().to_string());
+
+        // Parse the file with its own lexer, then pick up where this one left off
+        let outer_lex = std::mem::replace(&mut self.lex, lex);
+        let outer_tokens = (self.current_token.take(), self.peek_token.take());
+        let outer_file = self.file;
+        self.file = self.files.len();
+        self.files.push(path.display().to_string());
+        self.including.push((canonical, self.file));
+
+        while self.current_token.is_none() {
+            self.next_token();
+        }
+        let statements = self.top_level();
+
+        self.including.pop();
+        self.file = outer_file;
+        self.lex = outer_lex;
+        (self.current_token, self.peek_token) = outer_tokens;
+        statements
+    }
+
+    /// Finds an included file next to the including one, then in the search paths.
+    fn resolve(&self, name: &str, line: usize) -> PathBuf {
+        let own = match &self.lex.file {
+            Some(file) => Path::new(file)
+                .parent()
+                .unwrap_or(Path::new(""))
+                .to_path_buf(),
+            None => PathBuf::new(),
+        };
+
+        for directory in std::iter::once(&own).chain(&self.search_paths) {
+            let path = directory.join(name);
+            if path.is_file() {
+                return path;
             }
         }
+
+        println!(
+            "Included file not found: {} at {}",
+            name,
+            self.lex.location(line)
+        );
+        #[cfg(not(test))]
+        abort();
+
+        #[cfg(test)] // Panic during testing
+        panic!();
     }
 
-    pub fn statement(&mut self) {
-        match self.current_token_type() {
+    pub fn statement(&mut self) -> Statement {
+        let id = self.next_id;
+        self.next_id += 1;
+        let line = self.current_token.as_ref().unwrap().line;
+
+        let kind = match self.current_token_type() {
             TokenType::PRINT => {
                 self.next_token();
 
-                if self.check_token(TokenType::STRING) {
-                    let line = format!("printf(\"{}\\n\");", &self.current_token_text());
-                    self.emit.emit_line(&line);
+                let file = self.file_prefix(line);
+                let mut format = NumberFormat::default();
+                if self.check_token(TokenType::USING) {
                     self.next_token();
-                } else {
-                    self.emit.emit("printf(\"%.2f\\n\", (float)(");
-                    self.expression();
-                    self.emit.emit_line("));")
+                    format = self.number_format(line);
+                }
+                let (items, newline) = self.print_items(line);
+
+                StatementKind::Print {
+                    file,
+                    items,
+                    format,
+                    newline,
                 }
             }
             TokenType::IF => {
                 self.next_token();
-                self.emit.emit("if(");
-                self.comparison();
+                let condition = self.comparison();
 
                 self.match_token(TokenType::THEN);
                 self.nl();
-                self.emit.emit_line("){");
 
-                while self.check_token(TokenType::ENDIF) == false {
-                    self.statement();
+                let mut body = Vec::new();
+                while !self.check_token(TokenType::ENDIF) {
+                    body.push(self.statement());
                 }
 
                 self.match_token(TokenType::ENDIF);
-                self.emit.emit_line("}");
+                StatementKind::If { condition, body }
             }
             TokenType::WHILE => {
                 self.next_token();
-                self.emit.emit("while(");
-                self.comparison();
+                let condition = self.comparison();
 
                 self.match_token(TokenType::REPEAT);
                 self.nl();
-                self.emit.emit_line("){");
 
-                while self.check_token(TokenType::ENDWHILE) == false {
-                    self.statement();
+                let mut body = Vec::new();
+                self.loops += 1;
+                while !self.check_token(TokenType::ENDWHILE) {
+                    body.push(self.statement());
                 }
+                self.loops -= 1;
 
                 self.match_token(TokenType::ENDWHILE);
-                self.emit.emit_line("}");
+                StatementKind::While { condition, body }
+            }
+            TokenType::NUMBER => {
+                let name = self.line_number(line);
+                self.declare_label(&name, &name, line);
+                self.next_token();
+
+                // A line number labels the statement after it, usually on the same line
+                if !self.check_token(TokenType::NEWLINE) {
+                    return Statement {
+                        id,
+                        line,
+                        file: self.file,
+                        kind: StatementKind::Label(name),
+                    };
+                }
+                StatementKind::Label(name)
             }
             TokenType::LABEL => {
                 self.next_token();
 
-                let text = &self.current_token_text();
+                let text = self.current_token_text();
+                let name = self.name(&text);
+
+                self.declare_label(&name, &text, line);
+                self.match_token(TokenType::IDENT);
+                StatementKind::Label(name)
+            }
+            TokenType::GOTO | TokenType::GOSUB => {
+                let gosub = self.check_token(TokenType::GOSUB);
+                self.next_token();
+
+                let numbered = self.check_token(TokenType::NUMBER);
+                let name = if numbered {
+                    self.line_number(line)
+                } else {
+                    self.require(Feature::Labels, line);
+                    self.name(&self.current_token_text())
+                };
+
+                self.gotoed_labels.insert(name.clone());
+                self.match_token(if numbered {
+                    TokenType::NUMBER
+                } else {
+                    TokenType::IDENT
+                });
+                if gosub {
+                    StatementKind::Gosub(name)
+                } else {
+                    StatementKind::Goto(name)
+                }
+            }
+            // Not a keyword, so `return` still works as a name elsewhere
+            TokenType::IDENT if self.is_word("RETURN") => {
+                self.next_token();
+                StatementKind::Return
+            }
+            TokenType::LET => {
+                self.next_token();
+
+                let name = self.variable_name(line);
+                self.symbols.insert(name.clone());
 
-                println!("{}", text);
+                self.match_token(TokenType::IDENT);
+                self.match_token(TokenType::EQ);
 
-                if self.declared_labels.contains(text) {
-                    println!("Redeclaration of label: {}", text);
+                StatementKind::Let(name, self.expression())
+            }
+            TokenType::INPUT => {
+                self.next_token();
+
+                let file = self.file_prefix(line);
+                let name = self.variable_name(line);
+                self.symbols.insert(name.clone());
+
+                self.match_token(TokenType::IDENT);
+                StatementKind::Input(file, name)
+            }
+            TokenType::BREAK => {
+                if self.loops == 0 {
+                    println!("BREAK outside of WHILE at {}", self.lex.location(line));
                     #[cfg(not(test))]
                     abort();
 
@@ -125,76 +351,201 @@ impl Parser {
                     panic!();
                 }
 
-                self.declared_labels.insert(text.clone());
-                self.emit.emit(text);
-                self.emit.emit_line(":");
-                self.match_token(TokenType::IDENT);
+                self.next_token();
+                StatementKind::Break
             }
-            TokenType::GOTO => {
+            TokenType::RANDOMIZE => {
+                self.next_token();
+                StatementKind::Randomize(self.expression())
+            }
+            TokenType::OPEN => {
                 self.next_token();
 
-                let text = self.current_token_text();
+                let path = self.current_token_text();
+                self.match_token(TokenType::STRING);
+                self.word("FOR", line);
 
-                self.gotoed_labels.insert(text.clone());
+                let mode = if self.check_token(TokenType::INPUT) {
+                    FileMode::Input
+                } else if self.is_word("OUTPUT") {
+                    FileMode::Output
+                } else if self.is_word("APPEND") {
+                    FileMode::Append
+                } else {
+                    println!(
+                        "Expected INPUT, OUTPUT or APPEND at {}",
+                        self.lex.location(line)
+                    );
+                    #[cfg(not(test))]
+                    abort();
 
-                let line = format!("goto {};", text);
-                self.emit.emit_line(&line);
+                    #[cfg(test)] // Panic during testing
+                    panic!();
+                };
+                self.next_token();
 
-                self.match_token(TokenType::IDENT);
+                self.word("AS", line);
+                self.match_token(TokenType::HASH);
+                let file = self.file_number(line);
+                StatementKind::Open { path, mode, file }
             }
-            TokenType::LET => {
+            TokenType::CLOSE => {
                 self.next_token();
+                self.match_token(TokenType::HASH);
+                StatementKind::Close(self.file_number(line))
+            }
+            TokenType::INCLUDE => {
+                println!(
+                    "INCLUDE outside of the top level at {}",
+                    self.lex.location(line)
+                );
+                #[cfg(not(test))]
+                abort();
 
-                let text = self.current_token_text();
+                #[cfg(test)] // Panic during testing
+                panic!();
+            }
+            _ => {
+                println!("Unexpected expression at {:?}", self.current_token_text());
+                #[cfg(not(test))]
+                abort();
+
+                #[cfg(test)] // Panic here during testing
+                panic!();
+            }
+        };
 
-                if !self.symbols.contains(&text) {
-                    self.symbols.insert(text.clone());
+        self.nl();
+        Statement {
+            id,
+            line,
+            file: self.file,
+            kind,
+        }
+    }
 
-                    let decl = format!("float {};", &text);
-                    self.emit.header_line(&decl);
-                }
+    /// The `#n,` that sends PRINT or INPUT to a file. A PRINT with nothing
+    /// after the number just ends the line in the file.
+    fn file_prefix(&mut self, line: usize) -> Option<usize> {
+        if !self.check_token(TokenType::HASH) {
+            return None;
+        }
 
-                let asgn = format!("{} = ", &text);
-                self.emit.emit(&asgn);
+        self.next_token();
+        let file = self.file_number(line);
+        if !self.check_token(TokenType::NEWLINE) {
+            self.match_token(TokenType::COMMA);
+        }
+        Some(file)
+    }
 
-                self.match_token(TokenType::IDENT);
-                self.match_token(TokenType::EQ);
+    /// A file number from 1 to `FILES`, after its `#` when there is one.
+    fn file_number(&mut self, line: usize) -> usize {
+        let text = self.current_token_text();
+        self.match_token(TokenType::NUMBER);
+
+        match text.parse::<usize>() {
+            Ok(file) if (1..=FILES).contains(&file) => file,
+            _ => {
+                println!(
+                    "Invalid file number: {} at {}, expected 1 to {}",
+                    text,
+                    self.lex.location(line),
+                    FILES
+                );
+                #[cfg(not(test))]
+                abort();
 
-                self.expression();
-                self.emit.emit_line(";");
+                #[cfg(test)] // Panic during testing
+                panic!();
             }
-            TokenType::INPUT => {
-                self.next_token();
+        }
+    }
 
-                let text = self.current_token_text();
+    /// Whether the current token is the given word. FOR, AS, OUTPUT and
+    /// APPEND only mean something inside OPEN, so they stay usable as names.
+    fn is_word(&self, word: &str) -> bool {
+        self.check_token(TokenType::IDENT) && self.current_token_text().eq_ignore_ascii_case(word)
+    }
+
+    fn word(&mut self, word: &str, line: usize) {
+        if !self.is_word(word) {
+            println!("Expected {} at {}", word, self.lex.location(line));
+            #[cfg(not(test))]
+            abort();
 
-                if !self.symbols.contains(&text) {
-                    self.symbols.insert(text.clone());
+            #[cfg(test)] // Panic during testing
+            panic!();
+        }
+        self.next_token();
+    }
 
-                    let decl = format!("float {};", &text);
-                    self.emit.header_line(&decl);
+    /// Strings and expressions up to the end of the line, separated by `;` or
+    /// `,`. Also returns whether the line ends without a separator.
+    fn print_items(&mut self, line: usize) -> (Vec<PrintItem>, bool) {
+        let mut items = Vec::new();
+        let mut separated = true;
+        let mut newline = true;
+
+        while !self.check_token(TokenType::NEWLINE) && !self.check_token(TokenType::EOF) {
+            if self.check_token(TokenType::SEMICOLON) || self.check_token(TokenType::COMMA) {
+                if self.check_token(TokenType::COMMA) {
+                    items.push(PrintItem::Tab);
                 }
+                self.next_token();
+                separated = true;
+                newline = false;
+                continue;
+            }
 
-                // Handle our input so we can fallback if a user enters an invalid value for input
-                let line = format!("if(0 == scanf(\"%f\", &{})) {{\n{} = 0;", &text, &text);
-                self.emit.emit_line(&line);
-                self.emit.emit("scanf(\"%");
-                self.emit.emit_line("*s\");");
-                self.emit.emit_line("}");
+            if !separated {
+                println!(
+                    "Expected ; or , between PRINT items at {}",
+                    self.lex.location(line)
+                );
+                #[cfg(not(test))]
+                abort();
 
-                self.match_token(TokenType::IDENT);
+                #[cfg(test)] // Panic during testing
+                panic!();
             }
-            _ => {
-                println!("Unexpected expression at {:?}", self.current_token_text());
+
+            if self.check_token(TokenType::STRING) {
+                items.push(PrintItem::Text(self.current_token_text()));
+                self.next_token();
+            } else {
+                items.push(PrintItem::Number(self.expression()));
+            }
+            separated = false;
+            newline = true;
+        }
+
+        (items, newline)
+    }
+
+    /// The `"###.##";` after PRINT USING.
+    fn number_format(&mut self, line: usize) -> NumberFormat {
+        let picture = self.current_token_text();
+        self.match_token(TokenType::STRING);
+        self.match_token(TokenType::SEMICOLON);
+
+        match NumberFormat::from_picture(&picture) {
+            Some(format) => format,
+            None => {
+                println!(
+                    "Invalid PRINT USING format \"{}\" at {}, expected at most {} #s with at most one point and {} decimals",
+                    picture,
+                    self.lex.location(line),
+                    NumberFormat::MAX_WIDTH,
+                    NumberFormat::MAX_DECIMALS
+                );
                 #[cfg(not(test))]
                 abort();
 
-                #[cfg(test)] // Panic here during testing
+                #[cfg(test)] // Panic during testing
                 panic!();
             }
         }
-
-        self.nl()
     }
 
     fn nl(&mut self) {
@@ -206,14 +557,16 @@ impl Parser {
         }
     }
 
-    fn comparison(&mut self) {
-        self.expression();
-
-        if self.is_comparison_operator() {
-            self.emit.emit(&self.current_token_text());
+    /// NOT binds loosest, as in classic BASIC, so `NOT a < b` is NOT (a < b).
+    fn comparison(&mut self) -> Expression {
+        if self.check_token(TokenType::NOT) {
             self.next_token();
-            self.expression();
-        } else {
+            return Expression::Unary(UnaryOperator::Not, Box::new(self.comparison()));
+        }
+
+        let mut left = self.expression();
+
+        if !self.is_comparison_operator() {
             println!(
                 "Expected comparison operator at {:?}",
                 self.current_token.as_ref().unwrap()
@@ -222,10 +575,13 @@ impl Parser {
         }
 
         while self.is_comparison_operator() {
-            self.emit.emit(&self.current_token_text());
+            let operator = self.binary_operator();
             self.next_token();
-            self.expression();
+            let right = self.expression();
+            left = Expression::Binary(operator, Box::new(left), Box::new(right));
         }
+
+        left
     }
 
     fn is_comparison_operator(&self) -> bool {
@@ -237,44 +593,120 @@ impl Parser {
             || self.check_token(TokenType::NOTEQ)
     }
 
-    fn expression(&mut self) {
-        self.term();
+    /// Outside a condition NOT applies to the whole expression after it, so
+    /// `NOT a + b` is NOT (a + b). Inside arithmetic it needs parentheses.
+    fn expression(&mut self) -> Expression {
+        if self.check_token(TokenType::NOT) {
+            self.next_token();
+            return Expression::Unary(UnaryOperator::Not, Box::new(self.expression()));
+        }
+
+        let mut left = self.term();
 
         while self.check_token(TokenType::PLUS) || self.check_token(TokenType::MINUS) {
-            self.emit.emit(&self.current_token_text());
+            let operator = self.binary_operator();
+            self.next_token();
+            let right = self.term();
+            left = Expression::Binary(operator, Box::new(left), Box::new(right));
+        }
+
+        left
+    }
+
+    fn term(&mut self) -> Expression {
+        let mut left = self.urnary();
+
+        while let Some(operator) = self.term_operator() {
             self.next_token();
-            self.term();
+            let right = self.urnary();
+            left = Expression::Binary(operator, Box::new(left), Box::new(right));
+        }
+
+        left
+    }
+
+    /// The operators that bind like `*`: `/`, `\`, and MOD or `%`.
+    fn term_operator(&self) -> Option<BinaryOperator> {
+        match self.current_token_type() {
+            TokenType::ASTERISK => Some(BinaryOperator::Multiply),
+            TokenType::SLASH => Some(BinaryOperator::Divide),
+            TokenType::BACKSLASH => Some(BinaryOperator::IntegerDivide),
+            TokenType::PERCENT => Some(BinaryOperator::Modulo),
+            // Only an operator can follow an operand, so a name here is MOD
+            TokenType::IDENT if self.is_word("MOD") => {
+                self.require(
+                    Feature::Operators,
+                    self.current_token.as_ref().unwrap().line,
+                );
+                Some(BinaryOperator::Modulo)
+            }
+            _ => None,
         }
     }
 
-    fn term(&mut self) {
-        self.urnary();
+    /// Signs nest, so `--x` is x and `- -x` is too.
+    fn urnary(&mut self) -> Expression {
+        let operator = match self.current_token_type() {
+            TokenType::PLUS => Some(UnaryOperator::Plus),
+            TokenType::MINUS => Some(UnaryOperator::Minus),
+            _ => None,
+        };
 
-        while self.check_token(TokenType::ASTERISK) || self.check_token(TokenType::SLASH) {
-            self.emit.emit(&self.current_token_text());
+        if let Some(operator) = operator {
             self.next_token();
-            self.urnary();
+            return Expression::Unary(operator, Box::new(self.urnary()));
         }
+
+        self.power()
     }
 
-    fn urnary(&mut self) {
-        if self.check_token(TokenType::PLUS) || self.check_token(TokenType::MINUS) {
-            self.emit.emit(&self.current_token_text());
+    /// `^` binds tighter than a sign on its left and groups to the right, so
+    /// `-2 ^ 2` is -4 and `2 ^ 3 ^ 2` is 2 ^ 9. The exponent may have a sign.
+    fn power(&mut self) -> Expression {
+        let base = self.primary();
+
+        if self.check_token(TokenType::CARET) {
             self.next_token();
+            let exponent = self.urnary();
+            return Expression::Binary(BinaryOperator::Power, Box::new(base), Box::new(exponent));
         }
 
-        self.primary();
+        base
     }
 
-    fn primary(&mut self) {
+    fn primary(&mut self) -> Expression {
         if self.check_token(TokenType::NUMBER) {
-            self.emit.emit(&self.current_token_text());
+            let text = self.current_token_text();
+            let value = match text.parse::<f64>() {
+                Ok(value) => value,
+                Err(_) => {
+                    println!("Invalid number: {}", text);
+                    abort();
+                }
+            };
+
+            self.next_token();
+            Expression::Number(value)
+        } else if self.check_token(TokenType::LPAREN) {
             self.next_token();
+            let expression = self.expression();
+            self.match_token(TokenType::RPAREN);
+            expression
+        } else if self.check_token(TokenType::IDENT)
+            && self.peek_token.as_ref().unwrap().token_type == TokenType::LPAREN
+        {
+            self.call()
         } else if self.check_token(TokenType::IDENT) {
             let text = self.current_token_text();
-
-            if !self.symbols.contains(&text) {
-                println!("Referencing variable before declaration: {}", text);
+            let line = self.current_token.as_ref().unwrap().line;
+            let name = self.variable_name(line);
+
+            if !self.symbols.contains(&name) {
+                println!(
+                    "Referencing variable before declaration: {} at {}",
+                    text,
+                    self.lex.location(line)
+                );
                 #[cfg(not(test))]
                 abort();
 
@@ -282,21 +714,170 @@ impl Parser {
                 panic!();
             }
 
-            self.emit.emit(&self.current_token_text());
             self.next_token();
+            Expression::Variable(name)
         } else {
             println!("Unexpected primary token: {:?}", self.current_token_text());
             abort();
         }
     }
 
+    /// The name a variable or label is known by. Case-insensitive programs use
+    /// the lowercase spelling everywhere, so every backend sees one symbol.
+    fn name(&self, text: &str) -> String {
+        if self.case_insensitive || self.lex.dialect.case_insensitive() {
+            text.to_lowercase()
+        } else {
+            text.to_string()
+        }
+    }
+
+    /// A built-in function call, `NAME(argument, ...)`.
+    fn call(&mut self) -> Expression {
+        let text = self.current_token_text();
+        let line = self.current_token.as_ref().unwrap().line;
+
+        // EOF takes a file number rather than an expression
+        if text.eq_ignore_ascii_case("EOF") {
+            self.require(Feature::FileIo, line);
+            self.next_token();
+            self.match_token(TokenType::LPAREN);
+            let file = self.file_number(line);
+            self.match_token(TokenType::RPAREN);
+            return Expression::Eof { file, line };
+        }
+
+        let function = match Function::from_name(&text) {
+            Some(function) => function,
+            None => {
+                println!("Unknown function: {} at {}", text, self.lex.location(line));
+                #[cfg(not(test))]
+                abort();
+
+                #[cfg(test)] // Panic during testing
+                panic!();
+            }
+        };
+        if !matches!(function, Function::Abs | Function::Rnd) {
+            self.require(Feature::MathFunctions, line);
+        }
+
+        self.next_token();
+        self.match_token(TokenType::LPAREN);
+        let mut arguments = vec![self.expression()];
+        while self.check_token(TokenType::COMMA) {
+            self.next_token();
+            arguments.push(self.expression());
+        }
+        self.match_token(TokenType::RPAREN);
+
+        if arguments.len() != function.arity() {
+            println!(
+                "{} takes {} argument{}, got {} at {}",
+                function.name(),
+                function.arity(),
+                if function.arity() == 1 { "" } else { "s" },
+                arguments.len(),
+                self.lex.location(line)
+            );
+            #[cfg(not(test))]
+            abort();
+
+            #[cfg(test)] // Panic during testing
+            panic!();
+        }
+
+        Expression::Call(function, arguments)
+    }
+
+    /// The current identifier as a variable name, checked against the dialect.
+    fn variable_name(&self, line: usize) -> String {
+        let text = self.current_token_text();
+
+        if self.check_token(TokenType::IDENT) {
+            if text.ends_with(['$', '%']) {
+                self.require(Feature::TypeSuffixes, line);
+            } else if text.len() > 1 {
+                self.require(Feature::LongNames, line);
+            }
+        }
+        self.name(&text)
+    }
+
+    /// The current number as a line number, which labels a statement.
+    fn line_number(&self, line: usize) -> String {
+        let text = self.current_token_text();
+
+        match text.parse::<u32>() {
+            Ok(number) => number.to_string(),
+            Err(_) => {
+                println!(
+                    "Invalid line number: {} at {}",
+                    text,
+                    self.lex.location(line)
+                );
+                #[cfg(not(test))]
+                abort();
+
+                #[cfg(test)] // Panic during testing
+                panic!();
+            }
+        }
+    }
+
+    fn declare_label(&mut self, name: &str, text: &str, line: usize) {
+        if self.declared_labels.contains(name) {
+            println!(
+                "Redeclaration of label: {} at {}",
+                text,
+                self.lex.location(line)
+            );
+            #[cfg(not(test))]
+            abort();
+
+            #[cfg(test)] // Panic during testing
+            panic!();
+        }
+
+        self.declared_labels.insert(name.to_string());
+    }
+
+    fn require(&self, feature: Feature, line: usize) {
+        let dialect = self.lex.dialect;
+
+        if !dialect.allows_extensions() {
+            println!("{}", dialect.unavailable(feature, &self.lex.location(line)));
+            #[cfg(not(test))]
+            abort();
+
+            #[cfg(test)] // Panic during testing
+            panic!();
+        }
+    }
+
+    fn binary_operator(&self) -> BinaryOperator {
+        match self.current_token_type() {
+            TokenType::PLUS => BinaryOperator::Add,
+            TokenType::MINUS => BinaryOperator::Subtract,
+            TokenType::ASTERISK => BinaryOperator::Multiply,
+            TokenType::SLASH => BinaryOperator::Divide,
+            TokenType::EQEQ => BinaryOperator::Equal,
+            TokenType::NOTEQ => BinaryOperator::NotEqual,
+            TokenType::LT => BinaryOperator::Less,
+            TokenType::LTEQ => BinaryOperator::LessEqual,
+            TokenType::GT => BinaryOperator::Greater,
+            TokenType::GTEQ => BinaryOperator::GreaterEqual,
+            other => unreachable!("Not a binary operator: {:?}", other),
+        }
+    }
+
     fn check_token(&self, kind: TokenType) -> bool {
         kind == self.current_token_type()
     }
 
     fn match_token(&mut self, kind: TokenType) {
         let kind_ref = kind.clone();
-        if self.check_token(kind) == false {
+        if !self.check_token(kind) {
             println!(
                 "Expected token: {:?}, Got token: {:?}",
                 kind_ref,
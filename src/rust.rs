@@ -0,0 +1,1025 @@
+use crate::ast::{
+    BinaryOperator, Expression, Function, PrintItem, Program, Statement, StatementKind,
+    UnaryOperator,
+};
+use crate::cfg::Cfg;
+
+/// Rust keywords, plus names the generated code uses itself. Variables with
+/// these names get an underscore appended.
+const RESERVED: [&str; 70] = [
+    "as",
+    "async",
+    "await",
+    "break",
+    "const",
+    "continue",
+    "crate",
+    "dyn",
+    "else",
+    "enum",
+    "extern",
+    "false",
+    "fn",
+    "for",
+    "if",
+    "impl",
+    "in",
+    "let",
+    "loop",
+    "match",
+    "mod",
+    "move",
+    "mut",
+    "pub",
+    "ref",
+    "return",
+    "self",
+    "static",
+    "struct",
+    "super",
+    "trait",
+    "true",
+    "type",
+    "unsafe",
+    "use",
+    "where",
+    "while",
+    "abstract",
+    "become",
+    "box",
+    "do",
+    "final",
+    "macro",
+    "override",
+    "priv",
+    "typeof",
+    "unsized",
+    "virtual",
+    "yield",
+    "try",
+    "gen",
+    "input",
+    "state",
+    "format_number",
+    "sgn",
+    "rnd",
+    "randomize",
+    "RANDOM_STATE",
+    "files",
+    "OpenFile",
+    "file_error",
+    "file_words",
+    "open_file",
+    "close_file",
+    "write_file",
+    "read_file",
+    "eof",
+    "returns",
+    "resume",
+    "gosub_error",
+];
+
+/// Generates a Rust program. WHILE and IF become Rust loops and ifs. GOTO is
+/// handled with a `loop` over a `match` on the current state: when every
+/// LABEL is at the top level each state is the code from one label to the
+/// next, otherwise every reachable basic block becomes a state. Programs with
+/// GOSUB or RETURN always use basic blocks, since a RETURN goes back to a state
+/// picked at runtime.
+pub fn generate(program: &Program) -> String {
+    let mut rust = Rust::default();
+    let variables = program.variables();
+
+    rust.line("// Generated from a Tiny BASIC program");
+    // BASIC variables start at zero, and names and dead code are kept as written
+    rust.line("#![allow(unused_assignments, unreachable_code, non_snake_case)]");
+    if uses_input(program) {
+        rust.line("use std::io;\nuse std::sync::Mutex;\n");
+    }
+
+    rust.line("fn main() {");
+    rust.indent += 1;
+    for name in &variables {
+        let assigned = assigns(program, name);
+        let declaration = if assigned { "let mut" } else { "let" };
+        rust.line(&format!("{} {}: f64 = 0.0;", declaration, rust_name(name)));
+    }
+
+    let labels = labels(&program.statements);
+    let nested = count_labels(&program.statements) > labels.len() || uses_gosub(program);
+    if nested {
+        rust.blocks(&Cfg::build(program));
+    } else if labels.is_empty() {
+        rust.statements(&program.statements);
+    } else {
+        rust.segments(&program.statements, &labels);
+    }
+
+    rust.indent -= 1;
+    rust.line("}");
+
+    if prints_numbers(program) {
+        rust.text.push_str(FORMAT_FUNCTION);
+    }
+    if uses_input(program) {
+        rust.text.push_str(INPUT_FUNCTION);
+    }
+    if uses_files(program) {
+        rust.text.push_str(FILE_FUNCTIONS);
+    }
+    if calls(program, Function::Sgn) {
+        rust.text.push_str(SGN_FUNCTION);
+    }
+    if uses_gosub(program) {
+        rust.text.push_str(GOSUB_FUNCTIONS);
+    }
+    if randomizes(program) || calls(program, Function::Rnd) {
+        rust.text.push_str(RANDOM_STATE);
+    }
+    if calls(program, Function::Rnd) {
+        rust.text.push_str(RND_FUNCTION);
+    }
+    if randomizes(program) {
+        rust.text.push_str(RANDOMIZE_FUNCTION);
+    }
+    rust.text
+}
+
+const FORMAT_FUNCTION: &str = r#"
+/// Formats like `printf("%*.*f")` in C, with NaN always spelled "nan".
+fn format_number(value: f64, width: usize, decimals: usize) -> String {
+    let text = if value.is_nan() {
+        String::from("nan")
+    } else {
+        format!("{:.*}", decimals, value)
+    };
+    format!("{:>1$}", text, width)
+}
+"#;
+
+const INPUT_FUNCTION: &str = r#"
+/// Reads the next number from stdin. A word that is not a number reads as 0,
+/// running out of input stops the program.
+fn input(statement: u32) -> f64 {
+    static WORDS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+    let mut words = WORDS.lock().unwrap();
+
+    while words.is_empty() {
+        // Show prompts printed without a newline before waiting
+        let _ = io::Write::flush(&mut io::stdout());
+        let mut line = String::new();
+        if io::stdin().read_line(&mut line).unwrap_or(0) == 0 {
+            eprintln!("Runtime error at line {}: end of input", statement);
+            std::process::exit(1);
+        }
+        words.extend(line.split_whitespace().rev().map(String::from));
+    }
+
+    words.pop().unwrap().parse().unwrap_or(0.0)
+}
+"#;
+
+const FILE_FUNCTIONS: &str = r#"
+/// A file opened with OPEN. Input files are read whole and split into words,
+/// the next one last, output is written straight through.
+enum OpenFile {
+    Input(Vec<String>),
+    Output(std::fs::File),
+}
+
+/// Files by number, from 1 to 8. Every use checks the file is open the right
+/// way, like `tb_file` in the C runtime.
+fn files() -> std::sync::MutexGuard<'static, Vec<Option<OpenFile>>> {
+    static FILES: std::sync::Mutex<Vec<Option<OpenFile>>> = std::sync::Mutex::new(Vec::new());
+    let mut files = FILES.lock().unwrap();
+    if files.is_empty() {
+        files.resize_with(9, || None);
+    }
+    files
+}
+
+fn file_error(statement: u32, message: String) -> ! {
+    let _ = std::io::Write::flush(&mut std::io::stdout());
+    eprintln!("Runtime error at line {}: {}", statement, message);
+    std::process::exit(1);
+}
+
+fn open_file(file: usize, path: &str, mode: &str, statement: u32) {
+    let mut files = files();
+    if files[file].is_some() {
+        file_error(statement, format!("file #{} is already open", file));
+    }
+
+    let opened = match mode {
+        "r" => std::fs::read_to_string(path)
+            .map(|text| OpenFile::Input(text.split_whitespace().rev().map(String::from).collect())),
+        "w" => std::fs::File::create(path).map(OpenFile::Output),
+        _ => std::fs::OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(path)
+            .map(OpenFile::Output),
+    };
+    match opened {
+        Ok(opened) => files[file] = Some(opened),
+        Err(_) => file_error(statement, format!("cannot open \"{}\"", path)),
+    }
+}
+
+fn close_file(file: usize, statement: u32) {
+    if files()[file].take().is_none() {
+        file_error(statement, format!("file #{} is not open", file));
+    }
+}
+
+fn write_file(file: usize, text: &str, statement: u32) {
+    match &mut files()[file] {
+        Some(OpenFile::Output(output)) => std::io::Write::write_all(output, text.as_bytes()).unwrap(),
+        Some(_) => file_error(statement, format!("file #{} is not open for output", file)),
+        None => file_error(statement, format!("file #{} is not open", file)),
+    }
+}
+
+/// The words left in an input file.
+fn file_words(files: &mut [Option<OpenFile>], file: usize, statement: u32) -> &mut Vec<String> {
+    match &mut files[file] {
+        Some(OpenFile::Input(words)) => words,
+        Some(_) => file_error(statement, format!("file #{} is not open for input", file)),
+        None => file_error(statement, format!("file #{} is not open", file)),
+    }
+}
+
+fn read_file(file: usize, statement: u32) -> f64 {
+    match file_words(&mut files(), file, statement).pop() {
+        Some(word) => word.parse().unwrap_or(0.0),
+        None => file_error(statement, format!("end of file #{}", file)),
+    }
+}
+
+fn eof(file: usize, statement: u32) -> f64 {
+    f64::from(file_words(&mut files(), file, statement).is_empty())
+}
+"#;
+
+const SGN_FUNCTION: &str = r#"
+/// -1, 0 or 1, and 0 for NaN like the C runtime.
+fn sgn(value: f64) -> f64 {
+    f64::from(value > 0.0) - f64::from(value < 0.0)
+}
+"#;
+
+const GOSUB_FUNCTIONS: &str = r#"
+/// Keeps the state a RETURN goes back to, at most 256 like `tb_gosub` in the C
+/// runtime.
+fn gosub(returns: &mut Vec<usize>, resume: usize, statement: u32) {
+    if returns.len() == 256 {
+        gosub_error(statement, "GOSUB nested too deeply");
+    }
+    returns.push(resume);
+}
+
+fn resume(returns: &mut Vec<usize>, statement: u32) -> usize {
+    match returns.pop() {
+        Some(resume) => resume,
+        None => gosub_error(statement, "RETURN without GOSUB"),
+    }
+}
+
+fn gosub_error(statement: u32, message: &str) -> ! {
+    let _ = std::io::Write::flush(&mut std::io::stdout());
+    eprintln!("Runtime error at line {}: {}", statement, message);
+    std::process::exit(1);
+}
+"#;
+
+const RANDOM_STATE: &str = r#"
+static RANDOM_STATE: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+"#;
+
+const RND_FUNCTION: &str = r#"
+/// The same 64-bit LCG as `tb_rnd` in the C runtime, scaled to [0, scale).
+fn rnd(scale: f64) -> f64 {
+    let state = RANDOM_STATE
+        .load(std::sync::atomic::Ordering::Relaxed)
+        .wrapping_mul(6364136223846793005)
+        .wrapping_add(1442695040888963407);
+    RANDOM_STATE.store(state, std::sync::atomic::Ordering::Relaxed);
+    (state >> 11) as f64 / (1u64 << 53) as f64 * scale
+}
+"#;
+
+const RANDOMIZE_FUNCTION: &str = r#"
+fn randomize(seed: f64) {
+    RANDOM_STATE.store(seed as i64 as u64, std::sync::atomic::Ordering::Relaxed);
+}
+"#;
+
+fn prints_numbers(program: &Program) -> bool {
+    let mut prints = false;
+    program.walk(&mut |statement| {
+        if let StatementKind::Print { items, .. } = &statement.kind {
+            prints |= items
+                .iter()
+                .any(|item| matches!(item, PrintItem::Number(_)));
+        }
+    });
+    prints
+}
+
+fn uses_input(program: &Program) -> bool {
+    let mut input = false;
+    program.walk(&mut |statement| {
+        input |= matches!(statement.kind, StatementKind::Input(None, _));
+    });
+    input
+}
+
+fn randomizes(program: &Program) -> bool {
+    let mut randomize = false;
+    program.walk(&mut |statement| {
+        randomize |= matches!(statement.kind, StatementKind::Randomize(_));
+    });
+    randomize
+}
+
+fn uses_files(program: &Program) -> bool {
+    let mut files = false;
+    program.walk(&mut |statement| {
+        files |= matches!(
+            statement.kind,
+            StatementKind::Open { .. }
+                | StatementKind::Close(_)
+                | StatementKind::Print { file: Some(_), .. }
+                | StatementKind::Input(Some(_), _)
+        );
+    });
+    files
+        || finds(program, &|expression| {
+            matches!(expression, Expression::Eof { .. })
+        })
+}
+
+fn uses_gosub(program: &Program) -> bool {
+    let mut gosub = false;
+    program.walk(&mut |statement| {
+        gosub |= matches!(
+            statement.kind,
+            StatementKind::Gosub(_) | StatementKind::Return
+        );
+    });
+    gosub
+}
+
+fn calls(program: &Program, function: Function) -> bool {
+    finds(
+        program,
+        &|expression| matches!(expression, Expression::Call(called, _) if *called == function),
+    )
+}
+
+/// Whether any expression in the program, or part of one, is a match.
+fn finds(program: &Program, matches: &impl Fn(&Expression) -> bool) -> bool {
+    fn contains(expression: &Expression, matches: &impl Fn(&Expression) -> bool) -> bool {
+        matches(expression)
+            || match expression {
+                Expression::Number(_) | Expression::Variable(_) | Expression::Eof { .. } => false,
+                Expression::Unary(_, operand) => contains(operand, matches),
+                Expression::Binary(_, left, right) => {
+                    contains(left, matches) || contains(right, matches)
+                }
+                Expression::Call(_, arguments) => {
+                    arguments.iter().any(|argument| contains(argument, matches))
+                }
+            }
+    }
+
+    let mut found = false;
+    program.walk(&mut |statement| {
+        found |= statement
+            .expressions()
+            .into_iter()
+            .any(|expression| contains(expression, matches));
+    });
+    found
+}
+
+fn assigns(program: &Program, name: &str) -> bool {
+    let mut assigned = false;
+    program.walk(&mut |statement| match &statement.kind {
+        StatementKind::Let(variable, _) | StatementKind::Input(_, variable) => {
+            assigned |= variable == name
+        }
+        _ => (),
+    });
+    assigned
+}
+
+/// Labels at the top level of the program, in order.
+fn labels(statements: &[Statement]) -> Vec<&str> {
+    statements
+        .iter()
+        .filter_map(|statement| match &statement.kind {
+            StatementKind::Label(name) => Some(name.as_str()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn count_labels(statements: &[Statement]) -> usize {
+    statements
+        .iter()
+        .map(|statement| match &statement.kind {
+            StatementKind::Label(_) => 1,
+            StatementKind::If { body, .. } | StatementKind::While { body, .. } => {
+                count_labels(body)
+            }
+            _ => 0,
+        })
+        .sum()
+}
+
+/// Reserved names and names already ending in `_` get an underscore appended,
+/// and `a$` and `a%` become `a_str_` and `a_int_`. No two BASIC names end up
+/// the same, since dropping the last underscore tells the cases apart.
+pub fn rust_name(name: &str) -> String {
+    match name.chars().last() {
+        Some('$') => format!("{}_str_", &name[..name.len() - 1]),
+        Some('%') => format!("{}_int_", &name[..name.len() - 1]),
+        _ if RESERVED.contains(&name) || name.ends_with('_') => format!("{}_", name),
+        _ => name.to_string(),
+    }
+}
+
+#[derive(Default)]
+struct Rust {
+    text: String,
+    indent: usize,
+    /// Top level labels in order, state `n + 1` starts at label `n`.
+    labels: Vec<String>,
+    /// How many WHILE loops the current statement is inside of.
+    loops: usize,
+    /// Whether a GOTO inside a WHILE needs the state loop to be labelled.
+    labelled: bool,
+}
+
+impl Rust {
+    fn line(&mut self, line: &str) {
+        for line in line.split('\n') {
+            if !line.is_empty() {
+                self.text.push_str(&"    ".repeat(self.indent));
+            }
+            self.text.push_str(line);
+            self.text.push('\n');
+        }
+    }
+
+    fn statements(&mut self, statements: &[Statement]) {
+        for statement in statements {
+            self.statement(statement);
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match &statement.kind {
+            StatementKind::Print {
+                file: Some(file),
+                items,
+                format,
+                newline,
+            } => {
+                // One write per item, so each is checked after its value is worked out
+                for item in items {
+                    let text = match item {
+                        PrintItem::Text(text) => format!("{:?}", text),
+                        PrintItem::Tab => "\"\\t\"".to_string(),
+                        PrintItem::Number(expression) => format!(
+                            "&format_number({}, {}, {})",
+                            rust_expression(expression),
+                            format.width,
+                            format.decimals
+                        ),
+                    };
+                    self.line(&format!(
+                        "write_file({}, {}, {});",
+                        file, text, statement.line
+                    ));
+                }
+                if *newline {
+                    self.line(&format!(
+                        "write_file({}, \"\\n\", {});",
+                        file, statement.line
+                    ));
+                }
+            }
+            StatementKind::Print {
+                file: None,
+                items,
+                format,
+                newline,
+            } => {
+                // One print! for the whole statement, numbers fill in the placeholders
+                let mut template = String::new();
+                let mut arguments = String::new();
+                for item in items {
+                    match item {
+                        PrintItem::Text(text) => {
+                            template.push_str(&text.replace('{', "{{").replace('}', "}}"))
+                        }
+                        PrintItem::Tab => template.push('\t'),
+                        PrintItem::Number(expression) => {
+                            template.push_str("{}");
+                            arguments.push_str(&format!(
+                                ", format_number({}, {}, {})",
+                                rust_expression(expression),
+                                format.width,
+                                format.decimals
+                            ));
+                        }
+                    }
+                }
+
+                let mac = if *newline { "println!" } else { "print!" };
+                self.line(&format!("{}({:?}{});", mac, template, arguments));
+            }
+            StatementKind::If { condition, body } => {
+                self.line(&format!("if {} {{", rust_condition(condition)));
+                self.block(body);
+            }
+            StatementKind::While { condition, body } => {
+                match constant(condition) {
+                    Some(value) if value != 0.0 => self.line("loop {"),
+                    _ => self.line(&format!("while {} {{", rust_condition(condition))),
+                }
+                self.loops += 1;
+                self.block(body);
+                self.loops -= 1;
+            }
+            StatementKind::Label(name) => self.line(&format!("// {}", name)),
+            StatementKind::Goto(name) => {
+                let state = self.labels.iter().position(|label| label == name).unwrap() + 1;
+                self.line(&format!("state = {};", state));
+
+                if self.loops > 0 {
+                    self.labelled = true;
+                    self.line("continue 'program;");
+                } else {
+                    self.line("continue;");
+                }
+            }
+            StatementKind::Let(name, expression) => {
+                self.line(&format!(
+                    "{} = {};",
+                    rust_name(name),
+                    rust_expression(expression)
+                ));
+            }
+            StatementKind::Input(None, name) => {
+                self.line(&format!("{} = input({});", rust_name(name), statement.line));
+            }
+            StatementKind::Input(Some(file), name) => self.line(&format!(
+                "{} = read_file({}, {});",
+                rust_name(name),
+                file,
+                statement.line
+            )),
+            StatementKind::Open { path, mode, file } => self.line(&format!(
+                "open_file({}, {:?}, {:?}, {});",
+                file,
+                path,
+                mode.c_mode(),
+                statement.line
+            )),
+            StatementKind::Close(file) => {
+                self.line(&format!("close_file({}, {});", file, statement.line))
+            }
+            StatementKind::Randomize(seed) => {
+                self.line(&format!("randomize({});", rust_expression(seed)))
+            }
+            StatementKind::Break => self.line("break;"),
+            StatementKind::Gosub(_) | StatementKind::Return => {
+                unreachable!("GOSUB and RETURN are written by the basic block fallback")
+            }
+        }
+    }
+
+    fn block(&mut self, body: &[Statement]) {
+        self.indent += 1;
+        self.statements(body);
+        self.indent -= 1;
+        self.line("}");
+    }
+
+    /// Splits the program at its labels, each piece is one state.
+    fn segments(&mut self, statements: &[Statement], labels: &[&str]) {
+        self.labels = labels.iter().map(|label| label.to_string()).collect();
+
+        let mut segments: Vec<&[Statement]> = Vec::new();
+        let mut start = 0;
+        for (index, statement) in statements.iter().enumerate() {
+            if matches!(statement.kind, StatementKind::Label(_)) {
+                segments.push(&statements[start..index]);
+                start = index;
+            }
+        }
+        segments.push(&statements[start..]);
+
+        // Write the arms first, the loop label is only needed if a GOTO inside a WHILE uses it
+        let outer = std::mem::take(&mut self.text);
+        let indent = self.indent;
+        self.indent = indent + 2;
+
+        for (state, segment) in segments.iter().enumerate() {
+            self.line(&format!("{} => {{", state));
+            self.indent += 1;
+            self.statements(segment);
+
+            let ends_in_goto = matches!(
+                segment.last().map(|statement| &statement.kind),
+                Some(StatementKind::Goto(_))
+            );
+            if ends_in_goto {
+                // The GOTO already picked the next state
+            } else if state + 1 == segments.len() {
+                self.line("break;");
+            } else {
+                self.line(&format!("state = {};", state + 1));
+            }
+
+            self.indent -= 1;
+            self.line("}");
+        }
+        self.line("_ => unreachable!(),");
+
+        let arms = std::mem::replace(&mut self.text, outer);
+        self.indent = indent;
+        self.line("let mut state = 0;");
+        self.line(if self.labelled {
+            "'program: loop {"
+        } else {
+            "loop {"
+        });
+        self.line("    match state {");
+        self.text.push_str(&arms);
+        self.line("    }\n}");
+    }
+
+    /// Fallback for labels inside IF or WHILE and for GOSUB, one state per
+    /// basic block.
+    fn blocks(&mut self, cfg: &Cfg) {
+        let reachable = cfg.reachable();
+
+        let gosubs = cfg
+            .blocks
+            .iter()
+            .flat_map(|block| &block.statements)
+            .any(|statement| {
+                matches!(
+                    statement.kind,
+                    StatementKind::Gosub(_) | StatementKind::Return
+                )
+            });
+        if gosubs {
+            self.line("let mut returns = Vec::new();");
+        }
+        self.line(&format!("let mut state = {};", cfg.entry));
+        self.line("loop {\n    match state {");
+        self.indent += 2;
+
+        for (index, block) in cfg.blocks.iter().enumerate() {
+            if !reachable.contains(&index) {
+                continue;
+            }
+
+            self.line(&format!("{} => {{", index));
+            self.indent += 1;
+
+            let mut condition = None;
+            let mut returning = None;
+            for statement in &block.statements {
+                match &statement.kind {
+                    StatementKind::If {
+                        condition: test, ..
+                    }
+                    | StatementKind::While {
+                        condition: test, ..
+                    } => condition = Some(test),
+                    // The block's successor already is the label or loop exit
+                    StatementKind::Goto(_) | StatementKind::Break => (),
+                    StatementKind::Gosub(_) => {
+                        let (_, resume) = cfg
+                            .resumes
+                            .iter()
+                            .find(|(gosub, _)| *gosub == index)
+                            .unwrap();
+                        self.line(&format!(
+                            "gosub(&mut returns, {}, {});",
+                            resume, statement.line
+                        ));
+                    }
+                    // Every GOSUB's resume is a successor, the stack picks one
+                    StatementKind::Return => returning = Some(statement.line),
+                    _ => self.statement(statement),
+                }
+            }
+
+            let line = match (condition, returning, block.successors.as_slice()) {
+                (_, Some(line), _) => format!("state = resume(&mut returns, {});", line),
+                (Some(condition), None, [then, otherwise]) => format!(
+                    "state = if {} {{ {} }} else {{ {} }};",
+                    rust_condition(condition),
+                    then,
+                    otherwise
+                ),
+                (_, None, [next]) => format!("state = {};", next),
+                _ => String::from("break;"),
+            };
+            self.line(&line);
+
+            self.indent -= 1;
+            self.line("}");
+        }
+
+        self.line("_ => unreachable!(),");
+        self.indent -= 2;
+        self.line("    }\n}");
+    }
+}
+
+/// The value of a condition made only of constants, like `1 == 1`.
+fn constant(expression: &Expression) -> Option<f64> {
+    match expression {
+        Expression::Number(value) => Some(*value),
+        Expression::Variable(_) => None,
+        Expression::Unary(operator, operand) => Some(operator.evaluate(constant(operand)?)),
+        Expression::Binary(operator, left, right) => {
+            Some(operator.evaluate(constant(left)?, constant(right)?))
+        }
+        Expression::Call(function, arguments) => {
+            let values: Option<Vec<f64>> = arguments.iter().map(constant).collect();
+            function.evaluate(&values?)
+        }
+        Expression::Eof { .. } => None,
+    }
+}
+
+fn number(value: f64) -> String {
+    if value.is_nan() {
+        String::from("f64::NAN")
+    } else if value.is_infinite() {
+        let sign = if value < 0.0 { "-" } else { "" };
+        format!("{}f64::INFINITY", sign)
+    } else {
+        // Debug formatting is always a valid float literal
+        format!("{:?}", value)
+    }
+}
+
+/// Renders a condition as a Rust `bool`. A comparison at the top is used as
+/// is, anything else is true when non-zero, like in C.
+fn rust_condition(expression: &Expression) -> String {
+    match expression {
+        Expression::Binary(operator, left, right) if operator.is_comparison() => format!(
+            "{} {} {}",
+            rust_expression(left),
+            operator.symbol(),
+            rust_expression(right)
+        ),
+        _ => format!("{} != 0.0", rust_expression(expression)),
+    }
+}
+
+/// Renders an expression as an `f64`. Rust comparisons give `bool` and do not
+/// chain, so each is converted back with `f64::from`.
+fn rust_expression(expression: &Expression) -> String {
+    match expression {
+        Expression::Number(value) => number(*value),
+        Expression::Variable(name) => rust_name(name),
+        // Rust has no unary plus, and it never changes the value
+        Expression::Unary(UnaryOperator::Plus, operand) => rust_operand(operand, u8::MAX),
+        Expression::Unary(UnaryOperator::Minus, operand) => match operand.as_ref() {
+            // Keep nested negation readable rather than writing `--x`
+            Expression::Unary(UnaryOperator::Minus, _) => {
+                format!("-({})", rust_expression(operand))
+            }
+            _ => format!("-{}", rust_operand(operand, u8::MAX)),
+        },
+        Expression::Unary(UnaryOperator::Not, operand) => {
+            format!("f64::from({} == 0.0)", rust_operand(operand, u8::MAX))
+        }
+        Expression::Binary(operator, left, right) if operator.is_comparison() => format!(
+            "f64::from({})",
+            rust_condition(&Expression::Binary(*operator, left.clone(), right.clone()))
+        ),
+        Expression::Binary(BinaryOperator::IntegerDivide, left, right) => {
+            let precedence = BinaryOperator::Divide.precedence();
+            // A method call on two literals would be an ambiguous `{float}`
+            format!(
+                "f64::trunc({} / {})",
+                rust_operand(left, precedence),
+                rust_operand(right, precedence + 1)
+            )
+        }
+        Expression::Binary(BinaryOperator::Power, left, right) => format!(
+            "f64::powf({}, {})",
+            rust_expression(left),
+            rust_expression(right)
+        ),
+        Expression::Binary(operator, left, right) => {
+            let precedence = operator.precedence();
+            let symbol = match operator {
+                BinaryOperator::Modulo => "%",
+                _ => operator.symbol(),
+            };
+            format!(
+                "{} {} {}",
+                rust_operand(left, precedence),
+                symbol,
+                // Operators are left associative, so an equal-precedence right side needs grouping
+                rust_operand(right, precedence + 1)
+            )
+        }
+        Expression::Eof { file, line } => format!("eof({}, {})", file, line),
+        // `%` on floats is fmod
+        Expression::Call(Function::Mod, arguments) => rust_expression(&Expression::Binary(
+            BinaryOperator::Modulo,
+            Box::new(arguments[0].clone()),
+            Box::new(arguments[1].clone()),
+        )),
+        Expression::Call(function, arguments) => {
+            let arguments: Vec<String> = arguments.iter().map(rust_expression).collect();
+            format!("{}({})", rust_function(*function), arguments.join(", "))
+        }
+    }
+}
+
+fn rust_function(function: Function) -> &'static str {
+    match function {
+        Function::Abs => "f64::abs",
+        Function::Sqr => "f64::sqrt",
+        Function::Int => "f64::floor",
+        Function::Sgn => "sgn",
+        Function::Mod => unreachable!("MOD is written as %"),
+        Function::Sin => "f64::sin",
+        Function::Cos => "f64::cos",
+        Function::Tan => "f64::tan",
+        Function::Atn => "f64::atan",
+        Function::Exp => "f64::exp",
+        Function::Log => "f64::ln",
+        Function::Rnd => "rnd",
+    }
+}
+
+fn rust_operand(expression: &Expression, min_precedence: u8) -> String {
+    match expression {
+        // Written as calls, which need no grouping
+        Expression::Binary(BinaryOperator::IntegerDivide | BinaryOperator::Power, _, _) => {
+            rust_expression(expression)
+        }
+        Expression::Binary(operator, _, _)
+            if !operator.is_comparison() && operator.precedence() < min_precedence =>
+        {
+            format!("({})", rust_expression(expression))
+        }
+        Expression::Call(Function::Mod, _)
+            if BinaryOperator::Modulo.precedence() < min_precedence =>
+        {
+            format!("({})", rust_expression(expression))
+        }
+        _ => rust_expression(expression),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lex::Lexer;
+    use crate::parser::Parser;
+    use crate::testing::{build, cc_available, programs, run, INPUT, SHORT_INPUT};
+    use crate::Target;
+    use std::process::Command;
+
+    fn generated(source: &str) -> String {
+        generate(&Parser::new(Lexer::new(format!("{}\n\0", source))).program())
+    }
+
+    #[test]
+    fn it_keeps_structured_code_structured() {
+        assert_eq!(
+            generated(
+                "LET a = 0\nWHILE a < 3 REPEAT\nIF a < 2 == 1 THEN\nPRINT -a * 2\nENDIF\nLET a = a + 1\nENDWHILE"
+            ),
+            "// Generated from a Tiny BASIC program
+#![allow(unused_assignments, unreachable_code, non_snake_case)]
+fn main() {
+    let mut a: f64 = 0.0;
+    a = 0.0;
+    while a < 3.0 {
+        if f64::from(a < 2.0) == 1.0 {
+            println!(\"{}\", format_number(-a * 2.0, 0, 2));
+        }
+        a = a + 1.0;
+    }
+}
+"
+            .to_string()
+                + FORMAT_FUNCTION
+        );
+    }
+
+    #[test]
+    fn it_turns_top_level_labels_into_states() {
+        assert_eq!(
+            generated("LABEL top\nINPUT loop\nWHILE loop > 0 REPEAT\nGOTO top\nENDWHILE"),
+            "// Generated from a Tiny BASIC program
+#![allow(unused_assignments, unreachable_code, non_snake_case)]
+use std::io;
+use std::sync::Mutex;
+
+fn main() {
+    let mut loop_: f64 = 0.0;
+    let mut state = 0;
+    'program: loop {
+        match state {
+            0 => {
+                state = 1;
+            }
+            1 => {
+                // top
+                loop_ = input(2);
+                while loop_ > 0.0 {
+                    state = 1;
+                    continue 'program;
+                }
+                break;
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+"
+            .to_string()
+                + INPUT_FUNCTION
+        );
+    }
+
+    #[test]
+    fn it_falls_back_to_basic_blocks_for_nested_labels() {
+        let rust = generated("IF 0 < 1 THEN\nLABEL inner\nPRINT 1\nENDIF\nGOTO inner");
+        assert!(rust.contains("state = if 0.0 < 1.0 { 1 } else { 2 };"));
+        assert!(
+            rust.contains("// inner\n                println!(\"{}\", format_number(1.0, 0, 2));")
+        );
+    }
+
+    #[test]
+    fn it_renames_variables_named_like_generated_code() {
+        for name in ["resume", "returns", "gosub_error", "file_words", "OpenFile"] {
+            assert_eq!(rust_name(name), format!("{}_", name));
+        }
+    }
+
+    #[test]
+    fn it_compiles_and_matches_the_c_backend() {
+        let rustc = Command::new("rustc").arg("--version").output().is_ok();
+        if !rustc || !cc_available() {
+            eprintln!("Skipping, rustc or cc not found");
+            return;
+        }
+
+        for program in programs() {
+            let name = program.file_stem().unwrap().to_str().unwrap();
+            let c = build(&program, &format!("rust-c-{}", name), Target::C);
+
+            let rust = format!("rust-{}", name);
+            let options = crate::Options {
+                target: Target::Rust,
+                ..crate::Options::default()
+            };
+            crate::compile(program.to_str().unwrap(), &rust, &options).unwrap();
+
+            let binary = std::path::PathBuf::from(format!("output/{}", rust));
+            let compiled = Command::new("rustc")
+                .arg("--edition=2021")
+                .arg("-o")
+                .arg(&binary)
+                .arg(format!("output/{}.rs", rust))
+                .output()
+                .unwrap();
+            assert!(
+                compiled.status.success(),
+                "rustc failed for {}: {}",
+                name,
+                String::from_utf8_lossy(&compiled.stderr)
+            );
+            assert_eq!(
+                String::from_utf8_lossy(&compiled.stderr),
+                "",
+                "rustc warned for {}",
+                name
+            );
+
+            for input in [INPUT, SHORT_INPUT] {
+                assert_eq!(run(&binary, input), run(&c, input), "{}", name);
+            }
+        }
+    }
+}
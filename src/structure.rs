@@ -0,0 +1,934 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::{BinaryOperator, Expression, Program, Statement, StatementKind, UnaryOperator};
+use crate::cfg::Cfg;
+
+/// Rewrites a program that uses GOTO into one built only from WHILE, IF and
+/// BREAK, for backends without goto and for the `--modernize` tool.
+///
+/// Loops are recovered from the back edges of the control-flow graph and
+/// branches from where their two sides meet again. Code that cannot be
+/// structured, because it is part of an irreducible cycle, needs CONTINUE or
+/// leaves a loop in more than one place, becomes a dispatcher instead: a WHILE
+/// loop around one IF per basic block, with a fresh variable holding the block
+/// to run next. The dispatcher only runs until the first block every path out
+/// of such code reaches, everything around it stays structured.
+///
+/// Programs with GOSUB or RETURN are left as they are, since where a RETURN
+/// goes is only known at runtime.
+pub fn structure(program: &Program) -> Program {
+    let mut uses_goto = false;
+    let mut uses_gosub = false;
+    program.walk(&mut |statement| {
+        uses_goto |= matches!(statement.kind, StatementKind::Goto(_));
+        uses_gosub |= matches!(
+            statement.kind,
+            StatementKind::Gosub(_) | StatementKind::Return
+        );
+    });
+    if !uses_goto || uses_gosub {
+        return program.clone();
+    }
+
+    let cfg = Cfg::build(program);
+    let used = program.variables();
+    let mut structurer = Structurer::new(&cfg, fresh("flag", &used), fresh("state", &used));
+    structurer.loops();
+
+    let mut statements = Vec::new();
+    if structurer
+        .region(cfg.entry, Some(cfg.exit), None, &mut statements)
+        .is_err()
+    {
+        // With nothing emitted yet, the whole program always fits in one dispatcher
+        statements.clear();
+        structurer.emitted.clear();
+        let _ = structurer.dispatch(cfg.entry, Some(cfg.exit), None, &mut statements);
+    }
+
+    let mut id = 0;
+    renumber(&mut statements, &mut id);
+    Program {
+        statements,
+        files: program.files.clone(),
+    }
+}
+
+/// A variable name the program does not use yet.
+fn fresh(name: &str, used: &[&str]) -> String {
+    let mut name = name.to_string();
+    while used.contains(&name.as_str()) {
+        name.push('x');
+    }
+    name
+}
+
+fn renumber(statements: &mut [Statement], id: &mut usize) {
+    for statement in statements {
+        statement.id = *id;
+        *id += 1;
+
+        if let StatementKind::If { body, .. } | StatementKind::While { body, .. } =
+            &mut statement.kind
+        {
+            renumber(body, id);
+        }
+    }
+}
+
+/// Statement ids are filled in once the whole program is built. Linting is
+/// already done, so new statements only keep a line for reference.
+fn statement(line: usize, kind: StatementKind) -> Statement {
+    Statement {
+        id: 0,
+        line,
+        file: 0,
+        kind,
+    }
+}
+
+fn number(value: f64) -> Box<Expression> {
+    Box::new(Expression::Number(value))
+}
+
+fn compare(operator: BinaryOperator, left: Expression, right: f64) -> Expression {
+    Expression::Binary(operator, Box::new(left), number(right))
+}
+
+/// True exactly when the condition is false, NaN included. NaN makes `==`
+/// false and `<>` true, so those swap; ordered comparisons get NOT instead.
+/// Only for conditions, since `NOT NOT x` comes back as `x` rather than 0 or 1.
+fn negate(condition: &Expression) -> Expression {
+    match condition {
+        Expression::Binary(BinaryOperator::Equal, left, right) => {
+            Expression::Binary(BinaryOperator::NotEqual, left.clone(), right.clone())
+        }
+        Expression::Binary(BinaryOperator::NotEqual, left, right) => {
+            Expression::Binary(BinaryOperator::Equal, left.clone(), right.clone())
+        }
+        Expression::Unary(UnaryOperator::Not, operand) => operand.as_ref().clone(),
+        _ => Expression::Unary(UnaryOperator::Not, Box::new(condition.clone())),
+    }
+}
+
+fn assigns(statements: &[Statement], names: &[&str]) -> bool {
+    statements.iter().any(|statement| match &statement.kind {
+        StatementKind::Let(name, _) | StatementKind::Input(_, name) => {
+            names.contains(&name.as_str())
+        }
+        StatementKind::If { body, .. } | StatementKind::While { body, .. } => assigns(body, names),
+        _ => false,
+    })
+}
+
+/// Stands for the end of a region when looking for where branches meet.
+const END: usize = usize::MAX;
+
+/// The loop a region is inside of.
+#[derive(Clone, Copy)]
+struct Context {
+    header: usize,
+    /// Where BREAK goes, `None` for a loop that never ends.
+    exit: Option<usize>,
+}
+
+/// What `Structurer::step` left for the rest of a region.
+enum Step {
+    /// Carry on from this block.
+    Next(usize),
+    /// The region is done, `true` when control gets to its follow.
+    Done(bool),
+}
+
+struct Structurer<'c, 'a> {
+    cfg: &'c Cfg<'a>,
+    reachable: HashSet<usize>,
+    /// Natural loop bodies, keyed by header.
+    loops: HashMap<usize, HashSet<usize>>,
+    emitted: HashSet<usize>,
+    /// Variable for telling apart the two sides of an IF without ELSE.
+    flag: String,
+    /// Variable holding the block a dispatcher runs next.
+    state: String,
+}
+
+impl<'c, 'a> Structurer<'c, 'a> {
+    fn new(cfg: &'c Cfg<'a>, flag: String, state: String) -> Self {
+        Structurer {
+            cfg,
+            reachable: cfg.reachable(),
+            loops: HashMap::new(),
+            emitted: HashSet::new(),
+            flag,
+            state,
+        }
+    }
+
+    fn successors(&self, block: usize) -> &[usize] {
+        &self.cfg.blocks[block].successors
+    }
+
+    /// Finds the natural loops. A cycle that can be entered other than through
+    /// its header is no loop, `region` runs into one of its blocks twice and
+    /// leaves it to a dispatcher.
+    fn loops(&mut self) {
+        let dominators = self.dominators();
+
+        // Depth-first search, an edge back to a block still on the stack closes a cycle
+        let mut back_edges = Vec::new();
+        let mut on_stack = HashSet::new();
+        let mut visited = HashSet::new();
+        let mut stack = vec![(self.cfg.entry, 0)];
+        on_stack.insert(self.cfg.entry);
+        visited.insert(self.cfg.entry);
+
+        while let Some((block, next)) = stack.pop() {
+            let Some(&successor) = self.successors(block).get(next) else {
+                on_stack.remove(&block);
+                continue;
+            };
+            stack.push((block, next + 1));
+
+            if on_stack.contains(&successor) {
+                if dominators[&block].contains(&successor) {
+                    back_edges.push((block, successor));
+                }
+            } else if visited.insert(successor) {
+                on_stack.insert(successor);
+                stack.push((successor, 0));
+            }
+        }
+
+        for (tail, header) in back_edges {
+            let body = self
+                .loops
+                .entry(header)
+                .or_insert_with(|| HashSet::from([header]));
+            let mut work = vec![tail];
+
+            while let Some(block) = work.pop() {
+                if body.insert(block) {
+                    work.extend(
+                        self.cfg
+                            .blocks
+                            .iter()
+                            .enumerate()
+                            .filter(|(_, other)| other.successors.contains(&block))
+                            .map(|(predecessor, _)| predecessor),
+                    );
+                }
+            }
+        }
+    }
+
+    fn dominators(&self) -> HashMap<usize, HashSet<usize>> {
+        let mut dominators: HashMap<usize, HashSet<usize>> = self
+            .reachable
+            .iter()
+            .map(|&block| (block, self.reachable.clone()))
+            .collect();
+        dominators.insert(self.cfg.entry, HashSet::from([self.cfg.entry]));
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+
+            for &block in &self.reachable {
+                if block == self.cfg.entry {
+                    continue;
+                }
+
+                let mut dominated: Option<HashSet<usize>> = None;
+                for (predecessor, other) in self.cfg.blocks.iter().enumerate() {
+                    if !other.successors.contains(&block) || !self.reachable.contains(&predecessor)
+                    {
+                        continue;
+                    }
+                    let theirs = &dominators[&predecessor];
+                    dominated = Some(match dominated {
+                        Some(ours) => ours.intersection(theirs).copied().collect(),
+                        None => theirs.clone(),
+                    });
+                }
+
+                let mut dominated = dominated.unwrap_or_default();
+                dominated.insert(block);
+                if dominated != dominators[&block] {
+                    dominators.insert(block, dominated);
+                    changed = true;
+                }
+            }
+        }
+
+        dominators
+    }
+
+    /// Structures the code from `entry` until control reaches `follow`. Returns
+    /// whether control can get there, rather than always leaving with BREAK or
+    /// looping forever. When a block cannot be structured, the code from it or
+    /// from an earlier block of the region goes into a dispatcher, an error is
+    /// left to the enclosing region when neither works.
+    fn region(
+        &mut self,
+        entry: usize,
+        follow: Option<usize>,
+        context: Option<Context>,
+        out: &mut Vec<Statement>,
+    ) -> Result<bool, ()> {
+        let mut current = entry;
+        let mut first = true;
+        // Where each step started, to go back to when a later one fails
+        let mut checkpoints: Vec<(usize, usize, HashSet<usize>)> = Vec::new();
+
+        loop {
+            let inside = context.is_some_and(|context| context.header == current);
+            if !first && Some(current) == follow {
+                return Ok(true);
+            }
+            if context.is_some_and(|context| context.exit == Some(current)) {
+                out.push(statement(self.line(current), StatementKind::Break));
+                return Ok(false);
+            }
+            // Going back to the header anywhere but the end of the body needs CONTINUE
+            if !first && inside {
+                return Err(());
+            }
+
+            checkpoints.push((current, out.len(), self.emitted.clone()));
+            let step = match self.step(current, first && inside, follow, context, out) {
+                Ok(step) => step,
+                Err(()) => loop {
+                    let Some((block, length, emitted)) = checkpoints.pop() else {
+                        return Err(());
+                    };
+                    out.truncate(length);
+                    self.emitted = emitted;
+                    if let Ok(step) = self.fallback(block, follow, context, out) {
+                        break step;
+                    }
+                },
+            };
+            match step {
+                Step::Next(next) => current = next,
+                Step::Done(falls) => return Ok(falls),
+            }
+            first = false;
+        }
+    }
+
+    /// Structures the loop or branch at `block`, or copies its statements.
+    /// `body` is set for a loop header starting its own body.
+    fn step(
+        &mut self,
+        block: usize,
+        body: bool,
+        follow: Option<usize>,
+        context: Option<Context>,
+        out: &mut Vec<Statement>,
+    ) -> Result<Step, ()> {
+        if self.loops.contains_key(&block) && !body {
+            return Ok(match self.structure_loop(block, out)? {
+                Some(exit) => Step::Next(exit),
+                None => Step::Done(false),
+            });
+        }
+
+        if !self.emitted.insert(block) {
+            return Err(());
+        }
+        let condition = self.simple_statements(block, out);
+
+        match (condition, self.successors(block)) {
+            (Some(condition), &[then, otherwise]) => {
+                let join = self.join(block, follow, context).or(follow);
+                let line = self.line(block);
+
+                let mut then_code = Vec::new();
+                let then_falls =
+                    Some(then) == join || self.region(then, join, context, &mut then_code)?;
+                let mut otherwise_code = Vec::new();
+                let otherwise_falls = Some(otherwise) == join
+                    || self.region(otherwise, join, context, &mut otherwise_code)?;
+
+                self.branch(
+                    line,
+                    condition,
+                    (then_code, then_falls),
+                    (otherwise_code, otherwise_falls),
+                    out,
+                );
+
+                if !then_falls && !otherwise_falls {
+                    return Ok(Step::Done(false));
+                }
+                Ok(match join {
+                    Some(join) if Some(join) != follow => Step::Next(join),
+                    _ => Step::Done(true),
+                })
+            }
+            (_, &[next]) => Ok(Step::Next(next)),
+            // Only the program's end has no successors, and it is the top level follow
+            _ => Err(()),
+        }
+    }
+
+    /// Puts the code from `block` into a dispatcher, up to the first block
+    /// every path from it meets at that does not lead back into it.
+    fn fallback(
+        &mut self,
+        block: usize,
+        follow: Option<usize>,
+        context: Option<Context>,
+        out: &mut Vec<Statement>,
+    ) -> Result<Step, ()> {
+        let mut stop = self.join(block, follow, context).or(follow);
+
+        loop {
+            match self.dispatch(block, stop, context, out) {
+                Ok(false) => return Ok(Step::Done(false)),
+                Ok(true) => {
+                    return Ok(match stop {
+                        Some(stop) if Some(stop) != follow => Step::Next(stop),
+                        _ => Step::Done(true),
+                    })
+                }
+                Err(()) => match stop {
+                    Some(next) if Some(next) != follow => {
+                        stop = self.join(next, follow, context).or(follow)
+                    }
+                    _ => return Err(()),
+                },
+            }
+        }
+    }
+
+    /// Runs the blocks from `entry` until control reaches `follow` under
+    /// `IF state == n`, inside a loop that ends when `state` drops to 0 for the
+    /// follow or to -1 for the enclosing loop's exit, which then BREAKs. Fails
+    /// when the blocks go anywhere else, were already emitted or can be reached
+    /// again from the follow.
+    fn dispatch(
+        &mut self,
+        entry: usize,
+        follow: Option<usize>,
+        context: Option<Context>,
+        out: &mut Vec<Statement>,
+    ) -> Result<bool, ()> {
+        let exit = context.and_then(|context| context.exit);
+        let header = context.map(|context| context.header);
+        let target = |block: usize| {
+            if Some(block) == follow {
+                0.0
+            } else if Some(block) == exit {
+                -1.0
+            } else {
+                (block + 1) as f64
+            }
+        };
+
+        let mut blocks = vec![entry];
+        let (mut falls, mut breaks) = (false, false);
+        let mut index = 0;
+        while index < blocks.len() {
+            for &successor in self.successors(blocks[index]) {
+                if Some(successor) == follow {
+                    falls = true;
+                } else if Some(successor) == exit {
+                    breaks = true;
+                } else if Some(successor) == header {
+                    return Err(());
+                } else if !blocks.contains(&successor) {
+                    blocks.push(successor);
+                }
+            }
+            index += 1;
+        }
+        if blocks
+            .iter()
+            .any(|block| *block == self.cfg.exit || self.emitted.contains(block))
+        {
+            return Err(());
+        }
+        // Going round a loop again is fine, it enters the dispatcher afresh
+        if let Some(follow) = follow.filter(|&follow| Some(follow) != header) {
+            let mut seen = vec![follow];
+            let mut index = 0;
+            while index < seen.len() {
+                for &successor in self.successors(seen[index]) {
+                    if blocks.contains(&successor) {
+                        return Err(());
+                    }
+                    if Some(successor) != exit
+                        && Some(successor) != header
+                        && !seen.contains(&successor)
+                    {
+                        seen.push(successor);
+                    }
+                }
+                index += 1;
+            }
+        }
+        self.emitted.extend(&blocks);
+        blocks.sort();
+
+        let assign = |line, value| {
+            statement(
+                line,
+                StatementKind::Let(self.state.clone(), Expression::Number(value)),
+            )
+        };
+        let mut arms = Vec::new();
+        for block in blocks {
+            let line = self.line(block);
+            let mut body = Vec::new();
+
+            match (
+                self.simple_statements(block, &mut body),
+                self.successors(block),
+            ) {
+                (Some(condition), &[then, otherwise]) => {
+                    body.push(assign(line, target(otherwise)));
+                    body.push(statement(
+                        line,
+                        StatementKind::If {
+                            condition,
+                            body: vec![assign(line, target(then))],
+                        },
+                    ));
+                }
+                (_, &[next]) => body.push(assign(line, target(next))),
+                _ => unreachable!("only the program's end has no successors"),
+            }
+
+            arms.push(statement(
+                line,
+                StatementKind::If {
+                    condition: compare(
+                        BinaryOperator::Equal,
+                        Expression::Variable(self.state.clone()),
+                        (block + 1) as f64,
+                    ),
+                    body,
+                },
+            ));
+        }
+
+        let line = self.line(entry);
+        out.push(assign(line, (entry + 1) as f64));
+        out.push(statement(
+            line,
+            StatementKind::While {
+                condition: compare(
+                    BinaryOperator::Greater,
+                    Expression::Variable(self.state.clone()),
+                    0.0,
+                ),
+                body: arms,
+            },
+        ));
+        if breaks {
+            out.push(statement(
+                line,
+                StatementKind::If {
+                    condition: compare(
+                        BinaryOperator::Less,
+                        Expression::Variable(self.state.clone()),
+                        0.0,
+                    ),
+                    body: vec![statement(line, StatementKind::Break)],
+                },
+            ));
+        }
+        Ok(falls)
+    }
+
+    /// Emits a loop starting at `header`, returning where it exits to.
+    fn structure_loop(
+        &mut self,
+        header: usize,
+        out: &mut Vec<Statement>,
+    ) -> Result<Option<usize>, ()> {
+        let exits = self.exits(header);
+        if exits.len() > 1 {
+            return Err(());
+        }
+
+        let exit = exits.first().copied();
+        let context = Some(Context { header, exit });
+        let line = self.line(header);
+        let block = &self.cfg.blocks[header];
+        let mut code = Vec::new();
+
+        // A header that only tests a condition leading out of the loop is a plain WHILE
+        let statements: Vec<&Statement> = block
+            .statements
+            .iter()
+            .copied()
+            .filter(|statement| !matches!(statement.kind, StatementKind::Label(_)))
+            .collect();
+        let test = match statements.as_slice() {
+            [statement] => match (&statement.kind, block.successors.as_slice()) {
+                (
+                    StatementKind::If { condition, .. } | StatementKind::While { condition, .. },
+                    &[then, otherwise],
+                ) if Some(otherwise) == exit => Some((condition.clone(), then)),
+                (
+                    StatementKind::If { condition, .. } | StatementKind::While { condition, .. },
+                    &[then, otherwise],
+                ) if Some(then) == exit => Some((negate(condition), otherwise)),
+                _ => None,
+            },
+            _ => None,
+        };
+
+        let condition = match test {
+            Some((condition, start)) => {
+                self.emitted.insert(header);
+                if start != header {
+                    self.region(start, Some(header), context, &mut code)?;
+                }
+                condition
+            }
+            None => {
+                self.region(header, Some(header), context, &mut code)?;
+                compare(BinaryOperator::Equal, Expression::Number(1.0), 1.0)
+            }
+        };
+
+        out.push(statement(
+            line,
+            StatementKind::While {
+                condition,
+                body: code,
+            },
+        ));
+        Ok(exit)
+    }
+
+    /// Where control can go when leaving the loop. Blocks only entered from
+    /// inside the loop, like one holding a GOTO out of a WHILE, are counted as
+    /// part of it and end in BREAK, so usually a single exit is left.
+    fn exits(&self, header: usize) -> Vec<usize> {
+        let mut body = self.loops[&header].clone();
+
+        loop {
+            let mut exits: Vec<usize> = Vec::new();
+            for &block in &body {
+                for &successor in self.successors(block) {
+                    if !body.contains(&successor) && !exits.contains(&successor) {
+                        exits.push(successor);
+                    }
+                }
+            }
+            exits.sort();
+
+            let mut inside: Vec<usize> = exits
+                .iter()
+                .copied()
+                .filter(|&exit| {
+                    exit != self.cfg.exit
+                        && !self.loops.contains_key(&exit)
+                        && self.cfg.blocks.iter().enumerate().all(|(block, other)| {
+                            !other.successors.contains(&exit) || body.contains(&block)
+                        })
+                })
+                .collect();
+
+            // Keep the exit furthest down the program as the one BREAK goes to
+            if inside.len() == exits.len() {
+                inside.pop();
+            }
+            if exits.len() <= 1 || inside.is_empty() {
+                return exits;
+            }
+            body.extend(inside);
+        }
+    }
+
+    /// Emits an IF for a branch, there is no ELSE so code that only runs when
+    /// the condition is false goes under the opposite condition.
+    fn branch(
+        &self,
+        line: usize,
+        condition: Expression,
+        (then, then_falls): (Vec<Statement>, bool),
+        (otherwise, otherwise_falls): (Vec<Statement>, bool),
+        out: &mut Vec<Statement>,
+    ) {
+        let when = |condition: Expression, body: Vec<Statement>| {
+            statement(line, StatementKind::If { condition, body })
+        };
+
+        if otherwise.is_empty() {
+            if !then.is_empty() {
+                out.push(when(condition, then));
+            }
+        } else if then.is_empty() {
+            out.push(when(negate(&condition), otherwise));
+        } else if !then_falls {
+            // The THEN side never gets past the IF, so the other side can follow it
+            out.push(when(condition, then));
+            out.extend(otherwise);
+        } else if !otherwise_falls {
+            out.push(when(negate(&condition), otherwise));
+            out.extend(then);
+        } else if !assigns(&then, &condition.variables()) {
+            let negated = negate(&condition);
+            out.push(when(condition, then));
+            out.push(when(negated, otherwise));
+        } else {
+            // The THEN side changes the condition, remember which side ran
+            let flag = |value| {
+                statement(
+                    line,
+                    StatementKind::Let(self.flag.clone(), Expression::Number(value)),
+                )
+            };
+            let mut then = then;
+            then.push(flag(0.0));
+
+            out.push(flag(1.0));
+            out.push(when(condition, then));
+            out.push(when(
+                compare(
+                    BinaryOperator::Equal,
+                    Expression::Variable(self.flag.clone()),
+                    1.0,
+                ),
+                otherwise,
+            ));
+        }
+    }
+
+    /// Where the two sides of the branch at `block` meet again: its nearest
+    /// postdominator, counting the follow, loop header and loop exit as the end.
+    /// `None` when the sides only meet at the end.
+    fn join(&self, block: usize, follow: Option<usize>, context: Option<Context>) -> Option<usize> {
+        let end = |other: usize| {
+            Some(other) == follow
+                || context
+                    .is_some_and(|context| context.header == other || context.exit == Some(other))
+        };
+
+        // Blocks between the branch and the end
+        let mut region = vec![block];
+        let mut index = 0;
+        while index < region.len() {
+            for &successor in self.successors(region[index]) {
+                if !end(successor) && !region.contains(&successor) {
+                    region.push(successor);
+                }
+            }
+            index += 1;
+        }
+
+        // Blocks that loop forever are treated as going to the end too
+        let mut finishes: HashSet<usize> = HashSet::new();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &other in &region {
+                let done = self
+                    .successors(other)
+                    .iter()
+                    .any(|&successor| end(successor) || finishes.contains(&successor));
+                if done && finishes.insert(other) {
+                    changed = true;
+                }
+            }
+        }
+
+        let successors = |other: usize| {
+            let mut successors: Vec<usize> = self
+                .successors(other)
+                .iter()
+                .map(|&successor| if end(successor) { END } else { successor })
+                .collect();
+            if !finishes.contains(&other) {
+                successors.push(END);
+            }
+            successors
+        };
+
+        let everything: HashSet<usize> = region.iter().copied().chain([END]).collect();
+        let mut postdominators: HashMap<usize, HashSet<usize>> = region
+            .iter()
+            .map(|&other| (other, everything.clone()))
+            .collect();
+        postdominators.insert(END, HashSet::from([END]));
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &other in &region {
+                let mut common: Option<HashSet<usize>> = None;
+                for successor in successors(other) {
+                    let theirs = &postdominators[&successor];
+                    common = Some(match common {
+                        Some(ours) => ours.intersection(theirs).copied().collect(),
+                        None => theirs.clone(),
+                    });
+                }
+
+                let mut common = common.unwrap_or_default();
+                common.insert(other);
+                if common != postdominators[&other] {
+                    postdominators.insert(other, common);
+                    changed = true;
+                }
+            }
+        }
+
+        // The nearest strict postdominator is the one with the most postdominators of its own
+        postdominators[&block]
+            .iter()
+            .filter(|&&other| other != block)
+            .max_by_key(|&&other| postdominators[&other].len())
+            .copied()
+            .filter(|&join| join != END)
+    }
+
+    /// Copies the block's statements, except the control flow the structure
+    /// now expresses. Returns the condition if the block ends in a branch.
+    fn simple_statements(&self, block: usize, out: &mut Vec<Statement>) -> Option<Expression> {
+        let mut condition = None;
+
+        for statement in &self.cfg.blocks[block].statements {
+            match &statement.kind {
+                StatementKind::If {
+                    condition: test, ..
+                }
+                | StatementKind::While {
+                    condition: test, ..
+                } => condition = Some(test.clone()),
+                StatementKind::Label(_) | StatementKind::Goto(_) | StatementKind::Break => (),
+                _ => out.push((*statement).clone()),
+            }
+        }
+
+        condition
+    }
+
+    /// Line to report for statements made up for a block.
+    fn line(&self, block: usize) -> usize {
+        self.cfg.blocks[block]
+            .statements
+            .first()
+            .map_or(0, |statement| statement.line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lex::Lexer;
+    use crate::parser::Parser;
+    use crate::testing::{build, build_with, cc_available, programs, run, INPUT};
+    use crate::Target;
+
+    fn modernized(source: &str) -> String {
+        let program = Parser::new(Lexer::new(format!("{}\n\0", source))).program();
+        structure(&program).to_string()
+    }
+
+    #[test]
+    fn it_turns_goto_loops_into_while() {
+        assert_eq!(
+            modernized("LABEL loop\nPRINT \"hi\"\nGOTO loop"),
+            "WHILE 1 == 1 REPEAT\n    PRINT \"hi\"\nENDWHILE\n"
+        );
+        assert_eq!(
+            modernized(
+                "LET a = 0\nLABEL top\nLET a = a + 1\nIF a < 3 THEN\nGOTO top\nENDIF\nPRINT a"
+            ),
+            "LET a = 0
+WHILE 1 == 1 REPEAT
+    LET a = a + 1
+    IF NOT a < 3 THEN
+        BREAK
+    ENDIF
+ENDWHILE
+PRINT a
+"
+        );
+    }
+
+    #[test]
+    fn it_recovers_while_and_if_else() {
+        assert_eq!(
+            modernized(
+                "INPUT n\nLABEL top\nIF n > 9 THEN\nGOTO done\nENDIF\nIF n < 5 THEN\nPRINT \"low\"\nGOTO next\nENDIF\nPRINT \"high\"\nLABEL next\nLET n = n + 1\nGOTO top\nLABEL done"
+            ),
+            "INPUT n
+WHILE NOT n > 9 REPEAT
+    IF n < 5 THEN
+        PRINT \"low\"
+    ENDIF
+    IF NOT n < 5 THEN
+        PRINT \"high\"
+    ENDIF
+    LET n = n + 1
+ENDWHILE
+"
+        );
+    }
+
+    #[test]
+    fn it_uses_a_flag_when_the_branch_changes_its_condition() {
+        assert_eq!(
+            modernized(
+                "INPUT a\nIF a < 5 THEN\nLET a = 10\nGOTO next\nENDIF\nPRINT a\nLABEL next\nPRINT a"
+            ),
+            "INPUT a
+LET flag = 1
+IF a < 5 THEN
+    LET a = 10
+    LET flag = 0
+ENDIF
+IF flag == 1 THEN
+    PRINT a
+ENDIF
+PRINT a
+"
+        );
+    }
+
+    #[test]
+    fn it_falls_back_to_a_dispatcher_for_irreducible_loops() {
+        let program = modernized(
+            "INPUT a\nIF a > 5 THEN\nPRINT \"big\"\nENDIF\nIF a < 1 THEN\nGOTO inside\nENDIF\nLABEL top\nPRINT a\nLABEL inside\nLET a = a + 1\nIF a < 3 THEN\nGOTO top\nENDIF\nPRINT \"end\"",
+        );
+        // Only the irreducible loop is dispatched, the code around it stays structured
+        assert!(program.starts_with(
+            "INPUT a\nIF a > 5 THEN\n    PRINT \"big\"\nENDIF\nLET state = 3\nWHILE state > 0 REPEAT\n"
+        ));
+        assert!(program.ends_with("ENDWHILE\nPRINT \"end\"\n"));
+        assert!(!program.contains("GOTO"));
+    }
+
+    #[test]
+    fn it_keeps_behaviour_and_removes_goto() {
+        if !cc_available() {
+            eprintln!("Skipping, cc not found");
+            return;
+        }
+
+        for program in programs() {
+            let name = program.file_stem().unwrap().to_str().unwrap();
+            let plain = build(&program, &format!("structure-c-{}", name), Target::C);
+
+            let structured = format!("structure-{}", name);
+            let options = crate::Options {
+                structure: true,
+                ..crate::Options::default()
+            };
+            let binary = build_with(&program, &structured, &options);
+            let c = std::fs::read_to_string(format!("output/{}.c", structured)).unwrap();
+            // Programs with GOSUB are left as they are
+            let gosub = std::fs::read_to_string(&program).unwrap().contains("GOSUB");
+            assert!(gosub || !c.contains("goto"), "{}", name);
+
+            assert_eq!(run(&binary, INPUT), run(&plain, INPUT), "{}", name);
+        }
+    }
+}
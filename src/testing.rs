@@ -0,0 +1,97 @@
+//! Helpers for tests that build and run generated programs.
+
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::{compile, Options, Target};
+
+/// Input fed to every test program, enough for the ones in `test_files/`.
+pub const INPUT: &str = "5 3 4 x 6 7\n";
+
+/// Input that runs out partway through `avg.teeny`.
+pub const SHORT_INPUT: &str = "2 4\n";
+
+/// Programs in `test_files/` that compile, in a stable order.
+pub fn programs() -> Vec<PathBuf> {
+    let mut programs: Vec<PathBuf> = std::fs::read_dir("test_files")
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "teeny")
+        })
+        .filter(|path| !path.ends_with("redeclare.teeny"))
+        .collect();
+
+    programs.sort();
+    programs
+}
+
+pub fn cc_available() -> bool {
+    Command::new("cc").arg("--version").output().is_ok()
+}
+
+/// Compiles the program for the target and links it with `cc`.
+pub fn build(source: &Path, name: &str, target: Target) -> PathBuf {
+    let options = Options {
+        target,
+        ..Options::default()
+    };
+    build_with(source, name, &options)
+}
+
+pub fn build_with(source: &Path, name: &str, options: &Options) -> PathBuf {
+    compile(source.to_str().unwrap(), name, options).unwrap();
+
+    let binary = PathBuf::from(format!("output/{}", name));
+    let status = Command::new("cc")
+        .arg("-o")
+        .arg(&binary)
+        .arg(format!("output/{}.{}", name, options.target.extension()))
+        .arg("-lm")
+        .status()
+        .unwrap();
+    assert!(status.success(), "cc failed for {}", name);
+
+    binary
+}
+
+/// Runs a binary with the given stdin, returning its output followed by any
+/// errors. Output is cut off after a few kilobytes so programs that loop
+/// forever still finish.
+pub fn run(binary: &Path, input: &str) -> String {
+    run_command(Command::new(binary), input)
+}
+
+/// Like `run`, for programs started through another command.
+pub fn run_command(mut command: Command, input: &str) -> String {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    // A program may exit before reading everything, so ignore broken pipes
+    let _ = child.stdin.take().unwrap().write_all(input.as_bytes());
+
+    let mut output = Vec::new();
+    child
+        .stdout
+        .take()
+        .unwrap()
+        .take(4096)
+        .read_to_end(&mut output)
+        .unwrap();
+
+    let _ = child.kill();
+    child
+        .stderr
+        .take()
+        .unwrap()
+        .read_to_end(&mut output)
+        .unwrap();
+    let _ = child.wait();
+    String::from_utf8_lossy(&output).into_owned()
+}
@@ -0,0 +1,443 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+
+use crate::ast::{FileMode, Function, Random, FILES, GOSUB_DEPTH};
+use crate::bytecode::{Chunk, Instruction};
+
+/// Runs a chunk to completion. Printing and input behave like the C backend:
+/// numbers print like `printf("%*.*f\n")` and INPUT reads like `scanf("%lf")`.
+/// Running out of input is an `UnexpectedEof` error naming the line, misusing
+/// a file or RETURN an `InvalidInput` one. See `is_runtime_error`.
+pub fn run(chunk: &Chunk, input: &mut impl BufRead, output: &mut impl Write) -> io::Result<()> {
+    let mut variables = vec![0.0; chunk.variables.len()];
+    let mut stack: Vec<f64> = Vec::new();
+    let mut scanner = Scanner::default();
+    let mut random = Random::default();
+    let mut files = Files::default();
+    let mut returns: Vec<usize> = Vec::new();
+    let mut position = 0;
+
+    loop {
+        let instruction = chunk.code[position];
+        position += 1;
+
+        match instruction {
+            Instruction::Push(value) => stack.push(value),
+            Instruction::Load(variable) => stack.push(variables[variable as usize]),
+            Instruction::Store(variable) => variables[variable as usize] = pop(&mut stack),
+            Instruction::Unary(operator) => {
+                let operand = pop(&mut stack);
+                stack.push(operator.evaluate(operand));
+            }
+            Instruction::Binary(operator) => {
+                let right = pop(&mut stack);
+                let left = pop(&mut stack);
+                stack.push(operator.evaluate(left, right));
+            }
+            Instruction::Call(function) => {
+                let start = stack
+                    .len()
+                    .checked_sub(function.arity())
+                    .expect("Bytecode stack underflow");
+                let arguments = stack.split_off(start);
+                stack.push(match function {
+                    Function::Rnd => random.next(arguments[0]),
+                    _ => function.evaluate(&arguments).unwrap(),
+                });
+            }
+            Instruction::Randomize => random.seed(pop(&mut stack)),
+            Instruction::Jump(target) => position = target as usize,
+            Instruction::JumpIfZero(target) => {
+                if pop(&mut stack) == 0.0 {
+                    position = target as usize;
+                }
+            }
+            Instruction::Gosub(target, line) => {
+                if returns.len() == GOSUB_DEPTH {
+                    return Err(misuse(line, "GOSUB nested too deeply"));
+                }
+                returns.push(position);
+                position = target as usize;
+            }
+            Instruction::Return(line) => match returns.pop() {
+                Some(resume) => position = resume,
+                None => return Err(misuse(line, "RETURN without GOSUB")),
+            },
+            Instruction::PrintNumber(format) => {
+                write!(output, "{}", format.format(pop(&mut stack)))?
+            }
+            Instruction::PrintString(string) => {
+                write!(output, "{}", chunk.strings[string as usize])?
+            }
+            Instruction::Newline => writeln!(output)?,
+            Instruction::Input(variable, line) => {
+                // Flush so prompts show up before waiting on input
+                output.flush()?;
+                match scanner.number(input)? {
+                    Some(value) => variables[variable as usize] = value,
+                    None => {
+                        return Err(runtime_error(
+                            io::ErrorKind::UnexpectedEof,
+                            line,
+                            "end of input",
+                        ))
+                    }
+                }
+            }
+            Instruction::Open(file, path, mode, line) => {
+                files.open(file as usize, &chunk.strings[path as usize], mode, line)?;
+            }
+            Instruction::Close(file, line) => files.close(file as usize, line)?,
+            Instruction::FilePrintNumber(file, format, line) => {
+                let value = pop(&mut stack);
+                write!(
+                    files.output(file as usize, line)?,
+                    "{}",
+                    format.format(value)
+                )?
+            }
+            Instruction::FilePrintString(file, string, line) => write!(
+                files.output(file as usize, line)?,
+                "{}",
+                chunk.strings[string as usize]
+            )?,
+            Instruction::FileNewline(file, line) => writeln!(files.output(file as usize, line)?)?,
+            Instruction::FileInput(file, variable, line) => {
+                let (scanner, reader) = files.input(file as usize, line)?;
+                match scanner.number(reader)? {
+                    Some(value) => variables[variable as usize] = value,
+                    None => {
+                        return Err(runtime_error(
+                            io::ErrorKind::UnexpectedEof,
+                            line,
+                            &format!("end of file #{}", file),
+                        ))
+                    }
+                }
+            }
+            Instruction::Eof(file, line) => {
+                let (scanner, reader) = files.input(file as usize, line)?;
+                let end = !scanner.skip_whitespace(reader)?;
+                stack.push(f64::from(end));
+            }
+            Instruction::Halt => return output.flush(),
+        }
+    }
+}
+
+fn pop(stack: &mut Vec<f64>) -> f64 {
+    stack.pop().expect("Bytecode stack underflow")
+}
+
+/// Whether the error stopped the program the way a compiled program stops at
+/// runtime, rather than failing to write its output.
+pub fn is_runtime_error(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::UnexpectedEof | io::ErrorKind::InvalidInput
+    )
+}
+
+fn runtime_error(kind: io::ErrorKind, line: u32, message: &str) -> io::Error {
+    io::Error::new(kind, format!("Runtime error at line {}: {}", line, message))
+}
+
+enum OpenFile {
+    Input(Scanner, BufReader<File>),
+    /// Unbuffered, so the file is complete whenever the program stops.
+    Output(File),
+}
+
+/// Files by number, checked on every use like `tb_file` in the C runtime.
+/// Numbers are in range, `Chunk::from_bytes` checks that.
+#[derive(Default)]
+struct Files([Option<OpenFile>; FILES + 1]);
+
+impl Files {
+    fn open(&mut self, file: usize, path: &str, mode: FileMode, line: u32) -> io::Result<()> {
+        if self.0[file].is_some() {
+            return Err(misuse(line, &format!("file #{} is already open", file)));
+        }
+
+        let opened = match mode {
+            FileMode::Input => File::open(path),
+            FileMode::Output => File::create(path),
+            FileMode::Append => OpenOptions::new().append(true).create(true).open(path),
+        };
+        let opened = match opened {
+            Ok(opened) => opened,
+            Err(_) => return Err(misuse(line, &format!("cannot open \"{}\"", path))),
+        };
+
+        self.0[file] = Some(match mode {
+            FileMode::Input => OpenFile::Input(Scanner::default(), BufReader::new(opened)),
+            FileMode::Output | FileMode::Append => OpenFile::Output(opened),
+        });
+        Ok(())
+    }
+
+    fn close(&mut self, file: usize, line: u32) -> io::Result<()> {
+        match self.0[file].take() {
+            Some(_) => Ok(()),
+            None => Err(not_open(file, line, "")),
+        }
+    }
+
+    fn output(&mut self, file: usize, line: u32) -> io::Result<&mut File> {
+        match &mut self.0[file] {
+            Some(OpenFile::Output(opened)) => Ok(opened),
+            Some(OpenFile::Input(..)) => Err(not_open(file, line, " for output")),
+            None => Err(not_open(file, line, "")),
+        }
+    }
+
+    fn input(
+        &mut self,
+        file: usize,
+        line: u32,
+    ) -> io::Result<(&mut Scanner, &mut BufReader<File>)> {
+        match &mut self.0[file] {
+            Some(OpenFile::Input(scanner, reader)) => Ok((scanner, reader)),
+            Some(OpenFile::Output(_)) => Err(not_open(file, line, " for input")),
+            None => Err(not_open(file, line, "")),
+        }
+    }
+}
+
+fn misuse(line: u32, message: &str) -> io::Error {
+    runtime_error(io::ErrorKind::InvalidInput, line, message)
+}
+
+fn not_open(file: usize, line: u32, purpose: &str) -> io::Error {
+    misuse(line, &format!("file #{} is not open{}", file, purpose))
+}
+
+/// Splits input into numbers the way repeated `scanf("%lf")` calls do, reading
+/// a line at a time so interactive programs don't wait for the end of input.
+#[derive(Default)]
+struct Scanner {
+    line: String,
+    position: usize,
+}
+
+impl Scanner {
+    /// Reads the next number, or None at the end of input. A word that is not
+    /// a number reads as 0 and is skipped.
+    fn number(&mut self, input: &mut impl BufRead) -> io::Result<Option<f64>> {
+        if !self.skip_whitespace(input)? {
+            return Ok(None);
+        }
+
+        let rest = &self.line[self.position..];
+        let length = number_prefix(rest);
+        if length == 0 {
+            let word = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            self.position += word;
+            return Ok(Some(0.0));
+        }
+
+        self.position += length;
+        let text = rest[..length].to_ascii_lowercase();
+        Ok(Some(text.replace("infinity", "inf").parse().unwrap()))
+    }
+
+    /// Moves to the next non-whitespace character, returning false when the
+    /// input ends first.
+    fn skip_whitespace(&mut self, input: &mut impl BufRead) -> io::Result<bool> {
+        loop {
+            let rest = &self.line[self.position..];
+            let skipped = rest.len() - rest.trim_start().len();
+            self.position += skipped;
+
+            if self.position < self.line.len() {
+                return Ok(true);
+            }
+
+            self.line.clear();
+            self.position = 0;
+            if input.read_line(&mut self.line)? == 0 {
+                return Ok(false);
+            }
+        }
+    }
+}
+
+/// Length of the longest prefix that is a decimal number, infinity or NaN.
+fn number_prefix(text: &str) -> usize {
+    let bytes = text.as_bytes();
+    let mut length = 0;
+    if matches!(bytes.first(), Some(b'+' | b'-')) {
+        length += 1;
+    }
+
+    let lower = text[length..].to_ascii_lowercase();
+    for word in ["infinity", "inf", "nan"] {
+        if lower.starts_with(word) {
+            return length + word.len();
+        }
+    }
+
+    let digits = |from: usize| {
+        bytes[from..]
+            .iter()
+            .take_while(|byte| byte.is_ascii_digit())
+            .count()
+    };
+
+    let whole = digits(length);
+    length += whole;
+    let mut fraction = 0;
+    if bytes.get(length) == Some(&b'.') {
+        fraction = digits(length + 1);
+        if whole + fraction > 0 {
+            length += 1 + fraction;
+        }
+    }
+    if whole + fraction == 0 {
+        return 0;
+    }
+
+    if matches!(bytes.get(length), Some(b'e' | b'E')) {
+        let mut exponent = length + 1;
+        if matches!(bytes.get(exponent), Some(b'+' | b'-')) {
+            exponent += 1;
+        }
+        let count = digits(exponent);
+        if count > 0 {
+            length = exponent + count;
+        }
+    }
+
+    length
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::NumberFormat;
+    use crate::bytecode::compile;
+    use crate::lex::Lexer;
+    use crate::parser::Parser;
+    use crate::testing::{build, cc_available, programs, run as run_binary, INPUT, SHORT_INPUT};
+    use crate::Target;
+
+    fn output(source: &str, input: &str) -> String {
+        let program = Parser::new(Lexer::new(format!("{}\n\0", source))).program();
+        let mut output = Vec::new();
+        run(&compile(&program), &mut input.as_bytes(), &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn it_reads_input_like_scanf() {
+        let source = "INPUT a\nPRINT a\nINPUT a\nPRINT a\nINPUT a\nPRINT a";
+        assert_eq!(output(source, "  2.5e1x -.5\n"), "25.00\n0.00\n-0.50\n");
+    }
+
+    #[test]
+    fn it_stops_at_the_end_of_input() {
+        let program =
+            Parser::new(Lexer::new(String::from("INPUT a\nPRINT a\nINPUT a\n\0"))).program();
+        let mut output = Vec::new();
+        let error = run(&compile(&program), &mut "7".as_bytes(), &mut output).unwrap_err();
+
+        assert_eq!(output, b"7.00\n");
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(error.to_string(), "Runtime error at line 3: end of input");
+    }
+
+    #[test]
+    fn it_prints_items_on_one_line() {
+        let source = "LET a = 2\nPRINT \"a is \"; a, \"!\";\nPRINT\nPRINT ,a;\nPRINT \"\"";
+        assert_eq!(output(source, ""), "a is 2.00\t!\n\t2.00\n");
+    }
+
+    #[test]
+    fn it_formats_like_printf() {
+        let plain = NumberFormat::default();
+        assert_eq!(plain.format(0.125), "0.12");
+        assert_eq!(plain.format(-0.001), "-0.00");
+        assert_eq!(plain.format(f64::NEG_INFINITY), "-inf");
+        assert_eq!(plain.format(-f64::NAN), "nan");
+
+        let picture = NumberFormat::from_picture("####.#").unwrap();
+        assert_eq!(picture.format(2.25), "   2.2");
+        assert_eq!(picture.format(-12345.0), "-12345.0");
+        assert_eq!(picture.format(f64::NAN), "   nan");
+        assert_eq!(
+            NumberFormat::from_picture("###").unwrap().format(2.5),
+            "  2"
+        );
+    }
+
+    #[test]
+    fn it_reads_back_what_it_writes() {
+        std::fs::create_dir_all("output").unwrap();
+        let source = "OPEN \"output/vm-read-back.txt\" FOR OUTPUT AS #1
+PRINT #1, 1.5; \" x \";
+CLOSE #1
+OPEN \"output/vm-read-back.txt\" FOR APPEND AS #1
+PRINT #1, USING \"#\"; 7
+CLOSE #1
+OPEN \"output/vm-read-back.txt\" FOR INPUT AS #2
+WHILE EOF(2) == 0 REPEAT
+INPUT #2, a
+PRINT a
+ENDWHILE";
+        assert_eq!(output(source, ""), "1.50\n0.00\n7.00\n");
+    }
+
+    /// Writer that fails once full, to stop programs that loop forever.
+    struct Limited(Vec<u8>);
+
+    impl Write for Limited {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            let room = 4096 - self.0.len();
+            if room == 0 {
+                return Err(io::Error::other("Output limit reached"));
+            }
+
+            let count = bytes.len().min(room);
+            self.0.extend(&bytes[..count]);
+            Ok(count)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn it_matches_the_c_backend() {
+        if !cc_available() {
+            eprintln!("Skipping, cc not found");
+            return;
+        }
+
+        for program in programs() {
+            let name = program.file_stem().unwrap().to_str().unwrap();
+            let c = build(&program, &format!("vm-c-{}", name), Target::C);
+
+            let source = std::fs::read_to_string(&program).unwrap();
+            let mut lex = Lexer::new(source + "\n\0");
+            lex.file = Some(program.to_str().unwrap().to_string());
+            let ast = Parser::new(lex).program();
+
+            for input in [INPUT, SHORT_INPUT] {
+                let mut output = Limited(Vec::new());
+                if let Err(error) = run(&compile(&ast), &mut input.as_bytes(), &mut output) {
+                    if is_runtime_error(&error) {
+                        output.0.extend(format!("{}\n", error).bytes());
+                    }
+                }
+
+                assert_eq!(
+                    String::from_utf8_lossy(&output.0),
+                    run_binary(&c, input),
+                    "{}",
+                    name
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,764 @@
+use crate::ast::{BinaryOperator, FileMode, Function, UnaryOperator};
+use crate::ir::{Instruction, Module, Operand, Terminator};
+
+/// Host functions every module imports from `env`. Printing never ends the
+/// line by itself, `print_newline` does. `print_number` receives the width and
+/// decimals to print with, `input_number` the line of the INPUT statement so
+/// the host can report running out of input. The built-ins Wasm has no
+/// instruction for are named after their libm function, then come RND and
+/// RANDOMIZE which keep the generator's state on the host. File functions take
+/// the file number and line last, so the host can report misuse of a file.
+/// `pow` is for the `^` operator, `MOD` shares `fmod` with the built-in.
+/// `gosub` keeps the block a RETURN goes back to and `resume` hands it back,
+/// both taking the line last so the host can report misuse.
+const IMPORTS: [(&str, usize); 23] = [
+    ("print_number", 0),
+    ("print_string", 1),
+    ("input_number", 2),
+    ("print_newline", 11),
+    ("fmod", 4),
+    ("sin", 3),
+    ("cos", 3),
+    ("tan", 3),
+    ("atan", 3),
+    ("exp", 3),
+    ("log", 3),
+    ("rnd", 3),
+    ("randomize", 5),
+    ("file_open", 6),
+    ("file_close", 1),
+    ("file_print_number", 7),
+    ("file_print_string", 8),
+    ("file_print_newline", 1),
+    ("file_input", 9),
+    ("file_eof", 9),
+    ("pow", 4),
+    ("gosub", 1),
+    ("resume", 10),
+];
+
+/// Parameter and result types of the imports, then of `main`.
+const TYPES: [(&[u8], &[u8]); 12] = [
+    (&[F64, I32, I32], &[]),
+    (&[I32, I32], &[]),
+    (&[I32], &[F64]),
+    (&[F64], &[F64]),
+    (&[F64, F64], &[F64]),
+    (&[F64], &[]),
+    (&[I32, I32, I32, I32, I32], &[]),
+    (&[F64, I32, I32, I32, I32], &[]),
+    (&[I32, I32, I32, I32], &[]),
+    (&[I32, I32], &[F64]),
+    (&[I32], &[I32]),
+    (&[], &[]),
+];
+
+const I32: u8 = 0x7f;
+const F64: u8 = 0x7c;
+const MAIN: u32 = IMPORTS.len() as u32;
+
+/// Index of the import with the given name.
+fn import(name: &str) -> u32 {
+    IMPORTS
+        .iter()
+        .position(|(import, _)| *import == name)
+        .unwrap() as u32
+}
+
+/// A WebAssembly instruction, kept structured so the same body can be written
+/// as text and as binary.
+#[derive(Clone, Debug, PartialEq)]
+enum Op {
+    Block,
+    Loop,
+    If,
+    Else,
+    End,
+    Br(u32),
+    BrTable(Vec<u32>, u32),
+    Return,
+    Call(u32),
+    LocalGet(u32),
+    LocalSet(u32),
+    GlobalGet(u32),
+    GlobalSet(u32),
+    I32Const(i32),
+    F64Const(f64),
+    F64(BinaryOperator),
+    F64Neg,
+    F64Abs,
+    F64Sqrt,
+    F64Floor,
+    F64Trunc,
+    F64ConvertI32U,
+    F64ConvertI32S,
+    I32Sub,
+    Comment(String),
+}
+
+/// Generates the module as WebAssembly text and binary.
+///
+/// Wasm only has structured control flow, so GOTO is handled by a dispatcher:
+/// a loop around one nested block per basic block, entered through a
+/// `br_table` on local 0 which holds the next block to run. Jumping sets the
+/// local and branches back to the loop, except when falling through to the
+/// block that follows. Variables are globals, virtual registers are locals.
+pub fn generate(module: &Module) -> (String, Vec<u8>) {
+    let (spans, data) = data(module);
+    let body = body(module, &spans);
+    (text(module, &body, &data), binary(module, &body, &data))
+}
+
+fn body(module: &Module, spans: &[(u32, u32)]) -> Vec<Op> {
+    let count = module.blocks.len() as u32;
+    let mut ops = vec![Op::Loop];
+    ops.extend((0..count).map(|_| Op::Block));
+    ops.push(Op::LocalGet(0));
+    ops.push(Op::BrTable((0..count).collect(), count - 1));
+
+    for (index, block) in module.blocks.iter().enumerate() {
+        let index = index as u32;
+        // Blocks still open around this one, the loop sits just outside them
+        let depth = count - 1 - index;
+
+        ops.push(Op::End);
+        ops.push(Op::Comment(match &block.label {
+            Some(label) => format!("bb{} ({})", index, label),
+            None => format!("bb{}", index),
+        }));
+
+        for instruction in &block.instructions {
+            instruction_ops(instruction, spans, &mut ops);
+        }
+
+        match &block.terminator {
+            Terminator::Jump(target) => jump(*target as u32, index, depth, &mut ops),
+            Terminator::Branch {
+                condition,
+                then,
+                otherwise,
+            } => {
+                operand(condition, &mut ops);
+                ops.push(Op::F64Const(0.0));
+                ops.push(Op::F64(BinaryOperator::NotEqual));
+                ops.push(Op::If);
+                jump(*then as u32, index, depth + 1, &mut ops);
+                ops.push(Op::Else);
+                jump(*otherwise as u32, index, depth + 1, &mut ops);
+                ops.push(Op::End);
+            }
+            Terminator::Gosub {
+                target,
+                resume,
+                line,
+            } => {
+                ops.push(Op::I32Const(*resume as i32));
+                ops.push(Op::I32Const(*line as i32));
+                ops.push(Op::Call(import("gosub")));
+                jump(*target as u32, index, depth, &mut ops);
+            }
+            Terminator::Resume { line } => {
+                // The host hands back the block to run next, dispatch to it
+                ops.push(Op::I32Const(*line as i32));
+                ops.push(Op::Call(import("resume")));
+                ops.push(Op::LocalSet(0));
+                ops.push(Op::Br(depth));
+            }
+            Terminator::Return => ops.push(Op::Return),
+        }
+    }
+
+    ops.push(Op::End);
+    ops
+}
+
+fn jump(target: u32, from: u32, depth: u32, ops: &mut Vec<Op>) {
+    if target != from + 1 {
+        ops.push(Op::I32Const(target as i32));
+        ops.push(Op::LocalSet(0));
+        ops.push(Op::Br(depth));
+    }
+}
+
+fn register(register: usize) -> u32 {
+    register as u32 + 1
+}
+
+fn operand(operand: &Operand, ops: &mut Vec<Op>) {
+    match operand {
+        Operand::Register(index) => ops.push(Op::LocalGet(register(*index))),
+        Operand::Constant(value) => ops.push(Op::F64Const(*value)),
+    }
+}
+
+fn instruction_ops(instruction: &Instruction, spans: &[(u32, u32)], ops: &mut Vec<Op>) {
+    match instruction {
+        Instruction::Load { dest, variable } => {
+            ops.push(Op::GlobalGet(*variable as u32));
+            ops.push(Op::LocalSet(register(*dest)));
+        }
+        Instruction::Store { variable, value } => {
+            operand(value, ops);
+            ops.push(Op::GlobalSet(*variable as u32));
+        }
+        Instruction::Unary {
+            dest,
+            operator,
+            operand: value,
+        } => {
+            operand(value, ops);
+            match operator {
+                UnaryOperator::Plus => (),
+                UnaryOperator::Minus => ops.push(Op::F64Neg),
+                UnaryOperator::Not => {
+                    ops.push(Op::F64Const(0.0));
+                    ops.push(Op::F64(BinaryOperator::Equal));
+                    ops.push(Op::F64ConvertI32U);
+                }
+            }
+            ops.push(Op::LocalSet(register(*dest)));
+        }
+        Instruction::Binary {
+            dest,
+            operator,
+            left,
+            right,
+        } => {
+            operand(left, ops);
+            operand(right, ops);
+            match operator {
+                BinaryOperator::IntegerDivide => {
+                    ops.push(Op::F64(BinaryOperator::Divide));
+                    ops.push(Op::F64Trunc);
+                }
+                BinaryOperator::Modulo => ops.push(Op::Call(import("fmod"))),
+                BinaryOperator::Power => ops.push(Op::Call(import("pow"))),
+                _ => ops.push(Op::F64(*operator)),
+            }
+            if operator.is_comparison() {
+                ops.push(Op::F64ConvertI32U);
+            }
+            ops.push(Op::LocalSet(register(*dest)));
+        }
+        Instruction::Call {
+            dest,
+            function,
+            arguments,
+        } => {
+            for argument in arguments {
+                operand(argument, ops);
+            }
+            call(*function, arguments, ops);
+            ops.push(Op::LocalSet(register(*dest)));
+        }
+        Instruction::Randomize(seed) => {
+            operand(seed, ops);
+            ops.push(Op::Call(import("randomize")));
+        }
+        Instruction::PrintNumber(value, format) => {
+            operand(value, ops);
+            ops.push(Op::I32Const(format.width as i32));
+            ops.push(Op::I32Const(format.decimals as i32));
+            ops.push(Op::Call(0));
+        }
+        Instruction::PrintString(string) => {
+            let (offset, length) = spans[*string];
+            ops.push(Op::I32Const(offset as i32));
+            ops.push(Op::I32Const(length as i32));
+            ops.push(Op::Call(1));
+        }
+        Instruction::PrintNewline => ops.push(Op::Call(3)),
+        Instruction::Input { variable, line } => {
+            ops.push(Op::I32Const(*line as i32));
+            ops.push(Op::Call(2));
+            ops.push(Op::GlobalSet(*variable as u32));
+        }
+        Instruction::Open {
+            file,
+            path,
+            mode,
+            line,
+        } => {
+            let (offset, length) = spans[*path];
+            let mode = [FileMode::Input, FileMode::Output, FileMode::Append]
+                .iter()
+                .position(|other| other == mode)
+                .unwrap();
+            ops.push(Op::I32Const(*file as i32));
+            ops.push(Op::I32Const(offset as i32));
+            ops.push(Op::I32Const(length as i32));
+            ops.push(Op::I32Const(mode as i32));
+            ops.push(Op::I32Const(*line as i32));
+            ops.push(Op::Call(import("file_open")));
+        }
+        Instruction::Close { file, line } => file_call("file_close", *file, *line, ops),
+        Instruction::FilePrintNumber {
+            file,
+            value,
+            format,
+            line,
+        } => {
+            operand(value, ops);
+            ops.push(Op::I32Const(format.width as i32));
+            ops.push(Op::I32Const(format.decimals as i32));
+            file_call("file_print_number", *file, *line, ops);
+        }
+        Instruction::FilePrintString { file, string, line } => {
+            let (offset, length) = spans[*string];
+            ops.push(Op::I32Const(offset as i32));
+            ops.push(Op::I32Const(length as i32));
+            file_call("file_print_string", *file, *line, ops);
+        }
+        Instruction::FilePrintNewline { file, line } => {
+            file_call("file_print_newline", *file, *line, ops)
+        }
+        Instruction::FileInput {
+            file,
+            variable,
+            line,
+        } => {
+            file_call("file_input", *file, *line, ops);
+            ops.push(Op::GlobalSet(*variable as u32));
+        }
+        Instruction::Eof { dest, file, line } => {
+            file_call("file_eof", *file, *line, ops);
+            ops.push(Op::LocalSet(register(*dest)));
+        }
+    }
+}
+
+/// Calls a file import with the file number and line after its other arguments.
+fn file_call(name: &str, file: usize, line: usize, ops: &mut Vec<Op>) {
+    ops.push(Op::I32Const(file as i32));
+    ops.push(Op::I32Const(line as i32));
+    ops.push(Op::Call(import(name)));
+}
+
+/// Applies a built-in to the arguments already on the stack.
+fn call(function: Function, arguments: &[Operand], ops: &mut Vec<Op>) {
+    match function {
+        Function::Abs => ops.push(Op::F64Abs),
+        Function::Sqr => ops.push(Op::F64Sqrt),
+        Function::Int => ops.push(Op::F64Floor),
+        Function::Sgn => {
+            // (x > 0) - (x < 0), both false for NaN
+            ops.push(Op::F64Const(0.0));
+            ops.push(Op::F64(BinaryOperator::Greater));
+            operand(&arguments[0], ops);
+            ops.push(Op::F64Const(0.0));
+            ops.push(Op::F64(BinaryOperator::Less));
+            ops.push(Op::I32Sub);
+            ops.push(Op::F64ConvertI32S);
+        }
+        Function::Rnd => ops.push(Op::Call(import("rnd"))),
+        _ => ops.push(Op::Call(import(function.libm().unwrap()))),
+    }
+}
+
+/// Strings are stored back to back from address 0. Returns each string's
+/// offset and the whole data segment.
+fn data(module: &Module) -> (Vec<(u32, u32)>, Vec<u8>) {
+    let mut bytes = Vec::new();
+    let mut spans = Vec::new();
+
+    for text in &module.strings {
+        spans.push((bytes.len() as u32, text.len() as u32));
+        bytes.extend(text.as_bytes());
+    }
+
+    (spans, bytes)
+}
+
+fn pages(bytes: usize) -> u32 {
+    (bytes.div_ceil(65536)).max(1) as u32
+}
+
+fn mnemonic(op: &Op) -> String {
+    match op {
+        Op::Block => String::from("block"),
+        Op::Loop => String::from("loop"),
+        Op::If => String::from("if"),
+        Op::Else => String::from("else"),
+        Op::End => String::from("end"),
+        Op::Br(depth) => format!("br {}", depth),
+        Op::BrTable(targets, default) => {
+            let targets: Vec<String> = targets.iter().map(|target| target.to_string()).collect();
+            format!("br_table {} {}", targets.join(" "), default)
+        }
+        Op::Return => String::from("return"),
+        Op::Call(function) => format!("call {}", function),
+        Op::LocalGet(local) => format!("local.get {}", local),
+        Op::LocalSet(local) => format!("local.set {}", local),
+        Op::GlobalGet(global) => format!("global.get {}", global),
+        Op::GlobalSet(global) => format!("global.set {}", global),
+        Op::I32Const(value) => format!("i32.const {}", value),
+        Op::F64Const(value) => format!("f64.const {}", float_text(*value)),
+        Op::F64(operator) => format!("f64.{}", binary_name(*operator)),
+        Op::F64Neg => String::from("f64.neg"),
+        Op::F64Abs => String::from("f64.abs"),
+        Op::F64Sqrt => String::from("f64.sqrt"),
+        Op::F64Floor => String::from("f64.floor"),
+        Op::F64Trunc => String::from("f64.trunc"),
+        Op::F64ConvertI32U => String::from("f64.convert_i32_u"),
+        Op::F64ConvertI32S => String::from("f64.convert_i32_s"),
+        Op::I32Sub => String::from("i32.sub"),
+        Op::Comment(text) => format!(";; {}", text),
+    }
+}
+
+fn binary_name(operator: BinaryOperator) -> &'static str {
+    match operator {
+        BinaryOperator::Add => "add",
+        BinaryOperator::Subtract => "sub",
+        BinaryOperator::Multiply => "mul",
+        BinaryOperator::Divide => "div",
+        BinaryOperator::Equal => "eq",
+        BinaryOperator::NotEqual => "ne",
+        BinaryOperator::Less => "lt",
+        BinaryOperator::LessEqual => "le",
+        BinaryOperator::Greater => "gt",
+        BinaryOperator::GreaterEqual => "ge",
+        _ => unreachable!("{:?} has no Wasm instruction", operator),
+    }
+}
+
+fn float_text(value: f64) -> String {
+    if value.is_nan() {
+        String::from("nan")
+    } else if value.is_infinite() {
+        String::from(if value < 0.0 { "-inf" } else { "inf" })
+    } else {
+        format!("{:?}", value)
+    }
+}
+
+fn text(module: &Module, body: &[Op], data: &[u8]) -> String {
+    let mut wat = String::from("(module\n");
+
+    for (index, (params, results)) in TYPES.iter().enumerate() {
+        wat.push_str(&format!(
+            "  (type (;{};) (func{}{}))\n",
+            index,
+            value_types("param", params),
+            value_types("result", results)
+        ));
+    }
+    for (name, signature) in IMPORTS {
+        wat.push_str(&format!(
+            "  (import \"env\" \"{}\" (func (type {})))\n",
+            name, signature
+        ));
+    }
+    for (index, name) in module.variables.iter().enumerate() {
+        wat.push_str(&format!(
+            "  (global (;{} {};) (mut f64) (f64.const 0))\n",
+            index, name
+        ));
+    }
+
+    wat.push_str(&format!(
+        "  (memory (export \"memory\") {})\n",
+        pages(data.len())
+    ));
+    wat.push_str(&format!("  (data (i32.const 0) \"{}\")\n", escape(data)));
+
+    wat.push_str(&format!(
+        "  (func (export \"main\") (type {})\n",
+        TYPES.len() - 1
+    ));
+    wat.push_str("    (local i32)");
+    if module.registers > 0 {
+        wat.push_str(&format!(" (local{})", " f64".repeat(module.registers)));
+    }
+    wat.push('\n');
+
+    let mut indent = 2;
+    for op in body {
+        if matches!(op, Op::End | Op::Else) {
+            indent -= 1;
+        }
+        wat.push_str(&"  ".repeat(indent));
+        wat.push_str(&mnemonic(op));
+        wat.push('\n');
+        if matches!(op, Op::Block | Op::Loop | Op::If | Op::Else) {
+            indent += 1;
+        }
+    }
+
+    wat.push_str("  )\n)\n");
+    wat
+}
+
+fn value_types(kind: &str, types: &[u8]) -> String {
+    if types.is_empty() {
+        return String::new();
+    }
+
+    let names: Vec<&str> = types
+        .iter()
+        .map(|value| if *value == F64 { "f64" } else { "i32" })
+        .collect();
+    format!(" ({} {})", kind, names.join(" "))
+}
+
+fn escape(bytes: &[u8]) -> String {
+    let mut escaped = String::new();
+    for byte in bytes {
+        match byte {
+            b'"' | b'\\' => escaped.push_str(&format!("\\{:02x}", byte)),
+            0x20..=0x7e => escaped.push(*byte as char),
+            _ => escaped.push_str(&format!("\\{:02x}", byte)),
+        }
+    }
+    escaped
+}
+
+fn binary(module: &Module, body: &[Op], data: &[u8]) -> Vec<u8> {
+    let mut wasm = b"\0asm".to_vec();
+    wasm.extend([1, 0, 0, 0]);
+
+    let mut types = Vec::new();
+    unsigned(TYPES.len() as u64, &mut types);
+    for (params, results) in TYPES {
+        types.push(0x60);
+        unsigned(params.len() as u64, &mut types);
+        types.extend(params);
+        unsigned(results.len() as u64, &mut types);
+        types.extend(results);
+    }
+    section(1, &types, &mut wasm);
+
+    let mut imports = Vec::new();
+    unsigned(IMPORTS.len() as u64, &mut imports);
+    for (name, signature) in IMPORTS {
+        name_bytes("env", &mut imports);
+        name_bytes(name, &mut imports);
+        imports.push(0x00);
+        unsigned(signature as u64, &mut imports);
+    }
+    section(2, &imports, &mut wasm);
+
+    let mut functions = Vec::new();
+    unsigned(1, &mut functions);
+    unsigned(TYPES.len() as u64 - 1, &mut functions);
+    section(3, &functions, &mut wasm);
+
+    let mut memory = Vec::new();
+    unsigned(1, &mut memory);
+    memory.push(0x00);
+    unsigned(pages(data.len()) as u64, &mut memory);
+    section(5, &memory, &mut wasm);
+
+    let mut globals = Vec::new();
+    unsigned(module.variables.len() as u64, &mut globals);
+    for _ in &module.variables {
+        globals.extend([F64, 0x01, 0x44]);
+        globals.extend(0.0f64.to_le_bytes());
+        globals.push(0x0b);
+    }
+    section(6, &globals, &mut wasm);
+
+    let mut exports = Vec::new();
+    unsigned(2, &mut exports);
+    name_bytes("main", &mut exports);
+    exports.push(0x00);
+    unsigned(MAIN as u64, &mut exports);
+    name_bytes("memory", &mut exports);
+    exports.push(0x02);
+    unsigned(0, &mut exports);
+    section(7, &exports, &mut wasm);
+
+    let mut function = Vec::new();
+    if module.registers > 0 {
+        unsigned(2, &mut function);
+        unsigned(1, &mut function);
+        function.push(I32);
+        unsigned(module.registers as u64, &mut function);
+        function.push(F64);
+    } else {
+        unsigned(1, &mut function);
+        unsigned(1, &mut function);
+        function.push(I32);
+    }
+    for op in body {
+        encode(op, &mut function);
+    }
+    function.push(0x0b);
+
+    let mut code = Vec::new();
+    unsigned(1, &mut code);
+    unsigned(function.len() as u64, &mut code);
+    code.extend(function);
+    section(10, &code, &mut wasm);
+
+    let mut segments = Vec::new();
+    unsigned(1, &mut segments);
+    segments.push(0x00);
+    segments.extend([0x41, 0x00, 0x0b]);
+    unsigned(data.len() as u64, &mut segments);
+    segments.extend(data);
+    section(11, &segments, &mut wasm);
+
+    wasm
+}
+
+fn encode(op: &Op, bytes: &mut Vec<u8>) {
+    match op {
+        Op::Block => bytes.extend([0x02, 0x40]),
+        Op::Loop => bytes.extend([0x03, 0x40]),
+        Op::If => bytes.extend([0x04, 0x40]),
+        Op::Else => bytes.push(0x05),
+        Op::End => bytes.push(0x0b),
+        Op::Br(depth) => {
+            bytes.push(0x0c);
+            unsigned(*depth as u64, bytes);
+        }
+        Op::BrTable(targets, default) => {
+            bytes.push(0x0e);
+            unsigned(targets.len() as u64, bytes);
+            for target in targets {
+                unsigned(*target as u64, bytes);
+            }
+            unsigned(*default as u64, bytes);
+        }
+        Op::Return => bytes.push(0x0f),
+        Op::Call(function) => {
+            bytes.push(0x10);
+            unsigned(*function as u64, bytes);
+        }
+        Op::LocalGet(local) => {
+            bytes.push(0x20);
+            unsigned(*local as u64, bytes);
+        }
+        Op::LocalSet(local) => {
+            bytes.push(0x21);
+            unsigned(*local as u64, bytes);
+        }
+        Op::GlobalGet(global) => {
+            bytes.push(0x23);
+            unsigned(*global as u64, bytes);
+        }
+        Op::GlobalSet(global) => {
+            bytes.push(0x24);
+            unsigned(*global as u64, bytes);
+        }
+        Op::I32Const(value) => {
+            bytes.push(0x41);
+            signed(*value as i64, bytes);
+        }
+        Op::F64Const(value) => {
+            bytes.push(0x44);
+            bytes.extend(value.to_le_bytes());
+        }
+        Op::F64(operator) => bytes.push(match operator {
+            BinaryOperator::Equal => 0x61,
+            BinaryOperator::NotEqual => 0x62,
+            BinaryOperator::Less => 0x63,
+            BinaryOperator::Greater => 0x64,
+            BinaryOperator::LessEqual => 0x65,
+            BinaryOperator::GreaterEqual => 0x66,
+            BinaryOperator::Add => 0xa0,
+            BinaryOperator::Subtract => 0xa1,
+            BinaryOperator::Multiply => 0xa2,
+            BinaryOperator::Divide => 0xa3,
+            _ => unreachable!("{:?} has no Wasm instruction", operator),
+        }),
+        Op::F64Neg => bytes.push(0x9a),
+        Op::F64Abs => bytes.push(0x99),
+        Op::F64Sqrt => bytes.push(0x9f),
+        Op::F64Floor => bytes.push(0x9c),
+        Op::F64Trunc => bytes.push(0x9d),
+        Op::F64ConvertI32U => bytes.push(0xb8),
+        Op::F64ConvertI32S => bytes.push(0xb7),
+        Op::I32Sub => bytes.push(0x6b),
+        Op::Comment(_) => (),
+    }
+}
+
+fn section(id: u8, contents: &[u8], wasm: &mut Vec<u8>) {
+    wasm.push(id);
+    unsigned(contents.len() as u64, wasm);
+    wasm.extend(contents);
+}
+
+fn name_bytes(name: &str, bytes: &mut Vec<u8>) {
+    unsigned(name.len() as u64, bytes);
+    bytes.extend(name.as_bytes());
+}
+
+/// Unsigned LEB128.
+fn unsigned(mut value: u64, bytes: &mut Vec<u8>) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte);
+            return;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+/// Signed LEB128.
+fn signed(mut value: i64, bytes: &mut Vec<u8>) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        let done = (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0);
+        if done {
+            bytes.push(byte);
+            return;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{build, cc_available, programs, run, run_command, INPUT, SHORT_INPUT};
+    use crate::Target;
+    use std::process::Command;
+
+    #[test]
+    fn it_encodes_leb128() {
+        let mut bytes = Vec::new();
+        unsigned(624485, &mut bytes);
+        signed(-123456, &mut bytes);
+        signed(63, &mut bytes);
+        signed(64, &mut bytes);
+        assert_eq!(
+            bytes,
+            [0xe5, 0x8e, 0x26, 0xc0, 0xbb, 0x78, 0x3f, 0xc0, 0x00]
+        );
+    }
+
+    #[test]
+    fn it_matches_the_c_backend() {
+        let node = Command::new("node").arg("--version").output().is_ok();
+        if !node || !cc_available() {
+            eprintln!("Skipping, node or cc not found");
+            return;
+        }
+
+        for program in programs() {
+            let name = program.file_stem().unwrap().to_str().unwrap();
+            let c = build(&program, &format!("wasm-c-{}", name), Target::C);
+
+            let wasm = format!("wasm-{}", name);
+            let options = crate::Options {
+                target: Target::Wasm,
+                ..crate::Options::default()
+            };
+            crate::compile(program.to_str().unwrap(), &wasm, &options).unwrap();
+
+            for input in [INPUT, SHORT_INPUT] {
+                let mut host = Command::new("node");
+                host.arg("runtime/wasm-host.js")
+                    .arg(format!("output/{}.wasm", wasm));
+
+                assert_eq!(run_command(host, input), run(&c, input), "{}", name);
+            }
+        }
+    }
+}